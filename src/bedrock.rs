@@ -0,0 +1,1230 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{prelude::*, Cursor, Error, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use csv::Reader;
+
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use serde::Deserialize;
+
+use super::biometypes::BiomeRegistry;
+use super::blocktypes;
+use super::blocktypes::BlockType;
+use super::dimension;
+use super::light;
+use super::nbt;
+use super::region;
+use super::region::{ChunkData, ChunkError, RegionChunks, RegionData};
+use super::sizes::*;
+use super::types::*;
+use super::world::World;
+
+// Bedrock Edition worlds keep their chunks in a LevelDB database in the db subdirectory.
+// This reads the database files directly: sorted table files, plus log files holding the most
+// recent writes. Every record has a sequence number, so the newest version of each key wins.
+
+const TABLE_FOOTER_SIZE: usize = 48;
+const TABLE_MAGIC: u64 = 0xdb47_7524_8b80_fb57;
+const BLOCK_TRAILER_SIZE: usize = 5;
+
+// Bedrock's fork of LevelDB compresses table blocks with zlib, or raw deflate in newer versions.
+const NO_COMPRESSION: u8 = 0;
+const ZLIB_COMPRESSION: u8 = 2;
+const ZLIB_RAW_COMPRESSION: u8 = 4;
+
+const LOG_BLOCK_SIZE: usize = 32768;
+const LOG_HEADER_SIZE: usize = 7;
+const LOG_FULL: u8 = 1;
+const LOG_FIRST: u8 = 2;
+const LOG_MIDDLE: u8 = 3;
+const LOG_LAST: u8 = 4;
+
+const TYPE_VALUE: u8 = 1;
+
+// Chunk record tags, found after the chunk coords (and dimension) in each key.
+const TAG_DATA_3D: u8 = 43;
+const TAG_DATA_2D: u8 = 45;
+const TAG_SUBCHUNK_PREFIX: u8 = 47;
+const TAG_FINALIZED_STATE: u8 = 54;
+
+pub const OVERWORLD: i32 = 0;
+pub const NETHER: i32 = 1;
+pub const END: i32 = 2;
+
+// The finalized state of a chunk that has been fully generated and populated.
+const FINALIZED_DONE: u32 = 2;
+
+// Biome storages with this header repeat the storage of the subchunk below.
+const BIOME_COPY_PREVIOUS: u8 = 0xff;
+const HEIGHTMAP_SIZE: usize = BLOCKS_IN_CHUNK_2D * 2;
+
+#[derive(Deserialize)]
+struct BlockRow {
+    name: String,
+    state: String,
+    javaname: String,
+    javastate: String,
+}
+
+#[derive(Deserialize)]
+struct BiomeRow {
+    id: u8,
+    name: String,
+}
+
+// A table block entry's full key and value.
+type BlockEntry<'a> = (Vec<u8>, &'a [u8]);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+struct ChunkKey {
+    c: Pair<isize>,
+    tag: u8,
+    y: i8,
+}
+
+enum Value {
+    Table(usize, BlockHandle),
+    Log(Vec<u8>),
+    Deleted,
+}
+
+pub struct Db {
+    dimension: i32,
+    tables: Vec<PathBuf>,
+    records: HashMap<ChunkKey, (u64, Value)>,
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or_else(|| invalid_data("Truncated varint"))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("Invalid varint"))
+}
+
+fn read_slice<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8], Error> {
+    let len = read_varint(data, pos)? as usize;
+    let slice = data.get(*pos..(*pos + len)).ok_or_else(|| invalid_data("Truncated slice"))?;
+    *pos += len;
+    Ok(slice)
+}
+
+fn read_block_handle(data: &[u8], pos: &mut usize) -> Result<BlockHandle, Error> {
+    Ok(BlockHandle {
+        offset: read_varint(data, pos)?,
+        size: read_varint(data, pos)?,
+    })
+}
+
+fn read_table_block(file: &mut File, handle: &BlockHandle) -> Result<Vec<u8>, Error> {
+    let size = handle.size as usize;
+    file.seek(SeekFrom::Start(handle.offset))?;
+    let mut data = vec![0u8; size + BLOCK_TRAILER_SIZE];
+    file.read_exact(&mut data)?;
+
+    // The trailer holds the compression type and a checksum, which we ignore.
+    let compression = data[size];
+    data.truncate(size);
+
+    let mut block = Vec::new();
+    match compression {
+        NO_COMPRESSION => return Ok(data),
+        ZLIB_COMPRESSION => ZlibDecoder::new(&data[..]).read_to_end(&mut block)?,
+        ZLIB_RAW_COMPRESSION => DeflateDecoder::new(&data[..]).read_to_end(&mut block)?,
+        _ => return Err(Error::new(ErrorKind::InvalidData,
+            format!("Unsupported table block compression type {}", compression))),
+    };
+    Ok(block)
+}
+
+// Read the key-value entries in a table block. Each key only stores the part that differs from
+// the previous key, and the block ends with a list of restart points that we don't need.
+fn read_block_entries(block: &[u8]) -> Result<Vec<BlockEntry<'_>>, Error> {
+    if block.len() < 4 {
+        return Err(invalid_data("Truncated table block"));
+    }
+    let restarts = LittleEndian::read_u32(&block[(block.len() - 4)..]) as usize;
+    let end = block.len().checked_sub(4 + restarts * 4)
+        .ok_or_else(|| invalid_data("Invalid table block restarts"))?;
+
+    let mut entries = Vec::new();
+    let mut key = Vec::new();
+    let mut pos = 0;
+    while pos < end {
+        let shared = read_varint(block, &mut pos)? as usize;
+        let unshared = read_varint(block, &mut pos)? as usize;
+        let value_len = read_varint(block, &mut pos)? as usize;
+        if shared > key.len() || pos + unshared + value_len > end {
+            return Err(invalid_data("Invalid table block entry"));
+        }
+
+        key.truncate(shared);
+        key.extend_from_slice(&block[pos..(pos + unshared)]);
+        pos += unshared;
+        entries.push((key.clone(), &block[pos..(pos + value_len)]));
+        pos += value_len;
+    }
+    Ok(entries)
+}
+
+// Read the handles of all data blocks in a table from its index block.
+fn read_table_index(file: &mut File) -> Result<Vec<BlockHandle>, Error> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < TABLE_FOOTER_SIZE as u64 {
+        return Err(invalid_data("Truncated table file"));
+    }
+    file.seek(SeekFrom::Start(len - TABLE_FOOTER_SIZE as u64))?;
+    let mut footer = [0u8; TABLE_FOOTER_SIZE];
+    file.read_exact(&mut footer)?;
+    if LittleEndian::read_u64(&footer[(TABLE_FOOTER_SIZE - 8)..]) != TABLE_MAGIC {
+        return Err(invalid_data("Invalid table file"));
+    }
+
+    let mut pos = 0;
+    read_block_handle(&footer, &mut pos)?; // The metaindex block, which holds filters.
+    let index_handle = read_block_handle(&footer, &mut pos)?;
+    let index = read_table_block(file, &index_handle)?;
+
+    read_block_entries(&index)?.iter()
+        .map(|(_, value)| read_block_handle(value, &mut 0))
+        .collect()
+}
+
+// Split a table key into the user key, sequence number and value type.
+fn split_internal_key(key: &[u8]) -> Option<(&[u8], u64, u8)> {
+    if key.len() < 8 {
+        return None;
+    }
+    let (user_key, trailer) = key.split_at(key.len() - 8);
+    let trailer = LittleEndian::read_u64(trailer);
+    Some((user_key, trailer >> 8, (trailer & 0xff) as u8))
+}
+
+// Read the write batches in a log file. Batches are split into records that don't cross
+// 32 KiB block boundaries.
+fn read_log_batches(path: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut pos = 0;
+    while pos + LOG_HEADER_SIZE <= data.len() {
+        // Block trailers too small for a header are padding.
+        let block_left = LOG_BLOCK_SIZE - pos % LOG_BLOCK_SIZE;
+        if block_left < LOG_HEADER_SIZE {
+            pos += block_left;
+            continue;
+        }
+
+        let len = LittleEndian::read_u16(&data[(pos + 4)..]) as usize;
+        let record_type = data[pos + 6];
+        let start = pos + LOG_HEADER_SIZE;
+        // A record cut off at the end of the log was never committed.
+        if start + len > data.len() {
+            break;
+        }
+        let record = &data[start..(start + len)];
+        pos = start + len;
+
+        match record_type {
+            LOG_FULL => batches.push(record.to_vec()),
+            LOG_FIRST => batch = record.to_vec(),
+            LOG_MIDDLE => batch.extend_from_slice(record),
+            LOG_LAST => {
+                batch.extend_from_slice(record);
+                batches.push(std::mem::take(&mut batch));
+            },
+            _ => {},
+        }
+    }
+    Ok(batches)
+}
+
+// Get the number that a dimension's chunk records are keyed by.
+pub fn get_dimension_id(name: &str) -> Option<i32> {
+    match name {
+        dimension::OVERWORLD => Some(OVERWORLD),
+        dimension::NETHER => Some(NETHER),
+        dimension::END => Some(END),
+        _ => None,
+    }
+}
+
+// Get the section Y of a dimension's lowest subchunk, which is only below 0 in the overworld.
+fn get_min_section_y(dimension: i32) -> isize {
+    if dimension == OVERWORLD { MIN_SECTION_Y } else { 0 }
+}
+
+// Parse a chunk record key: X and Z chunk coords, the dimension (omitted for the overworld),
+// the record tag, and for subchunks, the section Y.
+fn parse_chunk_key(key: &[u8], dimension: i32) -> Option<ChunkKey> {
+    let rest = match key.len() {
+        9 | 10 if dimension == OVERWORLD => &key[8..],
+        13 | 14 if dimension != OVERWORLD && LittleEndian::read_i32(&key[8..12]) == dimension =>
+            &key[12..],
+        _ => return None,
+    };
+    let y = match (rest[0], rest.len()) {
+        (TAG_SUBCHUNK_PREFIX, 2) => rest[1] as i8,
+        (TAG_DATA_3D, 1) | (TAG_DATA_2D, 1) | (TAG_FINALIZED_STATE, 1) => 0,
+        _ => return None,
+    };
+    Some(ChunkKey {
+        c: Pair {
+            x: LittleEndian::read_i32(&key[0..4]) as isize,
+            z: LittleEndian::read_i32(&key[4..8]) as isize,
+        },
+        tag: rest[0],
+        y,
+    })
+}
+
+fn encode_chunk_key(key: &ChunkKey, dimension: i32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(14);
+    bytes.extend_from_slice(&(key.c.x as i32).to_le_bytes());
+    bytes.extend_from_slice(&(key.c.z as i32).to_le_bytes());
+    if dimension != OVERWORLD {
+        bytes.extend_from_slice(&dimension.to_le_bytes());
+    }
+    bytes.push(key.tag);
+    if key.tag == TAG_SUBCHUNK_PREFIX {
+        bytes.push(key.y as u8);
+    }
+    bytes
+}
+
+impl Db {
+    // Index the chunk records for one dimension, keeping the location of the newest value of
+    // each key so that it can be read when its region is drawn.
+    pub fn open(path: &Path, dimension: i32) -> Result<Db, Error> {
+        let mut table_paths = Vec::new();
+        let mut log_paths = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            match entry_path.extension().and_then(|ext| ext.to_str()) {
+                Some("ldb") | Some("sst") => table_paths.push(entry_path),
+                Some("log") => log_paths.push(entry_path),
+                _ => {},
+            }
+        }
+        if table_paths.is_empty() && log_paths.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "No LevelDB files found in db directory."));
+        }
+        table_paths.sort();
+        log_paths.sort();
+
+        let mut db = Db {
+            dimension,
+            tables: Vec::new(),
+            records: HashMap::new(),
+        };
+
+        let bar = ProgressBar::with_draw_target((table_paths.len() + log_paths.len()) as u64,
+            ProgressDrawTarget::stdout_nohz())
+            .with_style(ProgressStyle::default_bar()
+                .template("{wide_bar}\n{msg} ({pos}/{len})")
+                .progress_chars("▪■ "));
+        bar.set_message("Reading chunk list from database");
+
+        for table_path in table_paths {
+            bar.inc(1);
+            let mut file = File::open(&table_path)?;
+            for handle in read_table_index(&mut file)? {
+                let block = read_table_block(&mut file, &handle)?;
+                for (key, _) in read_block_entries(&block)? {
+                    if let Some((user_key, seq, value_type)) = split_internal_key(&key) {
+                        if let Some(ckey) = parse_chunk_key(user_key, dimension) {
+                            let value = if value_type == TYPE_VALUE {
+                                Value::Table(db.tables.len(), handle)
+                            } else {
+                                Value::Deleted
+                            };
+                            db.insert(ckey, seq, value);
+                        }
+                    }
+                }
+            }
+            db.tables.push(table_path);
+        }
+
+        for log_path in log_paths {
+            bar.inc(1);
+            for batch in read_log_batches(&log_path)? {
+                if batch.len() < 12 {
+                    return Err(invalid_data("Truncated log batch"));
+                }
+                // Each record in a batch takes the next sequence number.
+                let first_seq = LittleEndian::read_u64(&batch);
+                let count = LittleEndian::read_u32(&batch[8..]) as u64;
+                let mut pos = 12;
+                for seq in first_seq..(first_seq + count) {
+                    let value_type = *batch.get(pos)
+                        .ok_or_else(|| invalid_data("Truncated log batch"))?;
+                    pos += 1;
+                    let key = read_slice(&batch, &mut pos)?;
+                    let value = if value_type == TYPE_VALUE {
+                        Value::Log(read_slice(&batch, &mut pos)?.to_vec())
+                    } else {
+                        Value::Deleted
+                    };
+                    if let Some(ckey) = parse_chunk_key(key, dimension) {
+                        db.insert(ckey, seq, value);
+                    }
+                }
+            }
+        }
+
+        bar.finish_and_clear();
+
+        Ok(db)
+    }
+
+    fn insert(&mut self, key: ChunkKey, seq: u64, value: Value) {
+        match self.records.get(&key) {
+            Some((old_seq, _)) if *old_seq > seq => {},
+            _ => { self.records.insert(key, (seq, value)); },
+        }
+    }
+
+    fn contains(&self, key: &ChunkKey) -> bool {
+        match self.records.get(key) {
+            Some((_, Value::Deleted)) | None => false,
+            Some(_) => true,
+        }
+    }
+
+    // List the coords of all chunks that have at least one subchunk.
+    pub fn get_chunk_coords(&self) -> Vec<Pair<isize>> {
+        let mut coords: Vec<Pair<isize>> = self.records.keys()
+            .filter(|key| key.tag == TAG_SUBCHUNK_PREFIX && self.contains(key))
+            .map(|key| key.c)
+            .collect();
+        coords.sort_by_key(|c| (c.x, c.z));
+        coords.dedup();
+        coords
+    }
+}
+
+// Reads values from the database, keeping the most recently read table block,
+// since the records for a chunk are usually stored together.
+struct DbReader<'a> {
+    db: &'a Db,
+    file: Option<(usize, File)>,
+    block: Option<(usize, BlockHandle, Vec<u8>)>,
+}
+
+impl<'a> DbReader<'a> {
+    fn new(db: &'a Db) -> DbReader<'a> {
+        DbReader { db, file: None, block: None }
+    }
+
+    fn get(&mut self, key: &ChunkKey) -> Result<Option<Vec<u8>>, Error> {
+        let (seq, table, handle) = match self.db.records.get(key) {
+            Some((_, Value::Log(value))) => return Ok(Some(value.clone())),
+            Some((seq, Value::Table(table, handle))) => (*seq, *table, *handle),
+            Some((_, Value::Deleted)) | None => return Ok(None),
+        };
+
+        let cached = match &self.block {
+            Some((btable, bhandle, _)) => *btable == table && *bhandle == handle,
+            None => false,
+        };
+        if !cached {
+            let reopen = match &self.file {
+                Some((ftable, _)) => *ftable != table,
+                None => true,
+            };
+            if reopen {
+                self.file = Some((table, File::open(&self.db.tables[table])?));
+            }
+            let (_, file) = self.file.as_mut().unwrap();
+            self.block = Some((table, handle, read_table_block(file, &handle)?));
+        }
+
+        let user_key = encode_chunk_key(key, self.db.dimension);
+        let (_, _, block) = self.block.as_ref().unwrap();
+        for (ikey, value) in read_block_entries(block)? {
+            if let Some((ukey, kseq, _)) = split_internal_key(&ikey) {
+                if ukey == &user_key[..] && kseq == seq {
+                    return Ok(Some(value.to_vec()));
+                }
+            }
+        }
+        Err(invalid_data("Record missing from table block"))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PaletteBlock {
+    block: u16,
+    waterlogged: u16,
+    air: bool,
+    water: bool,
+}
+
+// Converts Bedrock chunk records into the same chunk data as Java Edition regions. One is made
+// for each world, so that its tables and palette cache are shared by all regions.
+pub struct ChunkDecoder<'a> {
+    reader: DbReader<'a>,
+    blocktypes: &'a [BlockType],
+    legacy_blocktypes: &'a [u16],
+    block_rows: Vec<BlockRow>,
+    palette_blocks: HashMap<String, PaletteBlock>,
+    biomes: Vec<u8>,
+}
+
+// Read a palettized storage of one index per block, with Y changing fastest, then Z, then X.
+// Returns None for a storage that repeats the previous one.
+fn read_storage_indices(reader: &mut Cursor<&[u8]>) -> Result<Option<(Vec<usize>, usize)>, Error> {
+    let header = reader.read_u8()?;
+    if header == BIOME_COPY_PREVIOUS {
+        return Ok(None);
+    }
+
+    // A storage with 0 bits per index has a single palette entry, and no palette length.
+    let bits = (header >> 1) as usize;
+    if bits == 0 {
+        return Ok(Some((vec![0; BLOCKS_IN_SECTION_3D], 1)));
+    }
+    if bits > 16 {
+        return Err(invalid_data("Invalid palette storage bits"));
+    }
+
+    // Indices don't cross 32-bit word boundaries.
+    let per_word = 32 / bits;
+    let words = BLOCKS_IN_SECTION_3D.div_ceil(per_word);
+    let mask = (1u32 << bits) - 1;
+    let mut indices = Vec::with_capacity(words * per_word);
+    for _ in 0..words {
+        let word = reader.read_u32::<LittleEndian>()?;
+        for i in 0..per_word {
+            indices.push(((word >> (i * bits)) & mask) as usize);
+        }
+    }
+    indices.truncate(BLOCKS_IN_SECTION_3D);
+
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    if indices.iter().any(|index| *index >= len) {
+        return Err(invalid_data("Palette index out of range"));
+    }
+    Ok(Some((indices, len)))
+}
+
+// Convert a block index in a Bedrock subchunk to an offset in the section.
+fn storage_index_to_section_offset(i: usize) -> usize {
+    let by = i % BLOCKS_IN_CHUNK;
+    let bz = i / BLOCKS_IN_CHUNK % BLOCKS_IN_CHUNK;
+    let bx = i / BLOCKS_IN_CHUNK_2D;
+    by * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx
+}
+
+fn get_state_value(tag: &nbt::Tag) -> String {
+    match tag {
+        nbt::Tag::Byte(value) => value.to_string(),
+        nbt::Tag::Short(value) => value.to_string(),
+        nbt::Tag::Int(value) => value.to_string(),
+        nbt::Tag::String(value) => value.clone(),
+        _ => "".to_string(),
+    }
+}
+
+impl<'a> ChunkDecoder<'a> {
+    pub fn new(db: &'a Db, blocktypes: &'a [BlockType], biome_registry: &BiomeRegistry,
+        legacy_blocktypes: &'a [u16])
+    -> ChunkDecoder<'a> {
+        let csvpath = Path::new("./resources/bedrockblocks.csv");
+        let mut reader = Reader::from_path(csvpath).unwrap();
+        let block_rows = reader.deserialize().map(|res| res.unwrap()).collect();
+
+        // Most Bedrock biome IDs match the old Java Edition IDs, which are used as biome indices.
+        // Those that don't are listed by name.
+        let mut biomes: Vec<u8> = (0..=u8::MAX).collect();
+        let csvpath = Path::new("./resources/bedrockbiomes.csv");
+        let mut reader = Reader::from_path(csvpath).unwrap();
+        for result in reader.deserialize() {
+            let row: BiomeRow = result.unwrap();
+            biomes[row.id as usize] = biome_registry.get(&format!("minecraft:{}", row.name))
+                .copied().unwrap_or(0);
+        }
+
+        ChunkDecoder {
+            reader: DbReader::new(db),
+            blocktypes,
+            legacy_blocktypes,
+            block_rows,
+            palette_blocks: HashMap::new(),
+            biomes,
+        }
+    }
+
+    // Find the Java Edition block type for a Bedrock block name and states, using the first
+    // matching row of the Bedrock block table, or else the same name.
+    fn find_block_type(&self, name: &str, states: &HashMap<String, String>, waterlogged: bool)
+    -> Option<u16> {
+        let short_name = name.trim_start_matches("minecraft:");
+        let (java_name, java_state) = self.block_rows.iter()
+            .find(|row| row.name == short_name && row.state.split('&')
+                .filter(|pair| !pair.is_empty())
+                .all(|pair| {
+                    let mut kv = pair.split('=');
+                    states.get(kv.next().unwrap()).map(|v| v.as_str()) == kv.next()
+                }))
+            .map_or((short_name, ""), |row| (row.javaname.as_str(), row.javastate.as_str()));
+
+        let mut props = HashMap::new();
+        for pair in java_state.split('&').filter(|pair| !pair.is_empty()) {
+            let mut kv = pair.split('=');
+            props.insert(kv.next().unwrap(), kv.next().unwrap_or(""));
+        }
+        if waterlogged {
+            props.insert("waterlogged", "true");
+        }
+
+        // Bedrock states mostly differ from Java properties, so fall back to any state. A dry
+        // block type with no state would also match a waterlogged block, so those are skipped.
+        let java_name = format!("minecraft:{}", java_name);
+        let find = |any_state: bool| self.blocktypes.iter()
+            .position(|b| {
+                b.name == java_name && (b.waterlogged || !waterlogged) && (any_state ||
+                    b.state.iter().all(|(k, v)| props.get(k.as_str()) == Some(&v.as_str())))
+            })
+            .map(|i| i as u16);
+        find(false).or_else(|| find(true))
+    }
+
+    fn get_palette_block(&mut self, ptag: &nbt::Tag) -> Result<PaletteBlock, Error> {
+        let pblock = ptag.to_hashmap()?;
+        let name = pblock.get("name").ok_or_else(|| invalid_data("Palette entry has no name"))?
+            .to_str()?;
+        let mut states = HashMap::new();
+        if let Some(stag) = pblock.get("states") {
+            for (k, v) in stag.to_hashmap()?.iter() {
+                states.insert(k.clone(), get_state_value(v));
+            }
+        }
+
+        let mut sorted_states: Vec<_> = states.iter().collect();
+        sorted_states.sort();
+        let cache_key = format!("{}{:?}", name, sorted_states);
+        if let Some(pb) = self.palette_blocks.get(&cache_key) {
+            return Ok(*pb);
+        }
+
+        let block = self.find_block_type(name, &states, false).unwrap_or_else(|| {
+            println!("Unknown block type {}", name);
+            blocktypes::get_unknown_block_type(self.blocktypes)
+        });
+        let pb = PaletteBlock {
+            block,
+            waterlogged: self.find_block_type(name, &states, true).unwrap_or(block),
+            air: name == "minecraft:air",
+            water: name == "minecraft:water" || name == "minecraft:flowing_water",
+        };
+        self.palette_blocks.insert(cache_key, pb);
+        Ok(pb)
+    }
+
+    fn read_block_storage(&mut self, reader: &mut Cursor<&[u8]>)
+    -> Result<Vec<PaletteBlock>, Error> {
+        let (indices, len) = read_storage_indices(reader)?
+            .ok_or_else(|| invalid_data("Invalid block storage"))?;
+        let mut palette = Vec::with_capacity(len);
+        for _ in 0..len {
+            let (id, _) = nbt::read_le_tag_header(reader)?;
+            let ptag = nbt::read_le_tag_payload(reader, &id)?;
+            palette.push(self.get_palette_block(&ptag)?);
+        }
+        Ok(indices.iter().map(|index| palette[*index]).collect())
+    }
+
+    fn read_subchunk(&mut self, data: &[u8], chunk: &mut ChunkData, so: usize)
+    -> Result<(), Error> {
+        let mut reader = Cursor::new(data);
+        let version = reader.read_u8()?;
+        let storage_count = match version {
+            1 => 1,
+            8 => reader.read_u8()?,
+            9 => {
+                let count = reader.read_u8()?;
+                reader.read_u8()?; // The section Y, which is also in the key.
+                count
+            },
+            // Older subchunks store numeric block IDs and data values like Java Edition did.
+            0 | 2..=7 => {
+                let ids = data.get(1..(1 + BLOCKS_IN_SECTION_3D))
+                    .ok_or_else(|| invalid_data("Truncated legacy subchunk"))?;
+                let values = data
+                    .get((1 + BLOCKS_IN_SECTION_3D)..(1 + BLOCKS_IN_SECTION_3D * 3 / 2))
+                    .ok_or_else(|| invalid_data("Truncated legacy subchunk"))?;
+                for (i, id) in ids.iter().enumerate() {
+                    let value = (values[i / 2] >> ((i % 2) * 4)) & 0x0f;
+                    chunk.blocks[so + storage_index_to_section_offset(i)] = self.legacy_blocktypes
+                        [*id as usize * LEGACY_DATA_VALUES + value as usize];
+                }
+                return Ok(());
+            },
+            _ => return Err(Error::new(ErrorKind::InvalidData,
+                format!("Unsupported subchunk version {}", version))),
+        };
+        if storage_count == 0 {
+            return Ok(());
+        }
+
+        // The second storage, if any, holds liquids in the same space as the first.
+        let blocks = self.read_block_storage(&mut reader)?;
+        let liquids = if storage_count > 1 {
+            Some(self.read_block_storage(&mut reader)?)
+        } else {
+            None
+        };
+
+        for (i, pb) in blocks.iter().enumerate() {
+            let water = liquids.as_ref().is_some_and(|liquids| liquids[i].water);
+            chunk.blocks[so + storage_index_to_section_offset(i)] = match (water, pb.air) {
+                (true, true) => liquids.as_ref().unwrap()[i].block,
+                (true, false) => pb.waterlogged,
+                _ => pb.block,
+            };
+        }
+        Ok(())
+    }
+
+    // Read 3D biomes, stored per block in a palettized storage for each subchunk from the
+    // dimension's lowest one.
+    fn read_biomes_3d(&self, data: &[u8], chunk: &mut ChunkData) -> Result<(), Error> {
+        let mut reader = Cursor::new(data.get(HEIGHTMAP_SIZE..)
+            .ok_or_else(|| invalid_data("Truncated 3D biome data"))?);
+        let mut storage: Option<(Vec<usize>, Vec<u8>)> = None;
+
+        let min_sy = (get_min_section_y(self.reader.db.dimension) - MIN_SECTION_Y) as usize;
+        for sy in min_sy..SECTIONS_IN_CHUNK_Y {
+            if reader.position() as usize >= reader.get_ref().len() {
+                break;
+            }
+            if let Some((indices, len)) = read_storage_indices(&mut reader)? {
+                let mut palette = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = reader.read_u32::<LittleEndian>()?;
+                    palette.push(*self.biomes.get(id as usize).unwrap_or(&0));
+                }
+                storage = Some((indices, palette));
+            }
+
+            // Take the block nearest the middle of each 4x4x4 area.
+            let (indices, palette) = storage.as_ref()
+                .ok_or_else(|| invalid_data("Invalid 3D biome data"))?;
+            for bio in 0..BIOMES_IN_SECTION_3D {
+                let bix = bio % BIOMES_IN_CHUNK;
+                let biz = bio / BIOMES_IN_CHUNK % BIOMES_IN_CHUNK;
+                let biy = bio / BIOMES_IN_CHUNK / BIOMES_IN_CHUNK;
+                let i = (bix * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2) * BLOCKS_IN_CHUNK_2D +
+                    (biz * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2) * BLOCKS_IN_CHUNK +
+                    biy * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2;
+                chunk.biomes[sy * BIOMES_IN_SECTION_3D + bio] = palette[indices[i]];
+            }
+        }
+        Ok(())
+    }
+
+    // Read 2D biomes, stored per column before 1.18, and use them for the full height.
+    fn read_biomes_2d(&self, data: &[u8], chunk: &mut ChunkData) -> Result<(), Error> {
+        let ids = data.get(HEIGHTMAP_SIZE..(HEIGHTMAP_SIZE + BLOCKS_IN_CHUNK_2D))
+            .ok_or_else(|| invalid_data("Truncated 2D biome data"))?;
+        for (bio, biome) in chunk.biomes.iter_mut().enumerate() {
+            let bix = bio % BIOMES_IN_CHUNK;
+            let biz = bio / BIOMES_IN_CHUNK % BIOMES_IN_CHUNK;
+            let bo2 = (biz * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2) * BLOCKS_IN_CHUNK +
+                bix * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2;
+            *biome = self.biomes[ids[bo2] as usize];
+        }
+        Ok(())
+    }
+
+    fn read_chunk(&mut self, c: Pair<isize>) -> Result<Option<ChunkData>, Error> {
+        let mut chunk = ChunkData {
+            blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+            lights: [0u8; BLOCKS_IN_CHUNK_3D],
+            biomes: [0u8; BIOMES_IN_CHUNK_3D],
+            full: true,
+            corrupt: false,
+        };
+
+        let mut found = false;
+        for sy in 0..SECTIONS_IN_CHUNK_Y {
+            let key = ChunkKey {
+                c,
+                tag: TAG_SUBCHUNK_PREFIX,
+                y: (sy as isize + MIN_SECTION_Y) as i8,
+            };
+            if let Some(data) = self.reader.get(&key)? {
+                self.read_subchunk(&data, &mut chunk, sy * BLOCKS_IN_SECTION_3D)?;
+                found = true;
+            }
+        }
+        if !found {
+            return Ok(None);
+        }
+
+        if let Some(data) = self.reader.get(&ChunkKey { c, tag: TAG_DATA_3D, y: 0 })? {
+            self.read_biomes_3d(&data, &mut chunk)?;
+        } else if let Some(data) = self.reader.get(&ChunkKey { c, tag: TAG_DATA_2D, y: 0 })? {
+            self.read_biomes_2d(&data, &mut chunk)?;
+        }
+
+        if let Some(data) = self.reader.get(&ChunkKey { c, tag: TAG_FINALIZED_STATE, y: 0 })? {
+            if data.len() >= 4 {
+                chunk.full = LittleEndian::read_u32(&data) >= FINALIZED_DONE;
+            }
+        }
+
+        // Bedrock Edition doesn't save light levels, so they are always computed from the blocks.
+        light::compute_lights(&mut chunk, self.blocktypes);
+
+        Ok(Some(chunk))
+    }
+
+    fn read_region_chunk_data(&mut self, r: &Pair<isize>, rclimits: &Edges<usize>)
+    -> (RegionChunks, Vec<ChunkError>) {
+        let mut chunks = HashMap::new();
+        let mut errors = vec![];
+        let size = rclimits.size();
+
+        let bar = ProgressBar::with_draw_target((size.x * size.z) as u64,
+            ProgressDrawTarget::stdout())
+            .with_style(ProgressStyle::default_bar().template("{wide_bar}").progress_chars("▪■ "));
+
+        for cz in rclimits.n..(rclimits.s + 1) {
+            for cx in rclimits.w..(rclimits.e + 1) {
+                bar.inc(1);
+
+                let c = Pair {
+                    x: r.x * CHUNKS_IN_REGION as isize + cx as isize,
+                    z: r.z * CHUNKS_IN_REGION as isize + cz as isize,
+                };
+                match self.read_chunk(c) {
+                    Ok(Some(chunk)) => {
+                        chunks.insert(Pair { x: cx, z: cz }, chunk);
+                    },
+                    Ok(None) => {},
+                    Err(err) => errors.push((Pair { x: cx, z: cz }, err)),
+                }
+            }
+        }
+
+        bar.finish_and_clear();
+
+        (chunks, errors)
+    }
+}
+
+pub fn read_region_data(world: &World, decoder: &mut ChunkDecoder, r: &Pair<isize>,
+    blocktypes: &[BlockType], neighbours: bool)
+-> Result<Option<RegionData>, Box<dyn std::error::Error>> {
+    if !world.regions.contains_key(r) {
+        return Ok(None);
+    }
+
+    let rclimits = region::get_region_chunk_limits(world, r);
+    let (mut chunks, errors) = decoder.read_region_chunk_data(r, &rclimits);
+    region::add_placeholder_chunks(&mut chunks, &errors, blocktypes, world.ylimits);
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    if !neighbours {
+        return Ok(Some(RegionData { chunks, nchunks: region::no_neighbour_chunks(), errors }));
+    }
+
+    let nrclimits = region::get_neighbour_chunk_limits(&rclimits);
+
+    Ok(Some(RegionData {
+        chunks,
+        nchunks: Edges {
+            n: decoder.read_region_chunk_data(&Pair { x: r.x, z: r.z - 1 }, &nrclimits.n).0,
+            e: decoder.read_region_chunk_data(&Pair { x: r.x + 1, z: r.z }, &nrclimits.e).0,
+            s: decoder.read_region_chunk_data(&Pair { x: r.x, z: r.z + 1 }, &nrclimits.s).0,
+            w: decoder.read_region_chunk_data(&Pair { x: r.x - 1, z: r.z }, &nrclimits.w).0,
+        },
+        errors,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biometypes;
+    use crate::compression;
+    use crate::options::Lighting;
+
+    fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+    }
+
+    fn internal_key(user_key: &[u8], seq: u64, value_type: u8) -> Vec<u8> {
+        let mut key = user_key.to_vec();
+        key.extend_from_slice(&(seq << 8 | value_type as u64).to_le_bytes());
+        key
+    }
+
+    fn subchunk_key(x: isize, z: isize, y: i8) -> ChunkKey {
+        ChunkKey { c: Pair { x, z }, tag: TAG_SUBCHUNK_PREFIX, y }
+    }
+
+    // Write a table block, sharing each key's prefix with the key before it.
+    fn table_block(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        let mut prev: &[u8] = &[];
+        for (key, value) in entries {
+            let shared = key.iter().zip(prev).take_while(|(a, b)| a == b).count();
+            write_varint(&mut block, shared as u64);
+            write_varint(&mut block, (key.len() - shared) as u64);
+            write_varint(&mut block, value.len() as u64);
+            block.extend_from_slice(&key[shared..]);
+            block.extend_from_slice(value);
+            prev = key;
+        }
+        // A single restart point at the start of the block.
+        block.extend_from_slice(&0u32.to_le_bytes());
+        block.extend_from_slice(&1u32.to_le_bytes());
+        block
+    }
+
+    // Write a table with one uncompressed data block and a zlib-compressed index block.
+    fn table_file(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut file = table_block(entries);
+        let mut handle = Vec::new();
+        write_varint(&mut handle, 0);
+        write_varint(&mut handle, file.len() as u64);
+        file.extend_from_slice(&[NO_COMPRESSION, 0, 0, 0, 0]);
+
+        let last_key = entries.last().unwrap().0.clone();
+        let index = compression::compress(compression::ZLIB, &table_block(&[(last_key, handle)]))
+            .unwrap();
+        let mut footer = vec![0, 0];
+        write_varint(&mut footer, file.len() as u64);
+        write_varint(&mut footer, index.len() as u64);
+        footer.resize(TABLE_FOOTER_SIZE - 8, 0);
+        footer.extend_from_slice(&TABLE_MAGIC.to_le_bytes());
+
+        file.extend_from_slice(&index);
+        file.extend_from_slice(&[ZLIB_COMPRESSION, 0, 0, 0, 0]);
+        file.extend_from_slice(&footer);
+        file
+    }
+
+    fn log_batch(seq: u64, records: &[(&ChunkKey, Option<&[u8]>)]) -> Vec<u8> {
+        let mut batch = seq.to_le_bytes().to_vec();
+        batch.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        for (key, value) in records {
+            batch.push(if value.is_some() { TYPE_VALUE } else { 0 });
+            let key = encode_chunk_key(key, OVERWORLD);
+            write_varint(&mut batch, key.len() as u64);
+            batch.extend_from_slice(&key);
+            if let Some(value) = value {
+                write_varint(&mut batch, value.len() as u64);
+                batch.extend_from_slice(value);
+            }
+        }
+        batch
+    }
+
+    // Write batches to a log the way LevelDB does, splitting them at block boundaries.
+    fn log_file(batches: &[Vec<u8>]) -> Vec<u8> {
+        let mut log = Vec::new();
+        for batch in batches {
+            let mut rest = &batch[..];
+            let mut first = true;
+            loop {
+                let block_left = LOG_BLOCK_SIZE - log.len() % LOG_BLOCK_SIZE;
+                if block_left < LOG_HEADER_SIZE {
+                    log.resize(log.len() + block_left, 0);
+                    continue;
+                }
+                let len = rest.len().min(block_left - LOG_HEADER_SIZE);
+                let last = len == rest.len();
+                let record_type = match (first, last) {
+                    (true, true) => LOG_FULL,
+                    (true, false) => LOG_FIRST,
+                    (false, false) => LOG_MIDDLE,
+                    (false, true) => LOG_LAST,
+                };
+                log.extend_from_slice(&[0, 0, 0, 0]);
+                log.extend_from_slice(&(len as u16).to_le_bytes());
+                log.push(record_type);
+                log.extend_from_slice(&rest[..len]);
+                rest = &rest[len..];
+                first = false;
+                if last {
+                    break;
+                }
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn varints() {
+        let mut bytes = Vec::new();
+        for value in [0, 127, 128, 300, u32::MAX as u64] {
+            write_varint(&mut bytes, value);
+        }
+        let mut pos = 0;
+        for value in [0, 127, 128, 300, u32::MAX as u64] {
+            assert_eq!(read_varint(&bytes, &mut pos).unwrap(), value);
+        }
+        assert_eq!(pos, bytes.len());
+        assert!(read_varint(&[0x80, 0x80], &mut 0).is_err());
+    }
+
+    #[test]
+    fn chunk_keys() {
+        let key = ChunkKey { c: Pair { x: -1, z: 70000 }, tag: TAG_SUBCHUNK_PREFIX, y: -4 };
+        let bytes = encode_chunk_key(&key, OVERWORLD);
+        assert_eq!(bytes, [255, 255, 255, 255, 0x70, 0x11, 1, 0, 47, 0xfc]);
+        assert!(parse_chunk_key(&bytes, OVERWORLD) == Some(key));
+        assert!(parse_chunk_key(&bytes, NETHER).is_none());
+
+        let key = ChunkKey { c: Pair { x: 5, z: -6 }, tag: TAG_DATA_3D, y: 0 };
+        let bytes = encode_chunk_key(&key, NETHER);
+        assert_eq!(bytes.len(), 13);
+        assert!(parse_chunk_key(&bytes, NETHER) == Some(key));
+        assert!(parse_chunk_key(&bytes, END).is_none());
+        assert!(parse_chunk_key(&bytes, OVERWORLD).is_none());
+
+        // Other chunk records, and keys that aren't for chunks.
+        assert!(parse_chunk_key(&[0, 0, 0, 0, 0, 0, 0, 0, 118], OVERWORLD).is_none());
+        assert!(parse_chunk_key(b"~local_player", OVERWORLD).is_none());
+        assert!(parse_chunk_key(b"LevelChunkMetaDataDictionary", OVERWORLD).is_none());
+    }
+
+    #[test]
+    fn table_entries() {
+        let entries = vec![
+            (internal_key(b"apple", 3, TYPE_VALUE), b"1".to_vec()),
+            (internal_key(b"applesauce", 2, TYPE_VALUE), b"22".to_vec()),
+            (internal_key(b"banana", 1, 0), vec![]),
+        ];
+        let block = table_block(&entries);
+        let read: Vec<(Vec<u8>, Vec<u8>)> = read_block_entries(&block).unwrap().into_iter()
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect();
+        assert_eq!(read, entries);
+        assert!(split_internal_key(&read[1].0) == Some((&b"applesauce"[..], 2, TYPE_VALUE)));
+        assert!(split_internal_key(b"short").is_none());
+
+        assert!(read_block_entries(&block[..(block.len() - 12)]).is_err());
+        assert!(read_block_entries(&[1, 0]).is_err());
+    }
+
+    #[test]
+    fn read_database() {
+        let dir = std::env::temp_dir().join(format!("rmapbash-db-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let nether_key = subchunk_key(0, 0, 2);
+        let table = table_file(&[
+            (internal_key(&encode_chunk_key(&subchunk_key(1, -2, 0), OVERWORLD), 1, TYPE_VALUE),
+                b"old".to_vec()),
+            (internal_key(&encode_chunk_key(&subchunk_key(3, 4, -4), OVERWORLD), 2, TYPE_VALUE),
+                b"deleted later".to_vec()),
+            (internal_key(&encode_chunk_key(&nether_key, NETHER), 3, TYPE_VALUE),
+                b"nether".to_vec()),
+            (internal_key(b"~local_player", 4, TYPE_VALUE), b"player".to_vec()),
+        ]);
+        std::fs::write(dir.join("000005.ldb"), table).unwrap();
+
+        // A value big enough to be split across three log blocks.
+        let big: Vec<u8> = (0..70000).map(|i| (i % 251) as u8).collect();
+        let log = log_file(&[
+            log_batch(10, &[(&subchunk_key(1, -2, 0), Some(&big))]),
+            log_batch(11, &[
+                (&subchunk_key(3, 4, -4), None),
+                (&subchunk_key(1, -2, 1), Some(b"x")),
+            ]),
+        ]);
+        assert!(log.len() > LOG_BLOCK_SIZE * 2);
+        std::fs::write(dir.join("000006.log"), log).unwrap();
+
+        let overworld = Db::open(&dir, OVERWORLD).unwrap();
+        let coords: Vec<(isize, isize)> = overworld.get_chunk_coords().iter()
+            .map(|c| (c.x, c.z))
+            .collect();
+        assert_eq!(coords, [(1, -2)]);
+        assert!(!overworld.contains(&subchunk_key(3, 4, -4)));
+        let mut reader = DbReader::new(&overworld);
+        assert_eq!(reader.get(&subchunk_key(1, -2, 0)).unwrap(), Some(big));
+        assert_eq!(reader.get(&subchunk_key(1, -2, 1)).unwrap(), Some(b"x".to_vec()));
+        assert_eq!(reader.get(&subchunk_key(3, 4, -4)).unwrap(), None);
+
+        // Values still in the table are read from its data block.
+        let nether = Db::open(&dir, NETHER).unwrap();
+        assert_eq!(nether.get_chunk_coords().len(), 1);
+        let mut reader = DbReader::new(&nether);
+        assert_eq!(reader.get(&nether_key).unwrap(), Some(b"nether".to_vec()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Write a little-endian NBT palette entry, with a name and string states.
+    fn palette_entry(name: &str, states: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let write_string = |bytes: &mut Vec<u8>, value: &str| {
+            bytes.extend((value.len() as u16).to_le_bytes());
+            bytes.extend(value.as_bytes());
+        };
+        bytes.push(10);
+        write_string(&mut bytes, "");
+        bytes.push(8);
+        write_string(&mut bytes, "name");
+        write_string(&mut bytes, name);
+        bytes.push(10);
+        write_string(&mut bytes, "states");
+        for (key, value) in states {
+            bytes.push(8);
+            write_string(&mut bytes, key);
+            write_string(&mut bytes, value);
+        }
+        bytes.push(0);
+        bytes.push(3);
+        write_string(&mut bytes, "version");
+        bytes.extend(17959425i32.to_le_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    // Write a block storage with a word of indices, then words of index 0, and its palette.
+    fn block_storage(bits: u8, word: u32, palette: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![bits << 1];
+        bytes.extend(word.to_le_bytes());
+        let words = BLOCKS_IN_SECTION_3D.div_ceil(32 / bits as usize);
+        bytes.extend(vec![0u8; (words - 1) * 4]);
+        bytes.extend((palette.len() as u32).to_le_bytes());
+        for entry in palette {
+            bytes.extend(entry);
+        }
+        bytes
+    }
+
+    #[test]
+    fn subchunks() {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+        let legacy_blocktypes = blocktypes::get_legacy_block_types(&blocktypes);
+        let registry = biometypes::get_biome_registry(&biometypes);
+        let db = Db { dimension: OVERWORLD, tables: vec![], records: HashMap::new() };
+        let mut decoder = ChunkDecoder::new(&db, &blocktypes, &registry, &legacy_blocktypes);
+        let find_block = |name: &str, waterlogged: bool| blocktypes.iter()
+            .position(|b| b.name == name && b.waterlogged == waterlogged)
+            .unwrap() as u16;
+
+        // The blocks at the bottom of the first column, with Y changing fastest: granite, a
+        // conduit in water, water, and a conduit on its own. The liquids are in a second layer.
+        let blocks = block_storage(2, 0b10_00_10_01, &[
+            palette_entry("minecraft:air", &[]),
+            palette_entry("minecraft:stone", &[("stone_type", "granite")]),
+            palette_entry("minecraft:conduit", &[]),
+        ]);
+        let liquids = block_storage(1, 0b110, &[
+            palette_entry("minecraft:air", &[]),
+            palette_entry("minecraft:water", &[("liquid_depth", "0")]),
+        ]);
+        let expected = [
+            find_block("minecraft:granite", false),
+            find_block("minecraft:conduit", true),
+            find_block("minecraft:water", false),
+            find_block("minecraft:conduit", false),
+            find_block("minecraft:air", false),
+        ];
+
+        // Versions 8 and 9 give the number of layers, and version 9 the section Y too.
+        for header in [vec![8, 2], vec![9, 2, 0xfc]] {
+            let data = [header, blocks.clone(), liquids.clone()].concat();
+            let mut chunk = ChunkData {
+                blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+                lights: [0u8; BLOCKS_IN_CHUNK_3D],
+                biomes: [0u8; BIOMES_IN_CHUNK_3D],
+                full: true,
+                corrupt: false,
+            };
+            let so = BLOCKS_IN_SECTION_3D;
+            decoder.read_subchunk(&data, &mut chunk, so).unwrap();
+            let column: Vec<u16> = (0..expected.len())
+                .map(|by| chunk.blocks[so + by * BLOCKS_IN_CHUNK_2D])
+                .collect();
+            assert_eq!(column, expected);
+            assert_eq!(chunk.blocks[0], 0);
+        }
+
+        // Version 1 has a single layer, so nothing is in water.
+        let data = [vec![1], blocks.clone()].concat();
+        let mut chunk = ChunkData {
+            blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+            lights: [0u8; BLOCKS_IN_CHUNK_3D],
+            biomes: [0u8; BIOMES_IN_CHUNK_3D],
+            full: true,
+            corrupt: false,
+        };
+        decoder.read_subchunk(&data, &mut chunk, 0).unwrap();
+        assert_eq!(chunk.blocks[BLOCKS_IN_CHUNK_2D], find_block("minecraft:conduit", false));
+        assert_eq!(chunk.blocks[BLOCKS_IN_CHUNK_2D * 2], find_block("minecraft:air", false));
+
+        assert!(decoder.read_subchunk(&[8, 1, 2], &mut chunk, 0).is_err());
+        assert!(decoder.read_subchunk(&[10], &mut chunk, 0).is_err());
+    }
+
+    #[test]
+    fn biomes_3d() {
+        // Two single-biome storages: plains, then desert.
+        let mut data = vec![0u8; HEIGHTMAP_SIZE];
+        data.extend([0, 1, 0, 0, 0, 0, 2, 0, 0, 0]);
+
+        let registry = BiomeRegistry::new();
+        let biome_at = |dimension: i32, sy: usize| {
+            let db = Db { dimension, tables: vec![], records: HashMap::new() };
+            let decoder = ChunkDecoder::new(&db, &[], &registry, &[]);
+            let mut chunk = ChunkData {
+                blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+                lights: [0u8; BLOCKS_IN_CHUNK_3D],
+                biomes: [u8::MAX; BIOMES_IN_CHUNK_3D],
+                full: true,
+                corrupt: false,
+            };
+            decoder.read_biomes_3d(&data, &mut chunk).unwrap();
+            (chunk.biomes[sy * BIOMES_IN_SECTION_3D], decoder.biomes[1], decoder.biomes[2])
+        };
+
+        // The overworld's first storage is at Y -64, but other dimensions' are at Y 0.
+        let (biome, plains, desert) = biome_at(OVERWORLD, 0);
+        assert_eq!(biome, plains);
+        assert_eq!(biome_at(OVERWORLD, 1).0, desert);
+        assert_eq!(biome_at(NETHER, 3).0, u8::MAX);
+        assert_eq!(biome_at(NETHER, 4).0, plains);
+        assert_eq!(biome_at(END, 5).0, desert);
+        assert_ne!(plains, desert);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::Path;
 
 use csv::Reader;
@@ -9,8 +10,8 @@ use super::sizes::*;
 
 #[derive(Deserialize)]
 struct Row {
-    id: u8,
-    // name: String,
+    id: Option<u8>,
+    name: String,
     fr: Option<u8>,
     fg: Option<u8>,
     fb: Option<u8>,
@@ -22,24 +23,43 @@ struct Row {
     wb: Option<u8>,
 }
 
+#[derive(Deserialize)]
+struct AliasRow {
+    alias: String,
+    name: String,
+}
+
 pub struct BiomeType {
-    pub id: u8,
-    // pub name: String,
+    // Index into block colors and chunk biome arrays; the same as the numeric ID if there is one.
+    pub index: u8,
+    pub name: String,
     pub foliage: RGBA,
     pub grass: RGBA,
     pub water: RGBA,
 }
 
+// Namespaced biome names, including aliases, mapped to biome indices.
+pub type BiomeRegistry = HashMap<String, u8>;
+
 pub fn get_biome_types() -> Vec<BiomeType> {
     let csvpath = Path::new("./resources/biomes.csv");
     let mut reader = Reader::from_path(csvpath).unwrap();
+    let rows: Vec<Row> = reader.deserialize().map(|res| res.unwrap()).collect();
+
+    // Biomes with a numeric ID use it as their index, so that pre-1.18 biome arrays can be used
+    // as they are. Biomes added since then are indexed after the highest numeric ID.
+    let mut next_index = rows.iter().filter_map(|row| row.id).max().map_or(0, |id| id + 1);
+
     let mut biometypes = Vec::new();
-    for result in reader.deserialize() {
-        let row: Row = result.unwrap();
+    for row in rows {
+        let index = row.id.unwrap_or_else(|| {
+            next_index += 1;
+            next_index - 1
+        });
 
         biometypes.push(BiomeType {
-            id: row.id,
-            // name: row.name,
+            index,
+            name: format!("minecraft:{}", row.name),
             foliage: RGBA {
                 r: row.fr.unwrap_or(0),
                 g: row.fg.unwrap_or(0),
@@ -62,3 +82,21 @@ pub fn get_biome_types() -> Vec<BiomeType> {
     }
     biometypes
 }
+
+pub fn get_biome_registry(biometypes: &[BiomeType]) -> BiomeRegistry {
+    let mut registry: BiomeRegistry = biometypes.iter()
+        .map(|biome| (biome.name.clone(), biome.index))
+        .collect();
+
+    // Add names that biomes have had in other versions.
+    let csvpath = Path::new("./resources/biomealiases.csv");
+    let mut reader = Reader::from_path(csvpath).unwrap();
+    for result in reader.deserialize() {
+        let row: AliasRow = result.unwrap();
+        if let Some(index) = registry.get(&format!("minecraft:{}", row.name)).copied() {
+            registry.insert(format!("minecraft:{}", row.alias), index);
+        }
+    }
+
+    registry
+}
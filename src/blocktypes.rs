@@ -7,7 +7,7 @@ use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 use serde::Deserialize;
 
-use super::biometypes;
+use super::biometypes::BiomeType;
 use super::color;
 use super::color::{RGBA, BLANK_RGBA, RGB};
 use super::options::Lighting;
@@ -30,6 +30,21 @@ struct BlockRow {
     waterlogged: Option<u8>,
 }
 
+#[derive(Deserialize)]
+struct LegacyRow {
+    id: u8,
+    data: Option<u8>,
+    name: String,
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct EmitterRow {
+    name: String,
+    state: String,
+    light: u8,
+}
+
 #[derive(Deserialize)]
 struct LightRow {
     sky: Option<usize>,
@@ -39,15 +54,30 @@ struct LightRow {
     b: Option<u8>,
 }
 
+// Block colors for each skylight and blocklight level, for each digit in the block shape.
+pub type LightColors = [[[RGBA; 7]; LIGHT_LEVELS]; LIGHT_LEVELS];
+
 pub struct BlockType {
     pub id: u16,
     pub name: String,
-    pub colors: [[[[RGBA; 7]; LIGHT_LEVELS]; LIGHT_LEVELS]; BIOME_ARRAY_SIZE],
+    pub colors: Vec<LightColors>,
     pub state: HashMap<String, String>,
     pub shape: [[usize; ISO_BLOCK_WIDTH]; ISO_BLOCK_HEIGHT],
     pub solid: bool,
+    pub opaque: bool,
     pub empty: bool,
     pub waterlogged: bool,
+    // Whether skylight loses a level through the block, rather than being stopped by it.
+    pub filters_light: bool,
+    // The block light level that the block gives off.
+    pub light: u8,
+}
+
+impl BlockType {
+    // Blocks that aren't tinted by biome only have one set of colors.
+    pub fn get_colors(&self, biome: usize) -> &LightColors {
+        self.colors.get(biome).unwrap_or(&self.colors[0])
+    }
 }
 
 impl PartialEq for BlockType {
@@ -56,14 +86,24 @@ impl PartialEq for BlockType {
     }
 }
 
+// The name of the block type for blocks that aren't in the block table.
+pub const UNKNOWN_BLOCK_NAME: &str = "unknown";
+
+// Blocks that skylight loses a level through, along with leaves and waterlogged blocks.
+const LIGHT_FILTER_BLOCKS: [&str; 5] = [
+    "bubble_column",
+    "cobweb",
+    "frosted_ice",
+    "ice",
+    "water",
+];
+
 const HILIGHT_SHADOW_AMOUNT_DAY: f64 = 0.125;
 const HILIGHT_SHADOW_AMOUNT_NIGHT: f64 = 0.05;
 
-pub fn get_block_types(lighting: &Lighting) -> Vec<BlockType> {
+pub fn get_block_types(lighting: &Lighting, biome_types: &[BiomeType]) -> Vec<BlockType> {
     let mut blocktypes = Vec::new();
 
-    let biome_types = biometypes::get_biome_types();
-
     let lightfile = format!("./resources/light/{}.csv", lighting).to_lowercase();
     let lightpath = Path::new(&lightfile);
     let mut lightreader = Reader::from_path(lightpath).unwrap();
@@ -103,16 +143,19 @@ pub fn get_block_types(lighting: &Lighting) -> Vec<BlockType> {
         };
         let biome_color_type = row.biome.unwrap_or(0);
 
-        let mut blockcolors = [[[[BLANK_RGBA; 7]; LIGHT_LEVELS]; LIGHT_LEVELS]; BIOME_ARRAY_SIZE];
-        for biome in &biome_types {
-            // Apply biome color to primary color only.
-            let biome_id = biome.id as usize;
-            let biome_color = match biome_color_type {
+        // Apply biome color to primary color only.
+        let biome_colors: Vec<(usize, RGBA)> = match biome_color_type {
+            0 => vec![(0, block_color)],
+            _ => biome_types.iter().map(|biome| (biome.index as usize, match biome_color_type {
                 1 => color::shade_biome_color(&block_color, &biome.foliage),
                 2 => color::shade_biome_color(&block_color, &biome.grass),
-                3 => color::multiply_color(&block_color, &biome.water),
-                _ => block_color.clone(),
-            };
+                _ => color::multiply_color(&block_color, &biome.water),
+            })).collect(),
+        };
+
+        let mut blockcolors = vec![[[[BLANK_RGBA; 7]; LIGHT_LEVELS]; LIGHT_LEVELS];
+            biome_colors.iter().map(|(biome_id, _)| biome_id + 1).max().unwrap_or(1)];
+        for (biome_id, biome_color) in biome_colors {
 
             for sl in 0..LIGHT_LEVELS {
                 for bl in 0..LIGHT_LEVELS {
@@ -160,12 +203,108 @@ pub fn get_block_types(lighting: &Lighting) -> Vec<BlockType> {
             shape,
             state,
             solid: row.shape.find('0').is_none(),
+            opaque: row.shape.find('0').is_none() && row.a == Some(MAX_CHANNEL_VALUE),
             empty: row.shape == "" || row.shape == "0000000000000000",
             waterlogged: row.waterlogged.unwrap_or(0) == 1,
+            filters_light: LIGHT_FILTER_BLOCKS.contains(&row.name.as_str()) ||
+                row.name.ends_with("_leaves") || row.waterlogged.unwrap_or(0) == 1,
+            light: 0,
         });
     }
 
     bar.finish_and_clear();
 
+    // Set light levels for blocks that emit light, matching on name and any given state.
+    let emitterpath = Path::new("./resources/lightblocks.csv");
+    let mut emitterreader = Reader::from_path(emitterpath).unwrap();
+    for result in emitterreader.deserialize() {
+        let row: EmitterRow = result.unwrap();
+        let name = format!("minecraft:{}", row.name);
+        let state: Vec<(&str, &str)> = row.state.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut kv = pair.split('=');
+                (kv.next().unwrap(), kv.next().unwrap())
+            })
+            .collect();
+        for blocktype in blocktypes.iter_mut().filter(|b| b.name == name) {
+            if state.iter().all(|(k, v)| blocktype.state.get(*k).map(|s| s.as_str()) == Some(v)) {
+                blocktype.light = row.light;
+            }
+        }
+    }
+
+    // Blocks that aren't in the table are drawn like the blank block type 0, but are given a
+    // block type of their own at the end, so that they can still be counted.
+    let blank = &blocktypes[0];
+    let unknown = BlockType {
+        id: blocktypes.len() as u16,
+        name: UNKNOWN_BLOCK_NAME.to_string(),
+        colors: blank.colors.clone(),
+        state: HashMap::new(),
+        shape: blank.shape,
+        solid: blank.solid,
+        opaque: blank.opaque,
+        empty: blank.empty,
+        waterlogged: blank.waterlogged,
+        filters_light: blank.filters_light,
+        light: blank.light,
+    };
+    blocktypes.push(unknown);
+
     blocktypes
 }
+
+// Get the block type for blocks that aren't in the block table.
+pub fn get_unknown_block_type(blocktypes: &[BlockType]) -> u16 {
+    (blocktypes.len() - 1) as u16
+}
+
+// Find the first block type with this name whose state is a subset of the given properties.
+pub fn find_block_type(blocktypes: &[BlockType], name: &str, props: &HashMap<&str, &str>)
+-> Option<u16> {
+    blocktypes.iter()
+        .position(|b| {
+            b.name == name &&
+            b.state.iter().all(|(k, v)| props.get(k.as_str()) == Some(&v.as_str()))
+        })
+        .map(|i| i as u16)
+}
+
+// Build a table mapping pre-1.13 numeric block IDs and data values to block types,
+// indexed by (id << 4 | data). IDs and data values that aren't listed are unknown blocks.
+pub fn get_legacy_block_types(blocktypes: &[BlockType]) -> Vec<u16> {
+    let unknown = get_unknown_block_type(blocktypes);
+    let mut legacy = vec![unknown; LEGACY_BLOCK_IDS * LEGACY_DATA_VALUES];
+
+    let legacypath = Path::new("./resources/legacyblocks.csv");
+    let mut legacyreader = Reader::from_path(legacypath).unwrap();
+    let legacyrows: Vec<LegacyRow> = legacyreader.deserialize().map(|res| res.unwrap()).collect();
+
+    // Rows without a data value apply to all data values of that ID,
+    // and come before any rows for specific data values.
+    for row in &legacyrows {
+        let name = format!("minecraft:{}", row.name);
+        let mut props = HashMap::new();
+        for pair in row.state.split('&') {
+            if !pair.is_empty() {
+                let mut kv = pair.split('=');
+                props.insert(kv.next().unwrap(), kv.next().unwrap());
+            }
+        }
+        let btype = find_block_type(blocktypes, &name, &props).unwrap_or_else(|| {
+            println!("Unknown block type {}", name);
+            unknown
+        });
+
+        let lo = row.id as usize * LEGACY_DATA_VALUES;
+        match row.data {
+            Some(data) => legacy[lo + data as usize] = btype,
+            None => for entry in &mut legacy[lo..(lo + LEGACY_DATA_VALUES)] {
+                *entry = btype;
+            },
+        }
+    }
+
+    legacy
+}
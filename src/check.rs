@@ -0,0 +1,425 @@
+use std::error::Error;
+use std::io::{Cursor, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use serde::Deserialize;
+
+use super::compression;
+use super::deserializer;
+use super::region;
+use super::region::RegionFormat;
+use super::sizes::*;
+use super::types::*;
+
+// The chunk offsets and timestamps at the start of each region file.
+const HEADER_SECTORS: usize = 2;
+const TIMESTAMPS_OFFSET: usize = SECTOR_SIZE;
+// The chunk length and compression type at the start of each chunk.
+const CHUNK_HEADER_SIZE: usize = 5;
+
+struct RegionFile {
+    path: PathBuf,
+    coords: Pair<isize>,
+}
+
+// A chunk that passed every check, with the range of bytes holding its length, compression type
+// and data, which can be copied as they are into a repaired file.
+struct GoodChunk {
+    index: usize,
+    start: usize,
+    end: usize,
+}
+
+struct RegionReport {
+    chunk_count: usize,
+    problems: Vec<String>,
+    good_chunks: Vec<GoodChunk>,
+}
+
+// Find region files for a world or region directory, or a single region file.
+fn get_region_files(path: &Path) -> Result<Vec<RegionFile>, Box<dyn Error>> {
+    let get_coords = |path: &Path| path.file_name().and_then(|filename| filename.to_str())
+        .and_then(|filename| region::get_coords_from_path(filename, RegionFormat::Anvil)
+            .or_else(|| region::get_coords_from_path(filename, RegionFormat::McRegion)));
+
+    if path.is_file() {
+        let coords = get_coords(path).ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput,
+            "Not a region file name"))?;
+        return Ok(vec![RegionFile { path: path.to_path_buf(), coords }]);
+    }
+
+    let region_path = if path.join("region").is_dir() { path.join("region") }
+        else { path.to_path_buf() };
+    let mut region_files: Vec<RegionFile> = std::fs::read_dir(region_path)?
+        .filter_map(|dir_entry| dir_entry.ok())
+        .filter_map(|entry| get_coords(&entry.path())
+            .map(|coords| RegionFile { path: entry.path(), coords }))
+        .collect();
+    region_files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(region_files)
+}
+
+// The chunk's own coords, which are in the Level compound before 21w43a.
+#[derive(Deserialize)]
+struct ChunkCoords {
+    #[serde(rename = "xPos")]
+    x: Option<i32>,
+    #[serde(rename = "zPos")]
+    z: Option<i32>,
+    #[serde(rename = "Level")]
+    level: Option<Box<ChunkCoords>>,
+}
+
+fn read_chunk_coords(bytes: Vec<u8>) -> Result<Option<Pair<isize>>, std::io::Error> {
+    let chunk: ChunkCoords = deserializer::from_reader(&mut Cursor::new(bytes))?;
+    let coords = chunk.level.as_deref().unwrap_or(&chunk);
+    Ok(match (coords.x, coords.z) {
+        (Some(x), Some(z)) => Some(Pair { x: x as isize, z: z as isize }),
+        _ => None,
+    })
+}
+
+fn check_region(file: &RegionFile, bytes: &[u8]) -> RegionReport {
+    let mut chunk_count = 0;
+    let mut problems = vec![];
+    let mut good_chunks = vec![];
+
+    if bytes.len() < HEADER_SECTORS * SECTOR_SIZE {
+        if !bytes.is_empty() {
+            problems.push(format!("Header is truncated ({} bytes)", bytes.len()));
+        }
+        return RegionReport { chunk_count, problems, good_chunks };
+    }
+    if !bytes.len().is_multiple_of(SECTOR_SIZE) {
+        problems.push(format!("File length {} is not a whole number of sectors", bytes.len()));
+    }
+
+    let sector_count = bytes.len().div_ceil(SECTOR_SIZE);
+    let mut sector_owners: Vec<Option<usize>> = vec![None; sector_count];
+
+    let mut reader = Cursor::new(bytes);
+    for index in 0..CHUNKS_IN_REGION_2D {
+        let cx = index % CHUNKS_IN_REGION;
+        let cz = index / CHUNKS_IN_REGION;
+        let (offset, sectors) = match region::read_chunk_location(&mut reader, cx, cz) {
+            Ok(location) => location,
+            Err(err) => {
+                problems.push(format!("Can't read header: {}", err));
+                break;
+            },
+        };
+        if offset == 0 && sectors == 0 {
+            continue;
+        }
+        chunk_count += 1;
+
+        let mut problem = |msg: String| problems.push(format!("Chunk {}, {}: {}", cx, cz, msg));
+
+        if offset < HEADER_SECTORS {
+            problem(format!("Offset {} is inside the header", offset));
+            continue;
+        }
+        if sectors == 0 {
+            problem("Sector count is 0".to_string());
+            continue;
+        }
+        if offset + sectors > sector_count {
+            problem(format!("Sectors {}-{} are past the end of the file ({} sectors)",
+                offset, offset + sectors - 1, sector_count));
+            continue;
+        }
+
+        // Overlapping chunks are still checked, since one of them may be intact.
+        let mut overlaps = vec![];
+        for owner in sector_owners[offset..(offset + sectors)].iter_mut() {
+            match owner {
+                Some(other) if !overlaps.contains(other) => overlaps.push(*other),
+                Some(_) => {},
+                None => *owner = Some(index),
+            }
+        }
+        for other in overlaps {
+            problem(format!("Sectors overlap chunk {}, {}", other % CHUNKS_IN_REGION,
+                other / CHUNKS_IN_REGION));
+        }
+
+        // The last sector may be cut short, so check the chunk's bytes against the file length.
+        let start = offset * SECTOR_SIZE;
+        if start + CHUNK_HEADER_SIZE > bytes.len() {
+            problem("Chunk header is past the end of the file".to_string());
+            continue;
+        }
+        let length = BigEndian::read_u32(&bytes[start..]) as usize;
+        if length == 0 || length + 4 > sectors * SECTOR_SIZE {
+            problem(format!("Length {} doesn't fit in its {} sector{}", length, sectors,
+                if sectors == 1 { "" } else { "s" }));
+            continue;
+        }
+        let end = start + 4 + length;
+        if end > bytes.len() {
+            problem(format!("Length {} runs past the end of the file", length));
+            continue;
+        }
+        let compression = bytes[start + 4];
+
+        let data = if compression::is_external(compression) {
+            match region::get_external_chunk_path(&file.path, cx, cz)
+                .and_then(std::fs::read) {
+                Ok(data) => data,
+                Err(err) => {
+                    problem(format!("Can't read external chunk file: {}", err));
+                    continue;
+                },
+            }
+        } else {
+            bytes[(start + CHUNK_HEADER_SIZE)..end].to_vec()
+        };
+        let data = match compression::decompress_chunk(compression, data) {
+            Ok(data) => data,
+            Err(err) => {
+                problem(format!("Can't decompress data: {}", err));
+                continue;
+            },
+        };
+
+        let expected = Pair {
+            x: file.coords.x * CHUNKS_IN_REGION as isize + cx as isize,
+            z: file.coords.z * CHUNKS_IN_REGION as isize + cz as isize,
+        };
+        match read_chunk_coords(data) {
+            Ok(Some(c)) if c == expected => good_chunks.push(GoodChunk { index, start, end }),
+            Ok(Some(c)) => problem(format!("Chunk coords are {}, {} instead of {}, {}",
+                c.x, c.z, expected.x, expected.z)),
+            Ok(None) => problem("Chunk coords are missing".to_string()),
+            Err(err) => problem(format!("Can't read NBT data: {}", err)),
+        }
+    }
+
+    RegionReport { chunk_count, problems, good_chunks }
+}
+
+// Write a copy of a region file with only the chunks that passed every check, packed together
+// after the header, along with any external chunk files they use.
+fn write_repaired_region(file: &RegionFile, bytes: &[u8], good_chunks: &[GoodChunk],
+    outdir: &Path)
+-> Result<PathBuf, Box<dyn Error>> {
+    let mut repaired = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+    for chunk in good_chunks {
+        let offset = repaired.len() / SECTOR_SIZE;
+        let sectors = (chunk.end - chunk.start).div_ceil(SECTOR_SIZE);
+        BigEndian::write_u32(&mut repaired[(chunk.index * 4)..],
+            (offset << 8 | sectors) as u32);
+        let to = TIMESTAMPS_OFFSET + chunk.index * 4;
+        repaired[to..(to + 4)].copy_from_slice(&bytes[to..(to + 4)]);
+
+        repaired.extend_from_slice(&bytes[chunk.start..chunk.end]);
+        repaired.resize((offset + sectors) * SECTOR_SIZE, 0);
+
+        if compression::is_external(bytes[chunk.start + 4]) {
+            let cx = chunk.index % CHUNKS_IN_REGION;
+            let cz = chunk.index / CHUNKS_IN_REGION;
+            let mccpath = region::get_external_chunk_path(&file.path, cx, cz)?;
+            std::fs::copy(&mccpath, outdir.join(mccpath.file_name().unwrap()))?;
+        }
+    }
+
+    let outpath = outdir.join(file.path.file_name().unwrap());
+    std::fs::write(&outpath, repaired)?;
+    Ok(outpath)
+}
+
+// Make sure repaired copies won't be written over the region files they're copied from.
+fn check_repair_dir(region_files: &[RegionFile], repair_path: &Path)
+-> Result<(), Box<dyn Error>> {
+    let repair_path = repair_path.canonicalize()?;
+    for file in region_files {
+        if file.path.canonicalize()?.parent() == Some(repair_path.as_path()) {
+            return Err(Box::new(std::io::Error::new(ErrorKind::InvalidInput,
+                "The repair directory can't be the directory being checked")));
+        }
+    }
+    Ok(())
+}
+
+pub fn check_regions(path: &Path, repair_path: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let region_files = get_region_files(path)?;
+    if let Some(repair_path) = repair_path {
+        std::fs::create_dir_all(repair_path)?;
+        check_repair_dir(&region_files, repair_path)?;
+    }
+
+    let bar = ProgressBar::with_draw_target(region_files.len() as u64,
+        ProgressDrawTarget::stdout_nohz())
+        .with_style(ProgressStyle::default_bar()
+            .template("{wide_bar}\n{msg} ({pos}/{len})")
+            .progress_chars("▪■ "));
+
+    let mut chunk_count = 0;
+    let mut problem_count = 0;
+    let mut messages = vec![];
+    for file in &region_files {
+        let filename = file.path.file_name().unwrap().to_string_lossy();
+        let msg = format!("Checking region {}, {}", file.coords.x, file.coords.z);
+        bar.set_message(&msg);
+        bar.inc(1);
+
+        let bytes = std::fs::read(&file.path)?;
+        let report = check_region(file, &bytes);
+        chunk_count += report.chunk_count;
+        if report.problems.is_empty() {
+            continue;
+        }
+
+        problem_count += report.problems.len();
+        for problem in &report.problems {
+            messages.push(format!("{}: {}", filename, problem));
+        }
+
+        if let Some(repair_path) = repair_path {
+            let outpath = write_repaired_region(file, &bytes, &report.good_chunks,
+                repair_path)?;
+            messages.push(format!("{}: Wrote a repaired copy with {} of {} chunks to {}",
+                filename, report.good_chunks.len(), report.chunk_count, outpath.display()));
+        }
+    }
+
+    bar.finish_and_clear();
+
+    for msg in messages {
+        println!("{}", msg);
+    }
+    println!("Checked {} chunk{} in {} region file{}; found {} problem{}.",
+        chunk_count, if chunk_count == 1 { "" } else { "s" },
+        region_files.len(), if region_files.len() == 1 { "" } else { "s" },
+        problem_count, if problem_count == 1 { "" } else { "s" });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::testing::{compound, to_bytes};
+    use crate::nbt::Tag;
+
+    fn region_file() -> RegionFile {
+        RegionFile { path: PathBuf::from("r.0.0.mca"), coords: Pair { x: 0, z: 0 } }
+    }
+
+    // Write a chunk into a region file's bytes at the given sector, with its header entry.
+    fn write_chunk(bytes: &mut Vec<u8>, index: usize, sector: usize, data: &[u8]) {
+        BigEndian::write_u32(&mut bytes[(index * 4)..], (sector << 8 | 1) as u32);
+        let start = sector * SECTOR_SIZE;
+        bytes.resize(start + SECTOR_SIZE, 0);
+        BigEndian::write_u32(&mut bytes[start..], data.len() as u32 + 1);
+        bytes[start + 4] = compression::ZLIB;
+        bytes[(start + CHUNK_HEADER_SIZE)..(start + CHUNK_HEADER_SIZE + data.len())]
+            .copy_from_slice(data);
+    }
+
+    fn chunk_data(x: i32, z: i32) -> Vec<u8> {
+        let tag = compound(vec![("xPos", Tag::Int(x)), ("zPos", Tag::Int(z))]);
+        compression::compress(compression::ZLIB, &to_bytes(&tag)).unwrap()
+    }
+
+    #[test]
+    fn truncated_files() {
+        let mut bytes = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+        write_chunk(&mut bytes, 0, 2, &chunk_data(0, 0));
+        write_chunk(&mut bytes, 1, 3, &[0; 1000]);
+        let report = check_region(&region_file(), &bytes);
+        assert_eq!(report.good_chunks.len(), 1);
+        assert_eq!(report.problems.len(), 1);
+
+        // The second chunk's data is cut off.
+        bytes.truncate(3 * SECTOR_SIZE + 100);
+        let report = check_region(&region_file(), &bytes);
+        assert_eq!(report.chunk_count, 2);
+        assert_eq!(report.good_chunks.len(), 1);
+        assert_eq!(report.problems, [
+            "File length 12388 is not a whole number of sectors",
+            "Chunk 1, 0: Length 1001 runs past the end of the file",
+        ]);
+
+        // Only part of the second chunk's length is left.
+        bytes.truncate(3 * SECTOR_SIZE + 2);
+        let report = check_region(&region_file(), &bytes);
+        assert_eq!(report.good_chunks.len(), 1);
+        assert_eq!(report.problems[1], "Chunk 1, 0: Chunk header is past the end of the file");
+    }
+
+    #[test]
+    fn overlapping_sectors() {
+        let mut bytes = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+        write_chunk(&mut bytes, 0, 2, &chunk_data(0, 0));
+        write_chunk(&mut bytes, 1, 3, &chunk_data(1, 0));
+        // Chunk 2, 0 covers both of the other chunks' sectors, and starts with chunk 0, 0's data.
+        BigEndian::write_u32(&mut bytes[8..], 2 << 8 | 2);
+        let report = check_region(&region_file(), &bytes);
+        assert_eq!(report.chunk_count, 3);
+        assert_eq!(report.good_chunks.iter().map(|chunk| chunk.index).collect::<Vec<usize>>(),
+            [0, 1]);
+        assert_eq!(report.problems, [
+            "Chunk 2, 0: Sectors overlap chunk 0, 0",
+            "Chunk 2, 0: Sectors overlap chunk 1, 0",
+            "Chunk 2, 0: Chunk coords are 0, 0 instead of 2, 0",
+        ]);
+    }
+
+    #[test]
+    fn misplaced_chunk() {
+        let file = RegionFile { path: PathBuf::from("r.-1.2.mca"), coords: Pair { x: -1, z: 2 } };
+        let mut bytes = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+        write_chunk(&mut bytes, 33, 2, &chunk_data(-31, 65));
+        write_chunk(&mut bytes, 34, 3, &chunk_data(-31, 65));
+        let report = check_region(&file, &bytes);
+        assert_eq!(report.good_chunks.len(), 1);
+        assert_eq!(report.problems, ["Chunk 2, 1: Chunk coords are -31, 65 instead of -30, 65"]);
+    }
+
+    #[test]
+    fn repaired_file() {
+        let dir = std::env::temp_dir().join(format!("rmapbash-check-{}", std::process::id()));
+        let outdir = dir.join("repaired");
+        std::fs::create_dir_all(&outdir).unwrap();
+        let files = [RegionFile { path: dir.join("r.0.0.mca"), coords: Pair { x: 0, z: 0 } }];
+        let file = &files[0];
+
+        let mut bytes = vec![0u8; HEADER_SECTORS * SECTOR_SIZE];
+        write_chunk(&mut bytes, 0, 2, &chunk_data(0, 0));
+        write_chunk(&mut bytes, 1, 3, &[0; 100]);
+        write_chunk(&mut bytes, 2, 4, &chunk_data(2, 0));
+        BigEndian::write_u32(&mut bytes[(TIMESTAMPS_OFFSET + 8)..], 1234);
+        std::fs::write(&file.path, &bytes).unwrap();
+
+        // The repaired copy can't replace the original.
+        assert!(check_repair_dir(&files, &outdir.join("..")).is_err());
+        check_repair_dir(&files, &outdir).unwrap();
+
+        let report = check_region(file, &bytes);
+        assert_eq!(report.problems.len(), 1);
+        let outpath = write_repaired_region(file, &bytes, &report.good_chunks, &outdir)
+            .unwrap();
+        assert_eq!(outpath, outdir.join("r.0.0.mca"));
+
+        // The good chunks are packed together after the header, and keep their timestamps.
+        let repaired = std::fs::read(&outpath).unwrap();
+        assert_eq!(repaired.len(), 4 * SECTOR_SIZE);
+        assert_eq!(BigEndian::read_u32(&repaired[0..]), 2 << 8 | 1);
+        assert_eq!(BigEndian::read_u32(&repaired[4..]), 0);
+        assert_eq!(BigEndian::read_u32(&repaired[8..]), 3 << 8 | 1);
+        assert_eq!(BigEndian::read_u32(&repaired[(TIMESTAMPS_OFFSET + 8)..]), 1234);
+        assert_eq!(repaired[(3 * SECTOR_SIZE)..(4 * SECTOR_SIZE)],
+            bytes[(4 * SECTOR_SIZE)..(5 * SECTOR_SIZE)]);
+
+        let report = check_region(file, &repaired);
+        assert_eq!(report.chunk_count, 2);
+        assert!(report.problems.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
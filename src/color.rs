@@ -148,6 +148,18 @@ pub fn blend_alpha_color(top: &RGBA, bottom: &RGBA) -> RGBA {
     }
 }
 
+// Mix a color towards a tint by the tint's alpha, keeping the color's own alpha.
+pub fn tint_color(color: &RGBA, tint: &RGBA) -> RGBA {
+    let amt = tint.a as u16;
+    let mix = |c: u8, t: u8| ((c as u16 * (255 - amt) + t as u16 * amt) / 255) as u8;
+    RGBA {
+        r: mix(color.r, tint.r),
+        g: mix(color.g, tint.g),
+        b: mix(color.b, tint.b),
+        a: color.a,
+    }
+}
+
 pub fn set_light_color(blockcolor: &RGBA, lightcolor: &RGB) -> RGBA {
     RGBA {
         r: (blockcolor.r as f64 * lightcolor.r as f64 / 255.0) as u8,
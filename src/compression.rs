@@ -0,0 +1,153 @@
+use std::io::{prelude::*, Cursor, Error, ErrorKind};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use flate2::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+// Compression types used for chunks in region files.
+pub const GZIP: u8 = 1;
+pub const ZLIB: u8 = 2;
+pub const UNCOMPRESSED: u8 = 3;
+const LZ4: u8 = 4;
+
+// Set on the compression type when the chunk is stored in its own .mcc file.
+pub const EXTERNAL_FLAG: u8 = 0x80;
+
+// LZ4 chunks are written as a series of blocks, each with this header.
+const LZ4_MAGIC: &[u8; 8] = b"LZ4Block";
+const LZ4_METHOD_RAW: u8 = 0x10;
+const LZ4_METHOD_LZ4: u8 = 0x20;
+
+pub fn is_external(compression: u8) -> bool {
+    compression & EXTERNAL_FLAG != 0
+}
+
+pub fn decompress_chunk(compression: u8, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    match compression & !EXTERNAL_FLAG {
+        GZIP => { GzDecoder::new(data.as_slice()).read_to_end(&mut bytes)?; },
+        ZLIB => { ZlibDecoder::new(data.as_slice()).read_to_end(&mut bytes)?; },
+        UNCOMPRESSED => bytes = data,
+        LZ4 => bytes = decompress_lz4_blocks(&data)?,
+        c => return Err(Error::new(ErrorKind::InvalidData,
+            format!("Unknown compression type: {}", c))),
+    };
+    Ok(bytes)
+}
+
+// Compress data with one of the compression types used for chunks, except LZ4.
+// Gzip is also used for .dat files and structure files.
+pub fn compress(compression: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(match compression {
+        GZIP => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        ZLIB => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        UNCOMPRESSED => data.to_vec(),
+        c => return Err(Error::new(ErrorKind::InvalidInput,
+            format!("Can't compress with compression type: {}", c))),
+    })
+}
+
+// Read the block stream format of lz4-java's LZ4BlockOutputStream, which ends with an empty block.
+// Block checksums are not verified.
+fn decompress_lz4_blocks(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut reader = Cursor::new(data);
+    let mut bytes = Vec::new();
+
+    while (reader.position() as usize) < data.len() {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != LZ4_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Bad LZ4 block header"));
+        }
+        let method = reader.read_u8()? & 0xf0;
+        let compressed_len = reader.read_u32::<LittleEndian>()? as usize;
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        reader.read_u32::<LittleEndian>()?; // Skip checksum
+        if len == 0 {
+            break;
+        }
+
+        let mut block = vec![0u8; compressed_len];
+        reader.read_exact(&mut block)?;
+        match method {
+            LZ4_METHOD_RAW => bytes.extend_from_slice(&block),
+            LZ4_METHOD_LZ4 => bytes.extend_from_slice(&lz4_flex::block::decompress(&block, len)
+                .map_err(|err| Error::new(ErrorKind::InvalidData,
+                    format!("Bad LZ4 block: {}", err)))?),
+            _ => return Err(Error::new(ErrorKind::InvalidData,
+                format!("Unknown LZ4 block method: {}", method))),
+        };
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use super::*;
+
+    fn write_lz4_block(stream: &mut Vec<u8>, method: u8, block: &[u8], len: usize) {
+        stream.extend_from_slice(LZ4_MAGIC);
+        // The low bits of the method byte hold the compression level.
+        stream.push(method | 0x06);
+        stream.write_u32::<LittleEndian>(block.len() as u32).unwrap();
+        stream.write_u32::<LittleEndian>(len as u32).unwrap();
+        stream.write_u32::<LittleEndian>(0).unwrap();
+        stream.extend_from_slice(block);
+    }
+
+    fn lz4_stream(data: &[u8]) -> Vec<u8> {
+        let (first, second) = data.split_at(data.len() / 2);
+        let mut stream = Vec::new();
+        write_lz4_block(&mut stream, LZ4_METHOD_LZ4, &lz4_flex::block::compress(first),
+            first.len());
+        write_lz4_block(&mut stream, LZ4_METHOD_RAW, second, second.len());
+        write_lz4_block(&mut stream, LZ4_METHOD_RAW, &[], 0);
+        stream
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        let data: Vec<u8> = b"minecraft:stone".iter().cycle().take(5000).copied().collect();
+        let stream = lz4_stream(&data);
+        assert!(stream.len() < data.len());
+        assert_eq!(decompress_chunk(LZ4, stream.clone()).unwrap(), data);
+        assert_eq!(decompress_chunk(LZ4 | EXTERNAL_FLAG, stream).unwrap(), data);
+    }
+
+    #[test]
+    fn lz4_truncated() {
+        let data: Vec<u8> = (0..2000).map(|i| (i * 7 % 251) as u8).collect();
+        let stream = lz4_stream(&data);
+        for len in [4, 20, 40, stream.len() - 30] {
+            assert!(decompress_chunk(LZ4, stream[..len].to_vec()).is_err(), "{} bytes", len);
+        }
+
+        let mut stream = stream;
+        stream[0] = b'X';
+        assert!(decompress_chunk(LZ4, stream).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = b"Level data".to_vec();
+        for compression in [GZIP, ZLIB, UNCOMPRESSED] {
+            let compressed = compress(compression, &data).unwrap();
+            assert_eq!(decompress_chunk(compression, compressed).unwrap(), data);
+        }
+        assert!(compress(LZ4, &data).is_err());
+        assert!(decompress_chunk(5, data).is_err());
+    }
+}
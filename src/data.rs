@@ -1,25 +1,36 @@
-use std::fs::File;
-use std::io::{Error, ErrorKind};
+use std::io::{Cursor, Error, ErrorKind};
 use std::path::Path;
 use std::result::Result;
 
-use flate2::read::GzDecoder;
-
 use nbt::Blob;
 
+use super::compression;
+use super::nbt::{read_tag_header, read_tag_payload, write_nbt_file};
+use super::snbt;
+
+// Read an NBT file, such as a .dat or structure file, which may or may not be gzipped.
+pub fn read_nbt_file(path: &Path) -> Result<Cursor<Vec<u8>>, Error> {
+    let mut bytes = std::fs::read(path)?;
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        bytes = compression::decompress_chunk(compression::GZIP, bytes)?;
+    }
+    Ok(Cursor::new(bytes))
+}
+
 pub fn read_dat_file(path: &Path) -> Result<(), Error> {
-    let file = File::open(path)?;
-    let mut reader = GzDecoder::new(file);
+    let bytes = read_nbt_file(path)?.into_inner();
 
-    println!("================================= NBT Contents =================================");
-    let blob = match Blob::from_reader(&mut reader) {
+    println!("================================= SNBT Contents ================================");
+    let mut reader = Cursor::new(&bytes);
+    let (id, _) = read_tag_header(&mut reader)?;
+    println!("{}", snbt::format_tag(&read_tag_payload(&mut reader, &id)?));
+
+    println!("============================== JSON Representation =============================");
+    let blob = match Blob::from_reader(&mut Cursor::new(&bytes)) {
         Ok(blob) => blob,
         Err(err) => return Err(Error::new(ErrorKind::InvalidData,
             format!("Error reading NBT: {}", err))),
     };
-    println!("{}", blob);
-
-    println!("============================== JSON Representation =============================");
     let json = match serde_json::to_string_pretty(&blob) {
         Ok(json) => json,
         Err(err) => return Err(Error::new(ErrorKind::InvalidData,
@@ -29,3 +40,16 @@ pub fn read_dat_file(path: &Path) -> Result<(), Error> {
 
     Ok(())
 }
+
+// Convert an SNBT file, such as an edited dump of a .dat file, back into a gzipped .dat file
+// next to it.
+pub fn write_dat_file(path: &Path) -> Result<(), Error> {
+    let snbt = std::fs::read_to_string(path)?;
+    let tag = snbt::parse_tag(snbt.trim())?;
+
+    let outpath = path.with_extension("dat");
+    write_nbt_file(&outpath, &tag, compression::GZIP)?;
+    println!("Wrote {}", outpath.display());
+
+    Ok(())
+}
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::io::{ErrorKind, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess,
+    Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::nbt;
+
+// Wraps IO errors so that serde can add its own messages, like missing fields.
+#[derive(Debug)]
+struct Error(std::io::Error);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(std::io::Error::new(ErrorKind::InvalidData, msg.to_string()))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error(err)
+    }
+}
+
+// Reads a single tag's payload straight from the reader as it is deserialized, so that
+// compound entries that a struct doesn't have fields for are skipped without being read.
+struct Deserializer<'a, R> {
+    reader: &'a mut R,
+    id: u8,
+    depth: usize,
+}
+
+// The elements of a list or array, which all have the same tag ID.
+struct ListAccess<'a, R> {
+    reader: &'a mut R,
+    id: u8,
+    depth: usize,
+    remaining: usize,
+}
+
+struct CompoundAccess<'a, R> {
+    reader: &'a mut R,
+    id: u8,
+    depth: usize,
+    done: bool,
+}
+
+impl<'a, R: Read> Deserializer<'a, R> {
+    fn visit_list<'de, V: Visitor<'de>>(self, id: u8, visitor: V) -> Result<V::Value, Error> {
+        let remaining = self.reader.read_u32::<BigEndian>()? as usize;
+        let depth = self.depth + 1;
+        let mut access = ListAccess { reader: self.reader, id, depth, remaining };
+        let value = visitor.visit_seq(&mut access)?;
+        // Skip any elements the visitor didn't need, such as the end of an overlong list.
+        for _ in 0..access.remaining {
+            nbt::skip_tag_payload_at(access.reader, &id, depth)?;
+        }
+        Ok(value)
+    }
+}
+
+impl<'de, 'a, R: Read> de::Deserializer<'de> for Deserializer<'a, R> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        nbt::check_depth(self.depth)?;
+        match self.id {
+            1 => visitor.visit_i8(self.reader.read_i8()?),
+            2 => visitor.visit_i16(self.reader.read_i16::<BigEndian>()?),
+            3 => visitor.visit_i32(self.reader.read_i32::<BigEndian>()?),
+            4 => visitor.visit_i64(self.reader.read_i64::<BigEndian>()?),
+            5 => visitor.visit_f32(self.reader.read_f32::<BigEndian>()?),
+            6 => visitor.visit_f64(self.reader.read_f64::<BigEndian>()?),
+            7 => self.visit_list(1, visitor),
+            8 => match nbt::read_tag_payload(self.reader, &8)? {
+                nbt::Tag::String(value) => visitor.visit_string(value),
+                _ => unreachable!(),
+            },
+            9 => {
+                let id = self.reader.read_u8()?;
+                self.visit_list(id, visitor)
+            },
+            10 => {
+                let mut access = CompoundAccess { reader: self.reader, id: 0,
+                    depth: self.depth + 1, done: false };
+                let value = visitor.visit_map(&mut access)?;
+                if !access.done {
+                    nbt::skip_tag_payload_at(access.reader, &10, self.depth)?;
+                }
+                Ok(value)
+            },
+            11 => self.visit_list(3, visitor),
+            12 => self.visit_list(4, visitor),
+            id => Err(de::Error::custom(format!("Invalid tag id: {}", id))),
+        }
+    }
+
+    // Bytes are often flags.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.id {
+            1 => visitor.visit_bool(self.reader.read_i8()? != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // Unsigned fields take the bits of a signed tag of the same size as they are,
+    // so that byte arrays and packed data can be read into unsigned types.
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.id {
+            1 => visitor.visit_u8(self.reader.read_u8()?),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.id {
+            2 => visitor.visit_u16(self.reader.read_u16::<BigEndian>()?),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.id {
+            3 => visitor.visit_u32(self.reader.read_u32::<BigEndian>()?),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.id {
+            4 => visitor.visit_u64(self.reader.read_u64::<BigEndian>()?),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // NBT has no null values, so any tag that is present is Some.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+    -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        nbt::skip_tag_payload_at(self.reader, &self.id, self.depth)?;
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u128 f32 f64 char str string bytes byte_buf unit unit_struct seq
+        tuple tuple_struct map struct enum identifier
+    }
+}
+
+impl<'de, 'a, R: Read> SeqAccess<'de> for ListAccess<'a, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+    -> Result<Option<T::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(Deserializer { reader: &mut *self.reader, id: self.id, depth: self.depth })
+            .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de, 'a, R: Read> MapAccess<'de> for CompoundAccess<'a, R> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+    -> Result<Option<K::Value>, Error> {
+        let (id, name) = nbt::read_tag_header(self.reader)?;
+        if id == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        self.id = id;
+        seed.deserialize(name.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(Deserializer { reader: &mut *self.reader, id: self.id, depth: self.depth })
+    }
+}
+
+// Deserialize a tag's payload from a reader, such as a chunk's root compound after its header.
+pub fn from_payload<R, T>(reader: &mut R, id: u8) -> Result<T, std::io::Error>
+where R: Read, T: DeserializeOwned {
+    T::deserialize(Deserializer { reader, id, depth: 0 }).map_err(|err| err.0)
+}
+
+// Deserialize the root tag of an NBT file or chunk, starting from its header.
+pub fn from_reader<R, T>(reader: &mut R) -> Result<T, std::io::Error>
+where R: Read, T: DeserializeOwned {
+    let (id, _) = nbt::read_tag_header(reader)?;
+    from_payload(reader, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::nbt::Tag;
+    use crate::nbt::testing::{compound, nested_lists, to_bytes};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Section {
+        #[serde(rename = "Y")]
+        y: i8,
+        #[serde(rename = "BlockLight")]
+        block_light: Option<Vec<u8>>,
+        #[serde(rename = "BlockStates", default)]
+        block_states: Vec<i64>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Chunk {
+        #[serde(rename = "xPos")]
+        x: i32,
+        #[serde(rename = "zPos")]
+        z: i32,
+        #[serde(rename = "isLightOn")]
+        light_on: bool,
+        sections: Vec<Section>,
+        #[serde(rename = "Heightmaps")]
+        heightmaps: HashMap<String, Vec<u64>>,
+    }
+
+    #[test]
+    fn deserialize_struct() {
+        let tag = compound(vec![
+            ("xPos", Tag::Int(-2)),
+            ("zPos", Tag::Int(7)),
+            ("isLightOn", Tag::Byte(1)),
+            ("Status", Tag::String("full".to_string())),
+            ("Entities", Tag::List(vec![compound(vec![("id", Tag::String("pig".to_string()))])])),
+            ("sections", Tag::List(vec![
+                compound(vec![("Y", Tag::Byte(-4)), ("BlockLight", Tag::ByteArray(vec![255, 1]))]),
+                compound(vec![("Y", Tag::Byte(0)), ("BlockStates", Tag::LongArray(vec![-1, 2]))]),
+            ])),
+            ("Heightmaps", compound(vec![("WORLD_SURFACE", Tag::LongArray(vec![-1]))])),
+        ]);
+        let chunk: Chunk = from_reader(&mut Cursor::new(to_bytes(&tag))).unwrap();
+        assert_eq!(chunk, Chunk {
+            x: -2,
+            z: 7,
+            light_on: true,
+            sections: vec![
+                Section { y: -4, block_light: Some(vec![255, 1]), block_states: vec![] },
+                Section { y: 0, block_light: None, block_states: vec![-1, 2] },
+            ],
+            heightmaps: vec![("WORLD_SURFACE".to_string(), vec![u64::MAX])].into_iter().collect(),
+        });
+    }
+
+    #[test]
+    fn skip_unused_tags() {
+        #[derive(Deserialize)]
+        struct Pos {
+            #[serde(rename = "Pos")]
+            pos: (f64, f64),
+        }
+
+        let tag = compound(vec![
+            ("Pos", Tag::List(vec![Tag::Double(1.5), Tag::Double(64.0), Tag::Double(-3.0)])),
+            ("Inventory", Tag::List(vec![compound(vec![("Count", Tag::Byte(3))])])),
+        ]);
+        let mut bytes = to_bytes(&tag);
+        bytes.extend(to_bytes(&Tag::Int(12345)));
+
+        let mut reader = Cursor::new(bytes);
+        let pos: Pos = from_reader(&mut reader).unwrap();
+        assert_eq!(pos.pos, (1.5, 64.0));
+        assert_eq!(nbt::read_tag_header(&mut reader).unwrap(), (3, "".to_string()));
+        assert_eq!(nbt::read_tag_payload(&mut reader, &3).unwrap(), Tag::Int(12345));
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        let tag = compound(vec![("xPos", Tag::String("zero".to_string()))]);
+        let result: Result<Chunk, _> = from_reader(&mut Cursor::new(to_bytes(&tag)));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let tag = compound(vec![("zPos", Tag::Int(0))]);
+        let result: Result<Chunk, _> = from_reader(&mut Cursor::new(to_bytes(&tag)));
+        assert!(result.unwrap_err().to_string().contains("xPos"));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // Nested lists are read as far as the limit, whether they're deserialized or skipped.
+        let value: Result<serde_json::Value, _> =
+            from_payload(&mut Cursor::new(nested_lists(nbt::MAX_DEPTH)), 9);
+        assert!(value.is_ok());
+        let value: Result<serde_json::Value, _> =
+            from_payload(&mut Cursor::new(nested_lists(nbt::MAX_DEPTH + 1)), 9);
+        assert_eq!(value.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // A compound with the lists in an entry that isn't a field, one level below the root.
+        #[derive(Debug, Deserialize)]
+        struct Unused {}
+        let entry = |depth| [&[9, 0, 1, b'a'][..], &nested_lists(depth), &[0]].concat();
+        assert!(from_payload::<_, Unused>(&mut Cursor::new(entry(nbt::MAX_DEPTH - 1)), 10)
+            .is_ok());
+        let err = from_payload::<_, Unused>(&mut Cursor::new(entry(nbt::MAX_DEPTH)), 10)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,350 @@
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use super::bedrock;
+use super::options::Lighting;
+use super::sizes::*;
+use super::structure;
+
+pub const OVERWORLD: &str = "minecraft:overworld";
+pub const NETHER: &str = "minecraft:the_nether";
+pub const END: &str = "minecraft:the_end";
+
+// Before 1.16, dimensions were numbered instead of named.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum DimensionId {
+    Id(i32),
+    Name(String),
+}
+
+impl DimensionId {
+    pub fn name(&self) -> &str {
+        match self {
+            DimensionId::Id(-1) => NETHER,
+            DimensionId::Id(1) => END,
+            DimensionId::Id(_) => OVERWORLD,
+            DimensionId::Name(name) => name,
+        }
+    }
+}
+
+// The parts of a dimension type that decide how a dimension is drawn.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct DimensionType {
+    min_y: isize,
+    height: usize,
+    has_skylight: bool,
+    has_ceiling: bool,
+}
+
+// A datapack dimension refers to its type by name, or defines it inline.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TypeRef {
+    Name(String),
+    Inline(DimensionType),
+}
+
+#[derive(Deserialize)]
+struct DimensionJson {
+    #[serde(rename = "type")]
+    dimtype: TypeRef,
+}
+
+pub struct Dimension {
+    pub name: String,
+    // The save directory, with level.dat, player data and datapacks.
+    pub savepath: PathBuf,
+    // The directory with the dimension's region subdirectory.
+    pub path: PathBuf,
+    pub dimtype: DimensionType,
+}
+
+impl Dimension {
+    // Dimensions without skylight are lit like the Nether if they have a ceiling, or like the
+    // End if they don't.
+    pub fn get_lighting(&self, night: bool) -> Lighting {
+        match self.dimtype {
+            DimensionType { has_skylight: true, .. } =>
+                if night { Lighting::Night } else { Lighting::Day },
+            DimensionType { has_ceiling: true, .. } => Lighting::Nether,
+            _ => Lighting::End,
+        }
+    }
+
+    // The dimension's height range, as offsets within a chunk's blocks. Datapacks can make
+    // dimensions taller than chunks are read, so the range is clamped, with a warning.
+    pub fn get_ylimits(&self) -> Range<usize> {
+        let (ylimits, warning) = self.clamp_ylimits();
+        if let Some(warning) = warning {
+            println!("{}", warning);
+        }
+        ylimits
+    }
+
+    fn clamp_ylimits(&self) -> (Range<usize>, Option<String>) {
+        let (dim_min_y, dim_max_y) =
+            (self.dimtype.min_y, self.dimtype.min_y + self.dimtype.height as isize - 1);
+        let min_y = dim_min_y.max(MIN_BLOCK_Y);
+        let max_y = dim_max_y.min(MAX_BLOCK_Y);
+        if max_y < min_y {
+            return (0..BLOCKS_IN_CHUNK_Y, Some(format!(
+                "Dimension {} goes from Y={} to {}, outside the Y={} to {} that can be drawn; \
+                drawing that instead", self.name, dim_min_y, dim_max_y, MIN_BLOCK_Y, MAX_BLOCK_Y)));
+        }
+        let warning = if (min_y, max_y) != (dim_min_y, dim_max_y) {
+            Some(format!("Dimension {} goes from Y={} to {}, but only Y={} to {} can be drawn",
+                self.name, dim_min_y, dim_max_y, min_y, max_y))
+        } else {
+            None
+        };
+        (block_y_to_offset(min_y)..(block_y_to_offset(max_y) + 1), warning)
+    }
+}
+
+fn dimension_type(min_y: isize, height: usize, has_skylight: bool, has_ceiling: bool)
+-> DimensionType {
+    DimensionType { min_y, height, has_skylight, has_ceiling }
+}
+
+fn get_builtin_type(name: &str) -> Option<DimensionType> {
+    match name {
+        "minecraft:overworld" => Some(dimension_type(-64, 384, true, false)),
+        "minecraft:overworld_caves" => Some(dimension_type(-64, 384, true, true)),
+        "minecraft:the_nether" => Some(dimension_type(0, 256, false, true)),
+        "minecraft:the_end" => Some(dimension_type(0, 256, false, false)),
+        _ => None,
+    }
+}
+
+// Bedrock's Nether is only 128 blocks tall.
+fn get_bedrock_type(name: &str) -> Option<DimensionType> {
+    match name {
+        NETHER => Some(dimension_type(0, 128, false, true)),
+        _ => get_builtin_type(name),
+    }
+}
+
+// Add the default namespace to an ID that doesn't have one.
+fn qualify(name: &str) -> String {
+    if name.contains(':') { name.to_string() } else { format!("minecraft:{}", name) }
+}
+
+// Accept a dimension as e.g. `the_nether` or `minecraft:the_nether`, or as the path of a
+// datapack dimension's directory in the save directory, e.g. `dimensions/mypack/mydim`.
+fn get_dimension_name(name: &str) -> Result<String, Error> {
+    let path = name.trim_start_matches("dimensions/").trim_end_matches('/');
+    let name = match path.split_once('/') {
+        Some((namespace, path)) if !namespace.contains(':') => format!("{}:{}", namespace, path),
+        _ => qualify(path),
+    };
+    match name.split_once(':') {
+        Some((namespace, path)) if !namespace.is_empty() && !path.is_empty() &&
+            !path.contains(':') => Ok(name),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid dimension name: {}", name))),
+    }
+}
+
+fn get_dimension_path(savepath: &Path, name: &str) -> PathBuf {
+    match name {
+        OVERWORLD => savepath.to_path_buf(),
+        NETHER => savepath.join("DIM-1"),
+        END => savepath.join("DIM1"),
+        _ => {
+            let (namespace, path) = name.split_once(':').unwrap();
+            savepath.join("dimensions").join(namespace).join(path)
+        },
+    }
+}
+
+// Find a file in the save's datapacks, checking them in name order. Zipped datapacks aren't
+// read.
+fn find_datapack_file(savepath: &Path, kind: &str, name: &str) -> Option<PathBuf> {
+    let (namespace, path) = name.split_once(':')?;
+    let mut packs: Vec<PathBuf> = std::fs::read_dir(savepath.join("datapacks")).ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    packs.sort();
+    packs.into_iter()
+        .map(|pack| pack.join("data").join(namespace).join(kind).join(format!("{}.json", path)))
+        .find(|path| path.is_file())
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(|err|
+        Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+}
+
+// Look up a dimension's type in the save's datapacks, then among the vanilla types.
+fn get_dimension_type(savepath: &Path, name: &str) -> Result<Option<DimensionType>, Error> {
+    let typeref = match find_datapack_file(savepath, "dimension", name) {
+        Some(path) => read_json::<DimensionJson>(&path)?.dimtype,
+        None => TypeRef::Name(name.to_string()),
+    };
+    match typeref {
+        TypeRef::Inline(dimtype) => Ok(Some(dimtype)),
+        TypeRef::Name(typename) => {
+            let typename = qualify(&typename);
+            match find_datapack_file(savepath, "dimension_type", &typename) {
+                Some(path) => read_json(&path).map(Some),
+                None => Ok(get_builtin_type(&typename)),
+            }
+        },
+    }
+}
+
+// Find the save directory and dimension for a path that points into a dimension's directory:
+// a DIM-1 or DIM1 subdir, or a datapack dimension under the dimensions subdir.
+fn get_path_dimension(inpath: &Path) -> (PathBuf, String) {
+    let parent = inpath.parent().unwrap_or(inpath).to_path_buf();
+    match inpath.file_name().and_then(|name| name.to_str()) {
+        Some("DIM-1") => return (parent, NETHER.to_string()),
+        Some("DIM1") => return (parent, END.to_string()),
+        _ => {},
+    }
+    let mut ancestors = inpath.ancestors().skip(1);
+    if let (Some(nspath), Some(dimspath), Some(savepath)) =
+        (ancestors.next(), ancestors.next(), ancestors.next()) {
+        if dimspath.file_name().is_some_and(|name| name == "dimensions") {
+            if let (Some(namespace), Some(name)) = (nspath.file_name(), inpath.file_name()) {
+                return (savepath.to_path_buf(),
+                    format!("{}:{}", namespace.to_string_lossy(), name.to_string_lossy()));
+            }
+        }
+    }
+    (inpath.to_path_buf(), OVERWORLD.to_string())
+}
+
+// Work out which dimension to draw, from the name passed or else from the input path.
+pub fn get_dimension(inpath: &Path, name: Option<&str>) -> Result<Dimension, Error> {
+    if structure::is_structure_path(inpath) {
+        if name.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Structure files have no dimensions."));
+        }
+        return Ok(Dimension {
+            name: OVERWORLD.to_string(),
+            savepath: inpath.to_path_buf(),
+            path: inpath.to_path_buf(),
+            dimtype: get_builtin_type(OVERWORLD).unwrap(),
+        });
+    }
+
+    if inpath.join("db").is_dir() {
+        let name = get_dimension_name(name.unwrap_or(OVERWORLD))?;
+        let dimtype = get_bedrock_type(&name).filter(|_| bedrock::get_dimension_id(&name).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound,
+                format!("Bedrock worlds have no {} dimension.", name)))?;
+        return Ok(Dimension {
+            name,
+            savepath: inpath.to_path_buf(),
+            path: inpath.to_path_buf(),
+            dimtype,
+        });
+    }
+
+    let (savepath, name, path) = match name {
+        Some(name) => {
+            let name = get_dimension_name(name)?;
+            let path = get_dimension_path(inpath, &name);
+            if !path.is_dir() {
+                return Err(Error::new(ErrorKind::NotFound,
+                    format!("No directory found for dimension {}.", name)));
+            }
+            (inpath.to_path_buf(), name, path)
+        },
+        None => {
+            let (savepath, name) = get_path_dimension(inpath);
+            (savepath, name, inpath.to_path_buf())
+        },
+    };
+    let dimtype = match get_dimension_type(&savepath, &name)? {
+        Some(dimtype) => dimtype,
+        None => {
+            println!("No type found for dimension {}; drawing it like the overworld", name);
+            get_builtin_type(OVERWORLD).unwrap()
+        },
+    };
+    Ok(Dimension { name, savepath, path, dimtype })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dimension(dimtype: DimensionType) -> Dimension {
+        Dimension {
+            name: OVERWORLD.to_string(),
+            savepath: PathBuf::new(),
+            path: PathBuf::new(),
+            dimtype,
+        }
+    }
+
+    #[test]
+    fn dimension_names() {
+        assert_eq!(get_dimension_name("the_nether").unwrap(), NETHER);
+        assert_eq!(get_dimension_name(END).unwrap(), END);
+        assert_eq!(get_dimension_name("dimensions/mypack/deep/dim/").unwrap(),
+            "mypack:deep/dim");
+        assert_eq!(get_dimension_name("mypack/dim").unwrap(), "mypack:dim");
+        assert!(get_dimension_name("mypack:").is_err());
+        assert!(get_dimension_name("a:b:c").is_err());
+
+        let savepath = Path::new("world");
+        assert_eq!(get_dimension_path(savepath, NETHER), Path::new("world/DIM-1"));
+        assert_eq!(get_dimension_path(savepath, "mypack:deep/dim"),
+            Path::new("world/dimensions/mypack/deep/dim"));
+        assert_eq!(get_path_dimension(Path::new("world/dimensions/mypack/dim")),
+            (PathBuf::from("world"), "mypack:dim".to_string()));
+        assert_eq!(get_path_dimension(Path::new("saves/world/DIM1")),
+            (PathBuf::from("saves/world"), END.to_string()));
+    }
+
+    #[test]
+    fn dimension_types() {
+        let json: DimensionJson = serde_json::from_str(r#"{
+            "type": {
+                "min_y": -128, "height": 512, "logical_height": 512, "has_skylight": false,
+                "has_ceiling": false, "ultrawarm": false, "ambient_light": 0.5
+            },
+            "generator": {"type": "minecraft:flat"}
+        }"#).unwrap();
+        let dimtype = match json.dimtype {
+            TypeRef::Inline(dimtype) => dimtype,
+            TypeRef::Name(_) => panic!("Expected an inline dimension type"),
+        };
+        assert_eq!(dimtype, dimension_type(-128, 512, false, false));
+        assert_eq!(dimension(dimtype).get_lighting(true), Lighting::End);
+
+        let nether = dimension(get_builtin_type(NETHER).unwrap());
+        assert_eq!(nether.get_ylimits(), 64..320);
+        assert_eq!(nether.get_lighting(false), Lighting::Nether);
+        let overworld = dimension(get_builtin_type(OVERWORLD).unwrap());
+        assert_eq!(overworld.get_lighting(true), Lighting::Night);
+    }
+
+    #[test]
+    fn clamped_heights() {
+        let datapack_dimension = |min_y, height| Dimension { name: "mypack:dim".to_string(),
+            ..dimension(dimension_type(min_y, height, true, false)) };
+
+        assert_eq!(datapack_dimension(-128, 512).clamp_ylimits(), (0..BLOCKS_IN_CHUNK_Y, Some(
+            "Dimension mypack:dim goes from Y=-128 to 383, but only Y=-64 to 319 can be drawn"
+            .to_string())));
+        assert_eq!(datapack_dimension(0, 400).clamp_ylimits(), (64..BLOCKS_IN_CHUNK_Y, Some(
+            "Dimension mypack:dim goes from Y=0 to 399, but only Y=0 to 319 can be drawn"
+            .to_string())));
+        assert_eq!(datapack_dimension(320, 16).clamp_ylimits(), (0..BLOCKS_IN_CHUNK_Y, Some(
+            "Dimension mypack:dim goes from Y=320 to 335, outside the Y=-64 to 319 that can be \
+            drawn; drawing that instead".to_string())));
+        assert_eq!(datapack_dimension(-64, 384).clamp_ylimits(), (0..BLOCKS_IN_CHUNK_Y, None));
+        assert_eq!(datapack_dimension(0, 128).clamp_ylimits(), (64..192, None));
+    }
+}
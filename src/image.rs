@@ -1,12 +1,14 @@
+use std::error::Error;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, ErrorKind};
 use std::path::Path;
 
-use png::{BitDepth, ColorType, Encoder, EncodingError};
+use png::{BitDepth, ColorType, Decoder, Encoder, EncodingError};
 
 use super::types::Pair;
 
-pub fn draw_block_map(pixels: &[u8], size: Pair<usize>, path: &Path, color: bool)
+pub fn draw_block_map(pixels: &[u8], size: Pair<usize>, path: &Path, color: bool,
+    title: Option<&str>)
 -> Result<(), EncodingError> {
     let len = size.x * size.z;
     println!("Saving map of size {}x{} ({} bytes)", size.x, size.z, len);
@@ -18,9 +20,29 @@ pub fn draw_block_map(pixels: &[u8], size: Pair<usize>, path: &Path, color: bool
     encoder.set_depth(BitDepth::Eight);
 
     let mut writer = encoder.write_header()?;
+    if let Some(title) = title {
+        // An international text chunk, which holds UTF-8 text after its keyword and three
+        // empty fields for compression and language.
+        let mut data = b"Title\0\0\0\0\0".to_vec();
+        data.extend_from_slice(title.as_bytes());
+        writer.write_chunk(*b"iTXt", &data)?;
+    }
     writer.write_image_data(&pixels)?;
 
     println!("Saved map to {}", path.display());
 
     Ok(())
 }
+
+// Read back the pixels of a color map saved by draw_block_map, if it is the expected size.
+pub fn read_block_map(path: &Path, size: &Pair<usize>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (info, mut reader) = Decoder::new(File::open(path)?).read_info()?;
+    if info.width as usize != size.x || info.height as usize != size.z ||
+        info.color_type != ColorType::RGBA || info.bit_depth != BitDepth::Eight {
+        return Err(Box::new(std::io::Error::new(ErrorKind::InvalidData,
+            format!("{} isn't a {}x{} color map", path.display(), size.x, size.z))));
+    }
+    let mut pixels = vec![0u8; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    Ok(pixels)
+}
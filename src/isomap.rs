@@ -89,13 +89,14 @@ pub fn draw_chunk(pixels: &mut [u8], blocktypes: &[BlockType], water_blocktype:
                         } else {
                             bcolors[blocktype.shape[x][y]]
                         };
+                        let tcolor = chunk.tint.map(|tint| color::tint_color(color, &tint));
 
                         let pcolor = color::blend_alpha_color(&RGBA {
                             r: pixels[po],
                             g: pixels[po + 1],
                             b: pixels[po + 2],
                             a: pixels[po + 3],
-                        }, color);
+                        }, tcolor.as_ref().unwrap_or(color));
                         pixels[po] = pcolor.r;
                         pixels[po + 1] = pcolor.g;
                         pixels[po + 2] = pcolor.b;
@@ -116,10 +117,12 @@ fn get_block_colors<'a>(blocktypes: &'a [BlockType], blocktype: &'a BlockType, b
     // Get the block above this one.
     let tblock = nblocks[0].unwrap();
 
+    let colors = blocktype.get_colors(biome);
+
     // Get the base color of the block, using light values from the block above.
     // TODO: are there cases where it's preferable to use the block's own light values?
-    bcolors[1] = &blocktype.colors[biome][tblock.slight][tblock.blight][1];
-    bcolors[4] = &blocktype.colors[biome][tblock.slight][tblock.blight][4];
+    bcolors[1] = &colors[tblock.slight][tblock.blight][1];
+    bcolors[4] = &colors[tblock.slight][tblock.blight][4];
 
     // If the block is solid, use light values from neighboring blocks for side colors.
     // Otherwise use the block's own light values.
@@ -129,22 +132,22 @@ fn get_block_colors<'a>(blocktypes: &'a [BlockType], blocktype: &'a BlockType, b
         let lblocktype = &blocktypes[lblock.btype as usize];
         let lshade = if lblock.slight > 0 && !lblocktype.solid && !lblocktype.waterlogged
             { 2 } else { 1 };
-        bcolors[2] = &blocktype.colors[biome][lblock.slight][lblock.blight][lshade];
-        bcolors[5] = &blocktype.colors[biome][lblock.slight][lblock.blight][lshade + 3];
+        bcolors[2] = &colors[lblock.slight][lblock.blight][lshade];
+        bcolors[5] = &colors[lblock.slight][lblock.blight][lshade + 3];
 
         // Add a shadow if block to the right has skylight and is not solid or waterlogged.
         let rblock = nblocks[2].unwrap();
         let rblocktype = &blocktypes[rblock.btype as usize];
         let rshade = if rblock.slight > 0 && !rblocktype.solid && !rblocktype.waterlogged
             { 3 } else { 1 };
-        bcolors[3] = &blocktype.colors[biome][rblock.slight][rblock.blight][rshade];
-        bcolors[6] = &blocktype.colors[biome][rblock.slight][rblock.blight][rshade + 3];
+        bcolors[3] = &colors[rblock.slight][rblock.blight][rshade];
+        bcolors[6] = &colors[rblock.slight][rblock.blight][rshade + 3];
     } else {
-        bcolors[2] = &blocktype.colors[biome][block.slight][block.blight][2];
-        bcolors[5] = &blocktype.colors[biome][block.slight][block.blight][5];
+        bcolors[2] = &colors[block.slight][block.blight][2];
+        bcolors[5] = &colors[block.slight][block.blight][5];
 
-        bcolors[3] = &blocktype.colors[biome][block.slight][block.blight][3];
-        bcolors[6] = &blocktype.colors[biome][block.slight][block.blight][6];
+        bcolors[3] = &colors[block.slight][block.blight][3];
+        bcolors[6] = &colors[block.slight][block.blight][6];
     }
 
     bcolors
@@ -0,0 +1,143 @@
+use std::io::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::data;
+use super::deserializer;
+use super::region::RegionFormat;
+
+// Values of the level's storage version tag.
+const VERSION_MCREGION: i32 = 19132;
+const VERSION_ANVIL: i32 = 19133;
+
+const TICKS_IN_DAY: i64 = 24000;
+
+#[derive(Deserialize)]
+struct LevelDat {
+    #[serde(rename = "Data")]
+    data: LevelInfo,
+}
+
+#[derive(Deserialize)]
+struct GameVersion {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+// The parts of a Java Edition world's level.dat that are used when rendering it.
+#[derive(Deserialize)]
+pub struct LevelInfo {
+    #[serde(rename = "LevelName")]
+    pub name: Option<String>,
+    #[serde(rename = "DataVersion")]
+    pub data_version: Option<u32>,
+    #[serde(rename = "Version")]
+    game_version: Option<GameVersion>,
+    #[serde(rename = "version")]
+    storage_version: Option<i32>,
+    #[serde(rename = "SpawnX")]
+    spawn_x: Option<i32>,
+    #[serde(rename = "SpawnY")]
+    spawn_y: Option<i32>,
+    #[serde(rename = "SpawnZ")]
+    spawn_z: Option<i32>,
+    #[serde(rename = "BorderCenterX")]
+    border_x: Option<f64>,
+    #[serde(rename = "BorderCenterZ")]
+    border_z: Option<f64>,
+    #[serde(rename = "BorderSize")]
+    border_size: Option<f64>,
+    #[serde(rename = "Time")]
+    time: Option<i64>,
+}
+
+impl LevelInfo {
+    pub fn game_version(&self) -> Option<&str> {
+        self.game_version.as_ref().map(|version| version.name.as_str())
+    }
+
+    // Worlds converted from McRegion keep their old files, but are marked as Anvil.
+    pub fn region_format(&self) -> Option<RegionFormat> {
+        match self.storage_version {
+            Some(VERSION_MCREGION) => Some(RegionFormat::McRegion),
+            Some(VERSION_ANVIL) => Some(RegionFormat::Anvil),
+            _ => None,
+        }
+    }
+
+    // The spawn point's block X, Y and Z.
+    pub fn spawn(&self) -> Option<(isize, isize, isize)> {
+        Some((self.spawn_x? as isize, self.spawn_y? as isize, self.spawn_z? as isize))
+    }
+
+    // The world border's center X and Z, and the length of its sides.
+    pub fn border(&self) -> Option<(f64, f64, f64)> {
+        Some((self.border_x.unwrap_or(0.0), self.border_z.unwrap_or(0.0), self.border_size?))
+    }
+
+    pub fn days(&self) -> Option<i64> {
+        self.time.map(|time| time / TICKS_IN_DAY)
+    }
+}
+
+// Read the level.dat file in a Java Edition save directory, if it has one.
+pub fn read_level_info(worldpath: &Path) -> Result<Option<LevelInfo>, Error> {
+    let path = worldpath.join("level.dat");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let level: LevelDat = deserializer::from_reader(&mut data::read_nbt_file(&path)?)?;
+    Ok(Some(level.data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::nbt::Tag;
+    use crate::nbt::testing::{compound, to_bytes};
+
+    fn read_level(data: Tag) -> LevelInfo {
+        let bytes = to_bytes(&compound(vec![("Data", data)]));
+        let level: LevelDat = deserializer::from_reader(&mut Cursor::new(bytes)).unwrap();
+        level.data
+    }
+
+    #[test]
+    fn read_level_info() {
+        let level = read_level(compound(vec![
+            ("LevelName", Tag::String("New World".to_string())),
+            ("DataVersion", Tag::Int(3465)),
+            ("Version", compound(vec![("Name", Tag::String("1.20.1".to_string()))])),
+            ("version", Tag::Int(19133)),
+            ("SpawnX", Tag::Int(-48)),
+            ("SpawnY", Tag::Int(72)),
+            ("SpawnZ", Tag::Int(160)),
+            ("BorderSize", Tag::Double(1000.0)),
+            ("Time", Tag::Long(50000)),
+            ("GameRules", compound(vec![("keepInventory", Tag::String("true".to_string()))])),
+        ]));
+        assert_eq!(level.name.as_deref(), Some("New World"));
+        assert_eq!(level.data_version, Some(3465));
+        assert_eq!(level.game_version(), Some("1.20.1"));
+        assert!(matches!(level.region_format(), Some(RegionFormat::Anvil)));
+        assert_eq!(level.spawn(), Some((-48, 72, 160)));
+        assert_eq!(level.border(), Some((0.0, 0.0, 1000.0)));
+        assert_eq!(level.days(), Some(2));
+    }
+
+    #[test]
+    fn read_old_level_info() {
+        let level = read_level(compound(vec![
+            ("version", Tag::Int(19132)),
+            ("SpawnX", Tag::Int(0)),
+            ("SpawnZ", Tag::Int(0)),
+        ]));
+        assert!(level.name.is_none());
+        assert!(matches!(level.region_format(), Some(RegionFormat::McRegion)));
+        assert_eq!(level.spawn(), None);
+        assert_eq!(level.border(), None);
+    }
+}
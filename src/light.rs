@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+use super::blocktypes::BlockType;
+use super::region::ChunkData;
+use super::sizes::*;
+
+// Compute skylight and blocklight for a chunk from its blocks, for chunks saved without light.
+// Skylight shines straight down from the top of the chunk, losing a level for each block that
+// filters light, such as water or leaves, and stopping at other opaque blocks. Both kinds of
+// light then spread out a level at a time through blocks that don't stop them. Light from
+// neighbouring chunks isn't included.
+pub fn compute_lights(chunk: &mut ChunkData, blocktypes: &[BlockType]) {
+    let mut slights = vec![0u8; BLOCKS_IN_CHUNK_3D];
+    let mut blights = vec![0u8; BLOCKS_IN_CHUNK_3D];
+
+    for bo2 in 0..BLOCKS_IN_CHUNK_2D {
+        let mut light = MAX_LIGHT_LEVEL;
+        for by in (0..BLOCKS_IN_CHUNK_Y).rev() {
+            let bo3 = by * BLOCKS_IN_CHUNK_2D + bo2;
+            let blocktype = &blocktypes[chunk.blocks[bo3] as usize];
+            if blocktype.filters_light {
+                light = light.saturating_sub(1);
+            } else if blocktype.opaque {
+                light = 0;
+            }
+            slights[bo3] = light;
+            blights[bo3] = blocktype.light;
+        }
+    }
+
+    spread_light(&mut slights, chunk, blocktypes);
+    spread_light(&mut blights, chunk, blocktypes);
+
+    for (bo3, light) in chunk.lights.iter_mut().enumerate() {
+        *light = (blights[bo3] << 4) | slights[bo3];
+    }
+}
+
+// Leaves are drawn as opaque, but let light through.
+fn stops_light(blocktype: &BlockType) -> bool {
+    blocktype.opaque && !blocktype.filters_light
+}
+
+// Spread light levels out to neighbouring blocks, handling the brightest blocks first
+// so that each block is only visited once it has reached its final level.
+fn spread_light(lights: &mut [u8], chunk: &ChunkData, blocktypes: &[BlockType]) {
+    let mut queues: Vec<VecDeque<usize>> = vec![VecDeque::new(); LIGHT_LEVELS];
+    for (bo3, light) in lights.iter().enumerate() {
+        if *light > 1 {
+            queues[*light as usize].push_back(bo3);
+        }
+    }
+
+    for level in (2..LIGHT_LEVELS).rev() {
+        while let Some(bo3) = queues[level].pop_front() {
+            if lights[bo3] as usize != level {
+                continue;
+            }
+
+            let bx = bo3 % BLOCKS_IN_CHUNK;
+            let bz = bo3 / BLOCKS_IN_CHUNK % BLOCKS_IN_CHUNK;
+            let by = bo3 / BLOCKS_IN_CHUNK_2D;
+            let neighbours = [
+                if by < MAX_BLOCK_IN_CHUNK_Y { Some(bo3 + BLOCKS_IN_CHUNK_2D) } else { None },
+                if by > 0 { Some(bo3 - BLOCKS_IN_CHUNK_2D) } else { None },
+                if bz > 0 { Some(bo3 - BLOCKS_IN_CHUNK) } else { None },
+                if bx < MAX_BLOCK_IN_CHUNK { Some(bo3 + 1) } else { None },
+                if bz < MAX_BLOCK_IN_CHUNK { Some(bo3 + BLOCKS_IN_CHUNK) } else { None },
+                if bx > 0 { Some(bo3 - 1) } else { None },
+            ];
+
+            let nlight = level as u8 - 1;
+            for nbo3 in neighbours.iter().flatten() {
+                let blocktype = &blocktypes[chunk.blocks[*nbo3] as usize];
+                if lights[*nbo3] < nlight && !stops_light(blocktype) {
+                    lights[*nbo3] = nlight;
+                    queues[nlight as usize].push_back(*nbo3);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biometypes;
+    use crate::blocktypes;
+    use crate::options::Lighting;
+
+    fn get_chunk(blocktypes: &[BlockType], fill: &str) -> ChunkData {
+        ChunkData {
+            blocks: [find_block(blocktypes, fill); BLOCKS_IN_CHUNK_3D],
+            lights: [0u8; BLOCKS_IN_CHUNK_3D],
+            biomes: [0u8; BIOMES_IN_CHUNK_3D],
+            full: true,
+            corrupt: false,
+        }
+    }
+
+    fn find_block(blocktypes: &[BlockType], name: &str) -> u16 {
+        blocktypes.iter().position(|blocktype| blocktype.name == name).unwrap() as u16
+    }
+
+    fn offset(bx: usize, by: usize, bz: usize) -> usize {
+        by * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx
+    }
+
+    #[test]
+    fn skylight() {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+
+        // Skylight reaches the bottom of a clear column, even through glass.
+        let mut chunk = get_chunk(&blocktypes, "minecraft:air");
+        chunk.blocks[offset(3, 250, 4)] = find_block(&blocktypes, "minecraft:glass");
+        compute_lights(&mut chunk, &blocktypes);
+        assert_eq!(chunk.lights[offset(3, 250, 4)], MAX_LIGHT_LEVEL);
+        assert_eq!(chunk.lights[offset(3, 0, 4)], MAX_LIGHT_LEVEL);
+
+        // It loses a level in each of two layers of leaves.
+        let leaves = find_block(&blocktypes, "minecraft:oak_leaves");
+        chunk.blocks[offset(0, 199, 0)..offset(0, 201, 0)].fill(leaves);
+        compute_lights(&mut chunk, &blocktypes);
+        assert_eq!(chunk.lights[offset(5, 200, 4)], MAX_LIGHT_LEVEL - 1);
+        assert_eq!(chunk.lights[offset(5, 199, 4)], MAX_LIGHT_LEVEL - 2);
+        assert_eq!(chunk.lights[offset(5, 0, 4)], MAX_LIGHT_LEVEL - 2);
+
+        // A layer of stone stops it completely.
+        let stone = find_block(&blocktypes, "minecraft:stone");
+        chunk.blocks[offset(0, 100, 0)..offset(0, 101, 0)].fill(stone);
+        compute_lights(&mut chunk, &blocktypes);
+        assert_eq!(chunk.lights[offset(8, 101, 8)], MAX_LIGHT_LEVEL - 2);
+        assert_eq!(chunk.lights[offset(8, 100, 8)], 0);
+        assert_eq!(chunk.lights[offset(8, 0, 8)], 0);
+    }
+
+    #[test]
+    fn block_light() {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+
+        // A torch in a closed cave, with a wall of stone to one side of it.
+        let mut chunk = get_chunk(&blocktypes, "minecraft:stone");
+        let air = find_block(&blocktypes, "minecraft:air");
+        for by in 10..20 {
+            chunk.blocks[offset(0, by, 0)..offset(0, by + 1, 0)].fill(air);
+        }
+        for bz in 0..BLOCKS_IN_CHUNK {
+            for by in 10..20 {
+                chunk.blocks[offset(6, by, bz)] = find_block(&blocktypes, "minecraft:stone");
+            }
+        }
+        chunk.blocks[offset(8, 15, 8)] = find_block(&blocktypes, "minecraft:torch");
+        compute_lights(&mut chunk, &blocktypes);
+
+        // Block light is in the high nibble, and falls off a level for each block away.
+        let blight = |bx, by, bz| chunk.lights[offset(bx, by, bz)] >> 4;
+        assert_eq!(blight(8, 15, 8), 14);
+        assert_eq!(blight(9, 15, 8), 13);
+        assert_eq!(blight(10, 16, 9), 10);
+        assert_eq!(blight(15, 15, 8), 7);
+        assert_eq!(blight(7, 15, 8), 13);
+        // The wall stops it, and the stone around the cave isn't lit.
+        assert_eq!(blight(6, 15, 8), 0);
+        assert_eq!(blight(5, 15, 8), 0);
+        assert_eq!(blight(8, 20, 8), 0);
+        assert_eq!(chunk.lights[offset(8, 15, 8)] & 0x0f, 0);
+    }
+}
@@ -1,29 +1,80 @@
 use std::path::Path;
 
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, SubCommand};
 
+use types::Pair;
+
+mod bedrock;
 mod biometypes;
 mod blocktypes;
+mod check;
 mod color;
+mod compression;
 mod data;
+mod deserializer;
+mod dimension;
 mod image;
 mod isomap;
+mod light;
+mod level;
+mod manifest;
 mod map;
+mod mapitems;
+mod marker;
+mod mutf8;
 mod nbt;
+mod nbtref;
 mod options;
 mod orthomap;
+mod players;
+mod query;
 mod region;
 mod sizes;
+mod snbt;
+mod stats;
+mod structure;
 mod types;
 mod world;
 
+// Options shared by the map and the stats subcommand.
+fn dimension_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("d")
+        .short("d")
+        .long("dimension")
+        .value_name("NAME")
+        .help("Dimension to read, e.g. the_end or dimensions/<NS>/<NAME>")
+}
+
+fn blocks_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("b")
+        .short("b")
+        .long("blocks")
+        .value_names(&["W", "N", "E", "S"])
+        .allow_hyphen_values(true)
+        .validator(|v| v.parse::<isize>().map(|_| ())
+            .map_err(|_| "Horizontal block limits must be numbers".to_string()))
+        .help("Horizontal block limits")
+}
+
+fn yblocks_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("y")
+        .short("y")
+        .long("yblocks")
+        .value_names(&["MIN", "MAX"])
+        .allow_hyphen_values(true)
+        .validator(|v| v.parse::<isize>().map(|_| ())
+            .map_err(|_| "Vertical block limits must be numbers".to_string()))
+        .help("Vertical block limits")
+}
+
 fn main() {
     let matches = App::new("rmapbash")
         .about("Minecraft map renderer")
         .author("saltiresable@gmail.com")
         .version("0.1.0")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("INPATH")
-            .help("Path to either a save directory or a .dat file")
+            .help("Path to a save directory, a .dat or .snbt file, or a structure file")
             .required(true)
             .index(1))
         .arg(Arg::with_name("OUTPATH")
@@ -42,30 +93,157 @@ fn main() {
             .short("n")
             .long("night")
             .help("Night lighting"))
-        .arg(Arg::with_name("b")
-            .short("b")
-            .long("blocks")
-            .value_names(&["W", "N", "E", "S"])
-            .allow_hyphen_values(true)
-            .validator(|v| v.parse::<isize>().map(|_| ())
-                .map_err(|_| "Horizontal block limits must be numbers".to_string()))
-            .help("Horizontal block limits"))
-        .arg(Arg::with_name("y")
-            .short("y")
-            .long("yblocks")
-            .value_names(&["MIN", "MAX"])
-            .validator(|v| v.parse::<usize>().map(|_| ())
-                .map_err(|_| "Vertical block limits must be positive numbers".to_string()))
-            .help("Vertical block limits"))
+        .arg(dimension_arg())
+        .arg(Arg::with_name("p")
+            .short("p")
+            .long("partial")
+            .value_name("MODE")
+            .possible_values(&["show", "hide", "tint"])
+            .default_value("show")
+            .help("How to draw chunks that haven't finished generating"))
+        .arg(Arg::with_name("s")
+            .short("s")
+            .long("spawn")
+            .help("Mark the spawn point from the world's level.dat"))
+        .arg(Arg::with_name("P")
+            .short("P")
+            .long("players")
+            .help("Mark players' positions, respawn points and last deaths"))
+        .arg(Arg::with_name("u")
+            .short("u")
+            .long("usercache")
+            .value_name("FILE")
+            .help("Server usercache.json to look up player names in"))
+        .arg(Arg::with_name("I")
+            .short("I")
+            .long("incremental")
+            .help("Only redraw the regions that changed since the map was last drawn"))
+        .arg(blocks_arg())
+        .arg(yblocks_arg())
+        .subcommand(SubCommand::with_name("check")
+            .about("Checks region files for damaged or misplaced chunks")
+            .arg(Arg::with_name("INPATH")
+                .help("Path to a save directory, a region directory, or a region file")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .value_name("DIR")
+                .help("Write copies of damaged region files without their bad chunks to DIR")))
+        .subcommand(SubCommand::with_name("maps")
+            .about("Renders a world's map items to .png files, and stitches them into mosaics")
+            .arg(Arg::with_name("INPATH")
+                .help("Path to a save directory, or a directory of map_<N>.dat files")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("OUTDIR")
+                .help("Directory to save the images in")
+                .default_value("maps")
+                .index(2)))
+        .subcommand(SubCommand::with_name("query")
+            .about("Prints the tags matching a path like Data.Player.Pos in an NBT file or chunk")
+            .setting(AppSettings::AllowNegativeNumbers)
+            .arg(Arg::with_name("INPATH")
+                .help("Path to an NBT file, or a save directory if a chunk is given")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("PATH")
+                .help("Tag path, e.g. Level.Sections[*].Palette[0].Name")
+                .required(true)
+                .index(2))
+            .arg(Arg::with_name("c")
+                .short("c")
+                .long("chunk")
+                .value_names(&["X", "Z"])
+                .validator(|v| v.parse::<isize>().map(|_| ())
+                    .map_err(|_| "Chunk coordinates must be numbers".to_string()))
+                .help("Query a chunk in a save directory instead of a file")))
+        .subcommand(SubCommand::with_name("stats")
+            .about("Counts the blocks and biomes in a world, and the heights of chosen blocks")
+            .arg(Arg::with_name("INPATH")
+                .help("Path to a save directory, or a structure file")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("OUTPATH")
+                .help("Path to an output .csv or .json file")
+                .default_value("stats.csv")
+                .validator(|v| match Path::new(&v).extension() {
+                    Some(ext) if ext == "csv" || ext == "json" => Ok(()),
+                    _ => Err("Output path must be a .csv or .json file".to_string()),
+                })
+                .index(2))
+            .arg(dimension_arg())
+            .arg(blocks_arg())
+            .arg(yblocks_arg())
+            .arg(Arg::with_name("H")
+                .short("H")
+                .long("heights")
+                .value_name("BLOCKS")
+                .use_delimiter(true)
+                .help("Blocks to count at each Y level, separated by commas [default: ores]")))
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let inpath = Path::new(matches.value_of("INPATH").unwrap());
+        match check::check_regions(inpath, matches.value_of("repair").map(Path::new)) {
+            Ok(()) => println!("Done."),
+            Err(err) => eprintln!("Error checking regions: {}", err),
+        };
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("maps") {
+        let inpath = Path::new(matches.value_of("INPATH").unwrap());
+        let outdir = Path::new(matches.value_of("OUTDIR").unwrap());
+        match mapitems::render_maps(inpath, outdir) {
+            Ok(()) => println!("Done."),
+            Err(err) => eprintln!("Error rendering maps: {}", err),
+        };
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("query") {
+        let inpath = Path::new(matches.value_of("INPATH").unwrap());
+        let chunk = matches.values_of("c").map(|values| {
+            let c: Vec<isize> = values.map(|v| v.parse::<isize>().unwrap()).collect();
+            Pair { x: c[0], z: c[1] }
+        });
+        match query::run_query(inpath, chunk, matches.value_of("PATH").unwrap()) {
+            Ok(()) => {},
+            Err(err) => eprintln!("Error running query: {}", err),
+        };
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("stats") {
+        let options = options::get_options(matches);
+        let names: Vec<&str> = match matches.values_of("H") {
+            Some(names) => names.collect(),
+            None => stats::DEFAULT_HISTOGRAM_BLOCKS.to_vec(),
+        };
+        match stats::write_stats(&options, &names) {
+            Ok(()) => println!("Done."),
+            Err(err) => eprintln!("Error counting blocks: {}", err),
+        };
+        return;
+    }
+
     let options = options::get_options(&matches);
 
     match options.inpath.extension() {
+        Some(_) if mapitems::is_map_path(options.inpath) =>
+            match mapitems::render_map_file(options.inpath, options.outpath) {
+                Ok(()) => println!("Done."),
+                Err(err) => eprintln!("Error rendering map: {}", err),
+            },
         Some(ext) if ext == "dat" => match data::read_dat_file(options.inpath) {
             Ok(()) => println!("Done."),
             Err(err) => eprintln!("Error reading data: {}", err),
         },
+        Some(ext) if ext == "snbt" => match data::write_dat_file(options.inpath) {
+            Ok(()) => println!("Done."),
+            Err(err) => eprintln!("Error writing data: {}", err),
+        },
         _ => match map::create_map(&options) {
             Ok(()) => println!("Done."),
             Err(err) => eprintln!("Error creating map: {}", err),
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::region::{self, RegionFormat};
+use super::types::*;
+use super::world::World;
+
+// Everything apart from the world's blocks that decides what each pixel of a map looks like.
+// A map drawn with different settings can't be reused.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct MapSettings {
+    pub version: String,
+    pub path: String,
+    pub dimension: String,
+    pub view: String,
+    pub lighting: String,
+    pub partial: String,
+    // The block limits passed with -b, as north, east, south and west. Without them, the map's
+    // edges move as the world grows, and the last map is moved to match.
+    pub limits: Option<[isize; 4]>,
+    // The lowest and highest block Y drawn.
+    pub ylimits: [isize; 2],
+}
+
+// Saved next to a map, to find which regions have changed when it is drawn again.
+#[derive(Deserialize, Serialize)]
+pub struct Manifest {
+    pub settings: MapSettings,
+    // Whether a copy of the map without markers was saved, since markers can move between runs.
+    pub base: bool,
+    // The block edges of the map, as north, east, south and west.
+    pub edges: [isize; 4],
+    // The save times of the chunks in each region file, keyed by region coords.
+    regions: BTreeMap<String, Vec<u32>>,
+}
+
+fn get_region_key(r: &Pair<isize>) -> String {
+    format!("{},{}", r.x, r.z)
+}
+
+fn parse_region_key(key: &str) -> Option<Pair<isize>> {
+    let (x, z) = key.split_once(',')?;
+    Some(Pair { x: x.parse().ok()?, z: z.parse().ok()? })
+}
+
+pub fn get_manifest_path(outpath: &Path) -> PathBuf {
+    outpath.with_extension("manifest.json")
+}
+
+pub fn get_base_path(outpath: &Path) -> PathBuf {
+    outpath.with_extension("base.png")
+}
+
+impl Manifest {
+    // Read the chunk save times of the regions in a map, and of the regions around them, since
+    // blocks at the edges of the map are shaded by the blocks next to them.
+    pub fn new(world: &World, format: RegionFormat, settings: MapSettings, base: bool)
+    -> Manifest {
+        let mut coords = HashSet::new();
+        for r in world.regions.keys() {
+            coords.insert(*r);
+            coords.extend(get_neighbours(r));
+        }
+
+        let mut regions = BTreeMap::new();
+        for r in coords {
+            let path = region::get_path_from_coords(world.path, &r, format);
+            // Regions that can't be read are left out, so that they are drawn again next time.
+            if let Ok(timestamps) = region::read_region_timestamps(&path) {
+                regions.insert(get_region_key(&r), timestamps);
+            }
+        }
+
+        let edges = [world.bedges.n, world.bedges.e, world.bedges.s, world.bedges.w];
+        Manifest { settings, base, edges, regions }
+    }
+
+    pub fn get_edges(&self) -> Edges<isize> {
+        Edges { n: self.edges[0], e: self.edges[1], s: self.edges[2], w: self.edges[3] }
+    }
+
+    // Find the regions that have to be drawn again since an earlier manifest: those whose chunks
+    // were saved, added or removed since then, and the ones next to them.
+    pub fn get_changed_regions(&self, old: &Manifest) -> HashSet<Pair<isize>> {
+        let mut changed = HashSet::new();
+        for key in self.regions.keys().chain(old.regions.keys()) {
+            if self.regions.get(key) != old.regions.get(key) {
+                if let Some(r) = parse_region_key(key) {
+                    changed.insert(r);
+                    changed.extend(get_neighbours(&r));
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn get_neighbours(r: &Pair<isize>) -> [Pair<isize>; 4] {
+    [
+        Pair { x: r.x, z: r.z - 1 },
+        Pair { x: r.x + 1, z: r.z },
+        Pair { x: r.x, z: r.z + 1 },
+        Pair { x: r.x - 1, z: r.z },
+    ]
+}
+
+pub fn read_manifest(path: &Path) -> Result<Option<Manifest>, Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map(Some)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), Error> {
+    serde_json::to_writer(BufWriter::new(File::create(path)?), manifest)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(regions: Vec<(&str, Vec<u32>)>) -> Manifest {
+        Manifest {
+            settings: MapSettings {
+                version: "0.1.0".to_string(),
+                path: "world".to_string(),
+                dimension: "minecraft:overworld".to_string(),
+                view: "Orthographic".to_string(),
+                lighting: "Day".to_string(),
+                partial: "Show".to_string(),
+                limits: None,
+                ylimits: [-64, 319],
+            },
+            base: false,
+            edges: [-512, 511, 511, -512],
+            regions: regions.into_iter().map(|(key, times)| (key.to_string(), times)).collect(),
+        }
+    }
+
+    #[test]
+    fn changed_regions() {
+        let old = manifest(vec![("0,0", vec![1, 2]), ("-1,0", vec![1, 0]), ("5,5", vec![3, 3])]);
+        let new = manifest(vec![("0,0", vec![1, 2]), ("-1,0", vec![1, 4]), ("0,-1", vec![1, 1])]);
+
+        let mut changed: Vec<(isize, isize)> = new.get_changed_regions(&old).iter()
+            .map(|r| (r.x, r.z))
+            .collect();
+        changed.sort();
+        assert_eq!(changed, [
+            (-2, 0), (-1, -1), (-1, 0), (-1, 1), (0, -2), (0, -1), (0, 0),
+            (1, -1), (4, 5), (5, 4), (5, 5), (5, 6), (6, 5),
+        ]);
+        assert!(new.get_changed_regions(&new).is_empty());
+
+        assert_eq!(parse_region_key(&get_region_key(&Pair { x: -3, z: 12 })),
+            Some(Pair { x: -3, z: 12 }));
+        assert_eq!(parse_region_key("3"), None);
+    }
+}
@@ -1,39 +1,116 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::time::Instant;
 
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 
+use super::biometypes;
 use super::blocktypes;
 use super::image;
 use super::isomap;
-use super::options::{Options, View};
+use super::color::RGBA;
+use super::dimension;
+use super::manifest::{self, Manifest};
+use super::marker;
+use super::options::{Lighting, Options, PartialChunks, View};
 use super::orthomap;
-use super::region;
+use super::players;
 use super::sizes::*;
 use super::types::*;
 use super::world;
 
+// Blended into chunks that haven't finished generating, when they are tinted.
+const PARTIAL_CHUNK_TINT: RGBA = RGBA { r: 255, g: 0, b: 255, a: 96 };
+// Blended into the placeholders drawn for chunks that couldn't be read.
+const CORRUPT_CHUNK_TINT: RGBA = RGBA { r: 255, g: 0, b: 0, a: 224 };
+
 pub fn create_map(options: &Options) -> Result<(), Box<dyn Error>> {
+    let dimension = dimension::get_dimension(options.inpath, options.dimension)?;
+    let lighting = dimension.get_lighting(options.night);
+    let ylimits = options.ylimits.clone().unwrap_or_else(|| dimension.get_ylimits());
+
     println!("View:              {}", options.view);
-    println!("Lighting:          {}", options.lighting);
+    println!("Dimension:         {}", dimension.name);
+    println!("Lighting:          {}", lighting);
+    println!("Partial chunks:    {}", options.partial);
     println!("Horizontal limits: {}", match options.blimits {
         Some(blimits) => format!("({}, {}) - ({}, {})", blimits.w, blimits.n, blimits.e, blimits.s),
         _ => "none".to_string(),
     });
-    println!("Vertical limits:   {} - {}", options.ylimits.start, options.ylimits.end - 1);
+    println!("Vertical limits:   {} - {}", offset_to_block_y(ylimits.start),
+        offset_to_block_y(ylimits.end - 1));
 
     let start = Instant::now();
 
     std::fs::create_dir_all(options.outpath.parent().unwrap())?;
 
-    println!("Getting world info from world dir {}", options.inpath.display());
-    let world = world::get_world(options.inpath, &options.blimits, &options.ylimits)?;
+    println!("Getting world info from world dir {}", dimension.path.display());
+    let world = world::get_world(&dimension, &options.blimits, &ylimits)?;
+    if let Some(level) = &world.level {
+        if let Some(name) = &level.name {
+            println!("World name:        {}", name);
+        }
+        match (level.game_version(), level.data_version) {
+            (Some(version), Some(data_version)) =>
+                println!("Game version:      {} (data version {})", version, data_version),
+            (None, Some(data_version)) => println!("Data version:      {}", data_version),
+            _ => {},
+        }
+        if let Some((x, y, z)) = level.spawn() {
+            println!("Spawn point:       ({}, {}, {})", x, y, z);
+        }
+        if let Some((x, z, size)) = level.border() {
+            println!("World border:      {} blocks wide, centered on ({}, {})", size, x, z);
+        }
+        if let Some(days) = level.days() {
+            println!("Game time:         day {}", days + 1);
+        }
+    }
+
+    let mut markers = vec![];
+    if options.players {
+        markers.extend(get_player_markers(&dimension, options)?);
+    }
+    if options.spawn {
+        match world.level.as_ref().and_then(|level| level.spawn()) {
+            Some(_) if dimension.name != dimension::OVERWORLD =>
+                println!("The spawn point is in the overworld"),
+            Some((x, y, z)) => markers.push(marker::Marker {
+                b: Pair { x, z },
+                y,
+                color: marker::SPAWN_MARKER,
+            }),
+            None => println!("No spawn point found in level.dat"),
+        }
+    }
 
     println!("Getting block types");
-    let blocktypes = blocktypes::get_block_types(&options.lighting);
+    let biometypes = biometypes::get_biome_types();
+    let blocktypes = blocktypes::get_block_types(&lighting, &biometypes);
+    let legacy_blocktypes = blocktypes::get_legacy_block_types(&blocktypes);
+    let biome_registry = biometypes::get_biome_registry(&biometypes);
+
+    let manifest = if options.incremental {
+        get_manifest(&world, &dimension, &lighting, &ylimits, !markers.is_empty(), options)
+    } else { None };
+    let incremental = Incremental {
+        update: manifest.as_ref().and_then(|manifest| get_update(&world, manifest, options)),
+        basepath: manifest.as_ref().filter(|manifest| manifest.base)
+            .map(|_| manifest::get_base_path(options.outpath)),
+    };
 
     println!("Drawing block map");
-    let result = draw_map(&world, &blocktypes, options);
+    let result = draw_map(&world, &blocktypes, &biome_registry, &legacy_blocktypes, &markers,
+        incremental, options);
+    if let (Ok(()), Some(manifest)) = (&result, &manifest) {
+        let path = manifest::get_manifest_path(options.outpath);
+        match manifest::write_manifest(&path, manifest) {
+            Ok(()) => println!("Saved manifest to {}", path.display()),
+            Err(err) => println!("Error saving manifest: {}", err),
+        }
+    }
 
     let elapsed = start.elapsed();
     let mins = elapsed.as_secs() / 60;
@@ -44,7 +121,123 @@ pub fn create_map(options: &Options) -> Result<(), Box<dyn Error>> {
     result
 }
 
-pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType], options: &Options)
+// Record the map's settings and the chunk save times of its regions, so that it can be updated
+// next time. Only orthographic maps can be, since each region has its own part of the map;
+// isometric regions overlap, and are blended into each other as they are drawn.
+fn get_manifest(world: &world::World, dimension: &dimension::Dimension, lighting: &Lighting,
+    ylimits: &Range<usize>, base: bool, options: &Options)
+-> Option<Manifest> {
+    let format = match (&world.format, &options.view) {
+        (_, View::Isometric) => {
+            println!("Isometric maps can't be updated incrementally, so all regions will be drawn");
+            return None;
+        },
+        (world::WorldFormat::Java(format), View::Orthographic) => *format,
+        _ => {
+            println!("Only worlds with region files can be updated incrementally");
+            return None;
+        },
+    };
+    let settings = manifest::MapSettings {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        path: dimension.path.display().to_string(),
+        dimension: dimension.name.clone(),
+        view: options.view.to_string(),
+        lighting: lighting.to_string(),
+        partial: options.partial.to_string(),
+        limits: options.blimits.map(|blimits| [blimits.n, blimits.e, blimits.s, blimits.w]),
+        ylimits: [offset_to_block_y(ylimits.start), offset_to_block_y(ylimits.end - 1)],
+    };
+    Some(Manifest::new(world, format, settings, base))
+}
+
+// Find the regions that changed since the map was last drawn with the same settings, and read
+// back the map as it was, without its markers.
+fn get_update(world: &world::World, manifest: &Manifest, options: &Options)
+-> Option<(HashSet<Pair<isize>>, Vec<u8>)> {
+    let old = match manifest::read_manifest(&manifest::get_manifest_path(options.outpath)) {
+        Ok(Some(old)) => old,
+        Ok(None) => return None,
+        Err(err) => {
+            println!("Error reading manifest: {}", err);
+            return None;
+        },
+    };
+    if old.settings != manifest.settings {
+        println!("The map's settings have changed, so all regions will be drawn");
+        return None;
+    }
+
+    // If the world has grown or shrunk, move the last map to the new edges. The regions whose
+    // chunks were added or removed are drawn again anyway.
+    let path = if old.base { manifest::get_base_path(options.outpath) }
+        else { options.outpath.to_path_buf() };
+    let oldedges = old.get_edges();
+    match image::read_block_map(&path, &oldedges.size()) {
+        Ok(pixels) if old.edges == manifest.edges =>
+            Some((manifest.get_changed_regions(&old), pixels)),
+        Ok(pixels) => Some((manifest.get_changed_regions(&old),
+            orthomap::move_map(&pixels, &oldedges, &world.bedges))),
+        Err(err) => {
+            println!("Error reading the last map: {}", err);
+            None
+        },
+    }
+}
+
+// Read the players' positions, respawn points and last deaths, and mark the ones in the
+// dimension being drawn.
+fn get_player_markers(dimension: &dimension::Dimension, options: &Options)
+-> Result<Vec<marker::Marker>, Box<dyn Error>> {
+    let names = match options.usercache {
+        Some(path) => players::read_user_cache(path)?,
+        None => HashMap::new(),
+    };
+    let (players, errors) = players::read_players(&dimension.savepath, &names)?;
+
+    let mut markers = vec![];
+    println!("Players:           {}", players.len());
+    for player in &players {
+        let mut locations = vec![];
+        // Draw deaths first, so that positions are drawn over them.
+        for (label, location, color) in [
+            ("last died at", &player.death, marker::DEATH_MARKER),
+            ("respawns at", &player.spawn, marker::RESPAWN_MARKER),
+            ("at", &player.position, marker::PLAYER_MARKER),
+        ] {
+            if let Some(location) = location {
+                locations.insert(0, format!("{} ({}, {}, {}) in {}", label, location.x,
+                    location.y, location.z, location.dimension));
+                if location.dimension == dimension.name {
+                    markers.push(marker::Marker {
+                        b: Pair { x: location.x, z: location.z },
+                        y: location.y,
+                        color,
+                    });
+                }
+            }
+        }
+        println!("  {}: {}", player.name, locations.join(", "));
+    }
+    for (name, err) in errors {
+        println!("  {}: error reading player data: {}", name, err);
+    }
+    Ok(markers)
+}
+
+// What to reuse from the last time a map was drawn, and what to keep for next time.
+pub struct Incremental {
+    // The regions that changed since the map was last drawn, and the map as it was then.
+    pub update: Option<(HashSet<Pair<isize>>, Vec<u8>)>,
+    // Where to save the map without its markers.
+    pub basepath: Option<PathBuf>,
+}
+
+// Draw the map, or if there is an earlier one, draw just the regions that changed over it.
+pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType],
+    biome_registry: &biometypes::BiomeRegistry,
+    legacy_blocktypes: &[u16], markers: &[marker::Marker], incremental: Incremental,
+    options: &Options)
 -> Result<(), Box<dyn Error>> {
     let size = match options.view {
         View::Isometric => isomap::get_size(world),
@@ -54,19 +247,39 @@ pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType], opti
         View::Isometric => isomap::get_crop(world, &size),
         View::Orthographic => orthomap::get_crop(world, &size),
     };
-    let mut pixels = vec![0u8; size.x * size.z * 4];
+    let (redraw, mut pixels) = match incremental.update {
+        Some((redraw, pixels)) => (Some(redraw), pixels),
+        None => (None, vec![0u8; size.x * size.z * 4]),
+    };
+    let region_count = world.regions.keys()
+        .filter(|r| redraw.as_ref().is_none_or(|redraw| redraw.contains(r)))
+        .count();
+    if redraw.is_some() {
+        println!("Updating {} of {} regions", region_count, world.regions.len());
+    }
 
     let water_blocktype = blocktypes.iter().find(|b| b.name == "minecraft:water").unwrap();
 
-    let bar = ProgressBar::with_draw_target(world.regions.len() as u64,
+    let bar = ProgressBar::with_draw_target(region_count as u64,
         ProgressDrawTarget::stdout_nohz())
         .with_style(ProgressStyle::default_bar()
             .template("{wide_bar}\n{msg} ({pos}/{len})")
             .progress_chars("▪■ "));
 
+    let mut errors = vec![];
+    let mut reader = world::RegionReader::new(world, blocktypes, biome_registry,
+        legacy_blocktypes);
+
     for rz in (world.redges.n..world.redges.s + 1).rev() {
         for rx in (world.redges.w..world.redges.e + 1).rev() {
             let r = &Pair { x: rx, z: rz };
+            // Regions that changed are cleared first, in case they have lost chunks.
+            if let Some(redraw) = &redraw {
+                if !redraw.contains(r) {
+                    continue;
+                }
+                orthomap::clear_region(&mut pixels, world, &size.x, r);
+            }
             if !world.regions.contains_key(&r) {
                 continue;
             }
@@ -75,7 +288,26 @@ pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType], opti
             bar.set_message(&msg);
             bar.inc(1);
 
-            if let Some(reg) = region::read_region_data(&world, r, blocktypes)? {
+            let reg = reader.read(r, true);
+            // Skip regions that can't be read at all, and carry on with the rest of the map.
+            let reg = reg.unwrap_or_else(|err| {
+                errors.push(format!("Region {}, {}: {}", r.x, r.z, err));
+                None
+            });
+            if let Some(mut reg) = reg {
+                for (c, err) in &reg.errors {
+                    errors.push(format!("Region {}, {}, chunk {}, {}: {}", r.x, r.z, c.x, c.z,
+                        err));
+                }
+
+                if options.partial == PartialChunks::Hide {
+                    reg.chunks.retain(|_, chunk| chunk.full);
+                    for nchunks in [&mut reg.nchunks.n, &mut reg.nchunks.e, &mut reg.nchunks.s,
+                        &mut reg.nchunks.w] {
+                        nchunks.retain(|_, chunk| chunk.full);
+                    }
+                }
+
                 let chunk_count = reg.chunks.len();
                 let msg = format!("Drawing block map for region {}, {} ({} chunk{})", r.x, r.z,
                     chunk_count, if chunk_count == 1 { "" } else { "s" });
@@ -96,7 +328,12 @@ pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType], opti
                         cbar.inc(1);
 
                         let c = &Pair { x: cx, z: cz };
-                        if let Some(chunk) = reg.get_chunk(c) {
+                        if let Some(mut chunk) = reg.get_chunk(c) {
+                            if chunk.data.corrupt {
+                                chunk.tint = Some(CORRUPT_CHUNK_TINT);
+                            } else if options.partial == PartialChunks::Tint && !chunk.data.full {
+                                chunk.tint = Some(PARTIAL_CHUNK_TINT);
+                            }
                             // println!("Drawing chunk {}, {}", c.x, c.z);
                             let wc = Pair {
                                 x: r.x * CHUNKS_IN_REGION as isize + c.x as isize,
@@ -132,9 +369,28 @@ pub fn draw_map(world: &world::World, blocktypes: &[blocktypes::BlockType], opti
         }
     }
 
-    image::draw_block_map(&pixels, size, options.outpath, true)?;
+    let title = world.level.as_ref().and_then(|level| level.name.as_deref());
+    if let Some(path) = &incremental.basepath {
+        image::draw_block_map(&pixels, size, path, true, title)?;
+    }
+
+    // Markers outside the map are left out.
+    for m in markers {
+        if let Some(p) = marker::get_block_pixel(world, &options.view, &m.b, m.y) {
+            marker::draw_marker(&mut pixels, &size, &p, &m.color);
+        }
+    }
+
+    image::draw_block_map(&pixels, size, options.outpath, true, title)?;
 
     bar.finish_and_clear();
 
+    if !errors.is_empty() {
+        println!("Unreadable chunks and regions ({}):", errors.len());
+        for error in errors {
+            println!("  {}", error);
+        }
+    }
+
     Ok(())
 }
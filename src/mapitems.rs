@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use csv::Reader;
+
+use regex::Regex;
+
+use serde::Deserialize;
+
+use super::color::{RGBA, BLANK_RGBA};
+use super::data;
+use super::deserializer;
+use super::dimension::DimensionId;
+use super::image;
+use super::types::*;
+
+// Map items are 128x128 pixels, each covering 2^scale blocks on a side.
+const MAP_SIZE: usize = 128;
+const MAP_PIXELS: usize = MAP_SIZE * MAP_SIZE;
+const MAX_MAP_SCALE: u8 = 4;
+// Each color byte is a base color index times four, plus one of these brightness multipliers.
+const MAP_SHADES: [u16; 4] = [180, 220, 255, 135];
+// Mosaics of maps that are very far apart would be too big to save.
+const MAX_MOSAIC_SIZE: usize = 16384;
+
+#[derive(Deserialize)]
+struct Row {
+    id: u8,
+    r: Option<u8>,
+    g: Option<u8>,
+    b: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct MapDat {
+    data: MapData,
+}
+
+#[derive(Deserialize)]
+struct MapData {
+    scale: u8,
+    dimension: DimensionId,
+    #[serde(rename = "xCenter")]
+    x_center: i32,
+    #[serde(rename = "zCenter")]
+    z_center: i32,
+    colors: Vec<u8>,
+}
+
+pub struct MapItem {
+    pub id: usize,
+    pub scale: u8,
+    pub dimension: String,
+    pub center: Pair<isize>,
+    pub pixels: Vec<u8>,
+}
+
+impl MapItem {
+    // The block coords of the map's northwest corner.
+    fn corner(&self) -> Pair<isize> {
+        let half = ((MAP_SIZE / 2) as isize) << self.scale;
+        Pair { x: self.center.x - half, z: self.center.z - half }
+    }
+}
+
+// Get the color of every possible map color byte. Base colors without an RGB value, including
+// the first one, are transparent.
+pub fn get_map_colors() -> Vec<RGBA> {
+    let csvpath = Path::new("./resources/mapcolors.csv");
+    let mut reader = Reader::from_path(csvpath).unwrap();
+
+    let mut colors = vec![BLANK_RGBA; 256];
+    for row in reader.deserialize::<Row>().map(|res| res.unwrap()) {
+        if let (Some(r), Some(g), Some(b)) = (row.r, row.g, row.b) {
+            for (shade, multiplier) in MAP_SHADES.iter().enumerate() {
+                let shaded = |c: u8| (c as u16 * multiplier / 255) as u8;
+                colors[row.id as usize * 4 + shade] = RGBA {
+                    r: shaded(r),
+                    g: shaded(g),
+                    b: shaded(b),
+                    a: 255,
+                };
+            }
+        }
+    }
+    colors
+}
+
+fn get_map_pixels(colors: &[u8], map_colors: &[RGBA]) -> Vec<u8> {
+    colors.iter()
+        .flat_map(|c| {
+            let color = &map_colors[*c as usize];
+            [color.r, color.g, color.b, color.a]
+        })
+        .collect()
+}
+
+fn get_map_id(path: &Path) -> Option<usize> {
+    let re = Regex::new(r"^map_(\d+)\.dat$").unwrap();
+    re.captures(path.file_name()?.to_str()?).and_then(|caps| caps[1].parse().ok())
+}
+
+pub fn is_map_path(path: &Path) -> bool {
+    get_map_id(path).is_some()
+}
+
+fn read_map_item(path: &Path, map_colors: &[RGBA]) -> Result<MapItem, std::io::Error> {
+    let map: MapDat = deserializer::from_reader(&mut data::read_nbt_file(path)?)?;
+    if map.data.colors.len() != MAP_PIXELS {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Map colors are the wrong size"));
+    }
+    if map.data.scale > MAX_MAP_SCALE {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Invalid map scale"));
+    }
+    Ok(MapItem {
+        id: get_map_id(path).unwrap_or(0),
+        scale: map.data.scale,
+        dimension: map.data.dimension.name().to_string(),
+        center: Pair { x: map.data.x_center as isize, z: map.data.z_center as isize },
+        pixels: get_map_pixels(&map.data.colors, map_colors),
+    })
+}
+
+fn draw_map_item(map: &MapItem, path: &Path) -> Result<(), Box<dyn Error>> {
+    image::draw_block_map(&map.pixels, Pair { x: MAP_SIZE, z: MAP_SIZE }, path, true,
+        Some(&format!("Map #{}", map.id)))?;
+    Ok(())
+}
+
+// Render a single map_N.dat file.
+pub fn render_map_file(inpath: &Path, outpath: &Path) -> Result<(), Box<dyn Error>> {
+    let map = read_map_item(inpath, &get_map_colors())?;
+    println!("Map #{}: scale {}, centered on {} in {}", map.id, map.scale, map.center,
+        map.dimension);
+    draw_map_item(&map, outpath)
+}
+
+// Draw maps of the same scale and dimension together, each at its place in the world, with
+// maps drawn over any that have lower IDs where they overlap. Unexplored pixels are left
+// showing the maps below them.
+fn draw_mosaic(maps: &[&MapItem], path: &Path) -> Result<(), Box<dyn Error>> {
+    let scale = maps[0].scale;
+    let corners: Vec<Pair<isize>> = maps.iter().map(|map| map.corner()).collect();
+    let min = Pair {
+        x: corners.iter().map(|corner| corner.x).min().unwrap(),
+        z: corners.iter().map(|corner| corner.z).min().unwrap(),
+    };
+    let offsets: Vec<Pair<usize>> = corners.iter()
+        .map(|corner| Pair {
+            x: ((corner.x - min.x) >> scale) as usize,
+            z: ((corner.z - min.z) >> scale) as usize,
+        })
+        .collect();
+    let size = Pair {
+        x: offsets.iter().map(|offset| offset.x).max().unwrap() + MAP_SIZE,
+        z: offsets.iter().map(|offset| offset.z).max().unwrap() + MAP_SIZE,
+    };
+    if size.x > MAX_MOSAIC_SIZE || size.z > MAX_MOSAIC_SIZE {
+        println!("Skipping mosaic of size {}x{}, since its maps are too far apart", size.x,
+            size.z);
+        return Ok(());
+    }
+
+    let mut pixels = vec![0u8; size.x * size.z * 4];
+    for (map, offset) in maps.iter().zip(offsets) {
+        for z in 0..MAP_SIZE {
+            for x in 0..MAP_SIZE {
+                let mo = (z * MAP_SIZE + x) * 4;
+                if map.pixels[mo + 3] == 0 {
+                    continue;
+                }
+                let po = ((offset.z + z) * size.x + offset.x + x) * 4;
+                pixels[po..(po + 4)].copy_from_slice(&map.pixels[mo..(mo + 4)]);
+            }
+        }
+    }
+
+    let title = format!("Maps at scale {} in {}", scale, maps[0].dimension);
+    image::draw_block_map(&pixels, size, path, true, Some(&title))?;
+    Ok(())
+}
+
+// Render every map item in a save directory's data directory, or in a directory of map files,
+// and stitch together any maps that share a scale and dimension.
+pub fn render_maps(inpath: &Path, outdir: &Path) -> Result<(), Box<dyn Error>> {
+    let datapath = if inpath.join("data").is_dir() { inpath.join("data") }
+        else { inpath.to_path_buf() };
+    let mut paths: Vec<(usize, _)> = std::fs::read_dir(&datapath)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| get_map_id(&path).map(|id| (id, path)))
+        .collect();
+    if paths.is_empty() {
+        return Err(Box::new(std::io::Error::new(ErrorKind::NotFound,
+            format!("No map files found in {}", datapath.display()))));
+    }
+    paths.sort();
+
+    std::fs::create_dir_all(outdir)?;
+    let map_colors = get_map_colors();
+
+    let mut maps = vec![];
+    for (id, path) in paths {
+        match read_map_item(&path, &map_colors) {
+            Ok(map) => {
+                draw_map_item(&map, &outdir.join(format!("map_{}.png", id)))?;
+                maps.push(map);
+            },
+            Err(err) => println!("Error reading map #{}: {}", id, err),
+        }
+    }
+
+    let mut groups: BTreeMap<(&str, u8), Vec<&MapItem>> = BTreeMap::new();
+    for map in &maps {
+        groups.entry((&map.dimension, map.scale)).or_default().push(map);
+    }
+    for ((dimension, scale), group) in groups {
+        if group.len() < 2 {
+            continue;
+        }
+        println!("Stitching {} maps at scale {} in {}", group.len(), scale, dimension);
+        let name = dimension.trim_start_matches("minecraft:").replace(':', "_");
+        draw_mosaic(&group, &outdir.join(format!("mosaic_{}_{}.png", name, scale)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_item(id: usize, scale: u8, x: isize, z: isize) -> MapItem {
+        MapItem {
+            id,
+            scale,
+            dimension: "minecraft:overworld".to_string(),
+            center: Pair { x, z },
+            pixels: vec![],
+        }
+    }
+
+    #[test]
+    fn map_colors() {
+        let colors = get_map_colors();
+        assert_eq!(colors.len(), 256);
+        // None
+        assert_eq!(colors[0].a, 0);
+        assert_eq!(colors[3].a, 0);
+        // Grass, in each shade
+        let grass: Vec<(u8, u8, u8)> = colors[4..8].iter().map(|c| (c.r, c.g, c.b)).collect();
+        assert_eq!(grass, [(89, 125, 39), (109, 153, 48), (127, 178, 56), (67, 94, 29)]);
+
+        let pixels = get_map_pixels(&[6, 0, 255], &colors);
+        assert_eq!(pixels[..8], [127, 178, 56, 255, 0, 0, 0, 0]);
+        assert_eq!(pixels.len(), 12);
+    }
+
+    #[test]
+    fn map_corners() {
+        // Maps line up on a grid offset by 64 blocks from the origin.
+        let corner = map_item(0, 0, 0, 0).corner();
+        assert_eq!((corner.x, corner.z), (-64, -64));
+        let corner = map_item(0, 2, 448, -64).corner();
+        assert_eq!((corner.x, corner.z), (192, -320));
+    }
+
+    #[test]
+    fn map_ids() {
+        assert_eq!(get_map_id(Path::new("world/data/map_12.dat")), Some(12));
+        assert_eq!(get_map_id(Path::new("map_12.dat_old")), None);
+        assert_eq!(get_map_id(Path::new("idcounts.dat")), None);
+    }
+}
@@ -0,0 +1,77 @@
+use super::color::RGBA;
+use super::options::View;
+use super::sizes::*;
+use super::types::*;
+use super::world::World;
+
+// How far a marker's points are from its center, in pixels.
+const MARKER_RADIUS: isize = 4;
+const MARKER_OUTLINE: RGBA = RGBA { r: 0, g: 0, b: 0, a: 255 };
+
+pub const SPAWN_MARKER: RGBA = RGBA { r: 255, g: 32, b: 32, a: 255 };
+pub const PLAYER_MARKER: RGBA = RGBA { r: 32, g: 96, b: 255, a: 255 };
+pub const RESPAWN_MARKER: RGBA = RGBA { r: 32, g: 192, b: 32, a: 255 };
+pub const DEATH_MARKER: RGBA = RGBA { r: 224, g: 224, b: 224, a: 255 };
+
+// A point in the world to mark on the map.
+pub struct Marker {
+    pub b: Pair<isize>,
+    pub y: isize,
+    pub color: RGBA,
+}
+
+// Find the pixel at the center of the top of a block, if the block is within the map.
+pub fn get_block_pixel(world: &World, view: &View, b: &Pair<isize>, y: isize)
+-> Option<Pair<usize>> {
+    if !world.bedges.contains(b) || !(MIN_BLOCK_Y..=MAX_BLOCK_Y).contains(&y) {
+        return None;
+    }
+
+    Some(match view {
+        View::Orthographic => Pair {
+            x: (b.x - world.bedges.w) as usize,
+            z: (b.z - world.bedges.n) as usize,
+        },
+        View::Isometric => {
+            // The block's position from the corner of the first chunk, as in isomap::draw_chunk,
+            // less the blocks cropped from the partial chunks at the edges of the map.
+            let ab = Pair {
+                x: (b.x - world.cedges.w * BLOCKS_IN_CHUNK as isize) as usize,
+                z: (b.z - world.cedges.n * BLOCKS_IN_CHUNK as isize) as usize,
+            };
+            let crop = Edges {
+                n: block_pos_in_chunk(world.bedges.n, None),
+                e: MAX_BLOCK_IN_CHUNK - block_pos_in_chunk(world.bedges.e, None),
+                s: MAX_BLOCK_IN_CHUNK - block_pos_in_chunk(world.bedges.s, None),
+                w: block_pos_in_chunk(world.bedges.w, None),
+            };
+            let by = block_y_to_offset(y);
+            Pair {
+                x: (ab.x + world.csize.z * BLOCKS_IN_CHUNK - ab.z - 1 - crop.w - crop.s) *
+                    ISO_BLOCK_X_MARGIN + ISO_BLOCK_WIDTH / 2,
+                z: (ab.x + ab.z - crop.w - crop.n) * ISO_BLOCK_Y_MARGIN +
+                    (MAX_BLOCK_IN_CHUNK_Y - by) * ISO_BLOCK_SIDE_HEIGHT + ISO_BLOCK_TOP_HEIGHT / 2,
+            }
+        },
+    })
+}
+
+// Draw a diamond with a dark outline, centered on a pixel and clipped to the edges of the map.
+pub fn draw_marker(pixels: &mut [u8], size: &Pair<usize>, p: &Pair<usize>, color: &RGBA) {
+    for dz in -MARKER_RADIUS..(MARKER_RADIUS + 1) {
+        for dx in -MARKER_RADIUS..(MARKER_RADIUS + 1) {
+            let distance = dx.abs() + dz.abs();
+            if distance > MARKER_RADIUS {
+                continue;
+            }
+            let (x, z) = (p.x as isize + dx, p.z as isize + dz);
+            if x < 0 || z < 0 || x >= size.x as isize || z >= size.z as isize {
+                continue;
+            }
+
+            let pcolor = if distance == MARKER_RADIUS { &MARKER_OUTLINE } else { color };
+            let po = (z as usize * size.x + x as usize) * 4;
+            pixels[po..(po + 4)].copy_from_slice(&[pcolor.r, pcolor.g, pcolor.b, pcolor.a]);
+        }
+    }
+}
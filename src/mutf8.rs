@@ -0,0 +1,134 @@
+use std::io::{Error, ErrorKind};
+
+// Java Edition NBT stores strings in Java's modified UTF-8, which differs from standard UTF-8
+// in two ways: the null character is encoded as two bytes (0xC0 0x80), and characters outside
+// the Basic Multilingual Plane, like emoji, are encoded as a UTF-16 surrogate pair of three
+// bytes each.
+
+fn invalid_string() -> Error {
+    Error::new(ErrorKind::InvalidData, "Invalid modified UTF-8 string")
+}
+
+pub fn decode(bytes: Vec<u8>) -> Result<String, Error> {
+    // ASCII is the same in both encodings, and is all that most strings contain.
+    if bytes.is_ascii() {
+        return Ok(String::from_utf8(bytes).unwrap());
+    }
+
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let cont = |i: usize| match bytes.get(i) {
+        Some(byte) if byte & 0xc0 == 0x80 => Ok((byte & 0x3f) as u32),
+        _ => Err(invalid_string()),
+    };
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i] as u32;
+        i += match byte {
+            0x00..=0x7f => {
+                units.push(byte as u16);
+                1
+            },
+            0xc0..=0xdf => {
+                units.push((((byte & 0x1f) << 6) | cont(i + 1)?) as u16);
+                2
+            },
+            0xe0..=0xef => {
+                units.push((((byte & 0x0f) << 12) | (cont(i + 1)? << 6) | cont(i + 2)?) as u16);
+                3
+            },
+            // Four-byte sequences aren't valid modified UTF-8, but some tools write standard
+            // UTF-8 anyway, so read them as the surrogate pair they stand for.
+            0xf0..=0xf4 => {
+                let code = ((byte & 0x07) << 18) | (cont(i + 1)? << 12) | (cont(i + 2)? << 6) |
+                    cont(i + 3)?;
+                let c = std::char::from_u32(code).ok_or_else(invalid_string)?;
+                units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+                4
+            },
+            _ => return Err(invalid_string()),
+        };
+    }
+
+    // Java strings can hold unpaired surrogates, which Rust strings can't; replace them.
+    Ok(String::from_utf16_lossy(&units))
+}
+
+pub fn encode(value: &str) -> Vec<u8> {
+    if value.bytes().all(|byte| byte != 0 && byte < 0x80) {
+        return value.as_bytes().to_vec();
+    }
+
+    let mut bytes = Vec::with_capacity(value.len() + 2);
+    for unit in value.encode_utf16() {
+        match unit {
+            0x01..=0x7f => bytes.push(unit as u8),
+            0x00 | 0x80..=0x7ff => bytes.extend_from_slice(&[
+                0xc0 | (unit >> 6) as u8,
+                0x80 | (unit & 0x3f) as u8,
+            ]),
+            _ => bytes.extend_from_slice(&[
+                0xe0 | (unit >> 12) as u8,
+                0x80 | ((unit >> 6) & 0x3f) as u8,
+                0x80 | (unit & 0x3f) as u8,
+            ]),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(value: &str, bytes: &[u8]) {
+        assert_eq!(encode(value), bytes);
+        assert_eq!(decode(bytes.to_vec()).unwrap(), value);
+    }
+
+    #[test]
+    fn ascii() {
+        check("", b"");
+        check("minecraft:oak_sign", b"minecraft:oak_sign");
+    }
+
+    #[test]
+    fn null_is_two_bytes() {
+        check("a\0b", &[0x61, 0xc0, 0x80, 0x62]);
+        // Java also reads a single null byte.
+        assert_eq!(decode(vec![0x61, 0x00]).unwrap(), "a\0");
+    }
+
+    #[test]
+    fn two_and_three_byte_characters() {
+        check("é", &[0xc3, 0xa9]);
+        check("€5", &[0xe2, 0x82, 0xac, 0x35]);
+        check("\u{ffff}", &[0xef, 0xbf, 0xbf]);
+    }
+
+    #[test]
+    fn supplementary_characters_are_surrogate_pairs() {
+        check("😀", &[0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80]);
+        check("Hi 🐝!", &[0x48, 0x69, 0x20, 0xed, 0xa0, 0xbd, 0xed, 0xb0, 0x9d, 0x21]);
+    }
+
+    #[test]
+    fn standard_utf8_supplementary_characters() {
+        assert_eq!(decode("😀".as_bytes().to_vec()).unwrap(), "😀");
+    }
+
+    #[test]
+    fn unpaired_surrogates_are_replaced() {
+        assert_eq!(decode(vec![0xed, 0xa0, 0xbd, 0x61]).unwrap(), "\u{fffd}a");
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        // Truncated
+        assert!(decode(vec![0x61, 0xe2, 0x82]).is_err());
+        // Missing continuation byte
+        assert!(decode(vec![0xc3, 0x61]).is_err());
+        // Unexpected continuation byte
+        assert!(decode(vec![0x80]).is_err());
+        assert!(decode(vec![0xff]).is_err());
+    }
+}
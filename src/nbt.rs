@@ -1,28 +1,34 @@
 use std::collections::HashMap;
 use std::fmt;
-use std::io::{Error, ErrorKind, Read};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 
+use super::compression;
+use super::mutf8;
 
-#[derive(Debug)]
+
+// NBT numbers are signed. Byte arrays are kept unsigned, since they hold raw data like block IDs
+// and light nibbles rather than numbers.
+#[derive(Debug, PartialEq)]
 pub enum Tag {
-    Byte(u8),
-    Short(u16),
-    Int(u32),
-    Long(u64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
     Float(f32),
     Double(f64),
     ByteArray(Vec<u8>),
     String(String),
     List(Vec<Tag>),
     Compound(HashMap<String, Tag>),
-    IntArray(Vec<u32>),
-    LongArray(Vec<u64>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
 }
 
 impl Tag {
-    pub fn to_u8(&self) -> Result<&u8, Error> {
+    pub fn to_i8(&self) -> Result<&i8, Error> {
         match self {
             Tag::Byte(byte) => Ok(byte),
             _ => Err(Error::new(ErrorKind::InvalidData, "Invalid byte tag"))
@@ -36,13 +42,20 @@ impl Tag {
         }
     }
 
-    pub fn to_u32(&self) -> Result<&u32, Error> {
+    pub fn to_i32(&self) -> Result<&i32, Error> {
         match self {
             Tag::Int(int) => Ok(int),
             _ => Err(Error::new(ErrorKind::InvalidData, "Invalid int tag"))
         }
     }
 
+    pub fn to_i64(&self) -> Result<&i64, Error> {
+        match self {
+            Tag::Long(long) => Ok(long),
+            _ => Err(Error::new(ErrorKind::InvalidData, "Invalid long tag"))
+        }
+    }
+
     pub fn to_str(&self) -> Result<&str, Error> {
         match self {
             Tag::String(string) => Ok(string),
@@ -64,12 +77,29 @@ impl Tag {
         }
     }
 
-    pub fn to_long_array(&self) -> Result<&Vec<u64>, Error> {
+    pub fn to_long_array(&self) -> Result<&Vec<i64>, Error> {
         match self {
             Tag::LongArray(vec) => Ok(vec),
             _ => Err(Error::new(ErrorKind::InvalidData, "Invalid long array tag"))
         }
     }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
 }
 
 impl fmt::Display for Tag {
@@ -91,27 +121,72 @@ impl fmt::Display for Tag {
     }
 }
 
-fn read_string<R>(reader: &mut R) -> Result<String, Error> where R: Read {
-    let len = reader.read_u16::<BigEndian>()? as usize;
+// Java Edition NBT is big-endian with strings in Java's modified UTF-8,
+// while Bedrock Edition NBT is little-endian with standard UTF-8 strings.
+trait Encoding: ByteOrder {
+    fn decode_string(bytes: Vec<u8>) -> Result<String, Error>;
+}
+
+impl Encoding for BigEndian {
+    fn decode_string(bytes: Vec<u8>) -> Result<String, Error> {
+        mutf8::decode(bytes)
+    }
+}
+
+impl Encoding for LittleEndian {
+    fn decode_string(bytes: Vec<u8>) -> Result<String, Error> {
+        String::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidData, "Bad string."))
+    }
+}
+
+fn read_string<R, E>(reader: &mut R) -> Result<String, Error> where R: Read, E: Encoding {
+    let len = reader.read_u16::<E>()? as usize;
     Ok(if len == 0 {
         "".to_string()
     } else {
         let mut bytes = vec![0u8; len];
         reader.read_exact(&mut bytes)?;
-        String::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidData, "Bad string."))?
+        E::decode_string(bytes)?
     })
 }
 
 pub fn read_tag_header<R>(reader: &mut R) -> Result<(u8, String), Error> where R: Read {
+    read_tag_header_with::<R, BigEndian>(reader)
+}
+
+// Bedrock Edition stores NBT in little-endian byte order.
+pub fn read_le_tag_header<R>(reader: &mut R) -> Result<(u8, String), Error> where R: Read {
+    read_tag_header_with::<R, LittleEndian>(reader)
+}
+
+fn read_tag_header_with<R, E>(reader: &mut R) -> Result<(u8, String), Error>
+where R: Read, E: Encoding {
     let id = reader.read_u8()?;
     let name = match id {
         0 => "".to_string(),
-        _ => read_string(reader)?,
+        _ => read_string::<R, E>(reader)?,
     };
     Ok((id, name))
 }
 
-fn skip_tag_payload<R>(reader: &mut R, id: &u8) -> Result<(), Error> where R: Read {
+// Minecraft refuses NBT nested more deeply than this. Lists and compounds are read
+// recursively, so corrupt data nested without a limit could overflow the stack.
+pub const MAX_DEPTH: usize = 512;
+
+pub fn check_depth(depth: usize) -> Result<(), Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::new(ErrorKind::InvalidData, "NBT data is nested too deeply"));
+    }
+    Ok(())
+}
+
+pub fn skip_tag_payload<R>(reader: &mut R, id: &u8) -> Result<(), Error> where R: Read {
+    skip_tag_payload_at(reader, id, 0)
+}
+
+pub fn skip_tag_payload_at<R>(reader: &mut R, id: &u8, depth: usize) -> Result<(), Error>
+where R: Read {
+    check_depth(depth)?;
     match id {
         1 => { reader.read_u8()?; },
         2 => { reader.read_u16::<BigEndian>()?; },
@@ -131,7 +206,7 @@ fn skip_tag_payload<R>(reader: &mut R, id: &u8) -> Result<(), Error> where R: Re
             let list_id = reader.read_u8()?;
             let len = reader.read_u32::<BigEndian>()? as usize;
             for _ in 0..len {
-                skip_tag_payload(reader, &list_id)?;
+                skip_tag_payload_at(reader, &list_id, depth + 1)?;
             }
         },
         10 => {
@@ -140,7 +215,7 @@ fn skip_tag_payload<R>(reader: &mut R, id: &u8) -> Result<(), Error> where R: Re
                 if sub_id == 0 {
                     break;
                 }
-                skip_tag_payload(reader, &sub_id)?;
+                skip_tag_payload_at(reader, &sub_id, depth + 1)?;
             }
         },
         11 => {
@@ -161,53 +236,53 @@ fn skip_tag_payload<R>(reader: &mut R, id: &u8) -> Result<(), Error> where R: Re
 }
 
 pub fn read_tag_payload<R>(reader: &mut R, id: &u8) -> Result<Tag, Error> where R: Read {
+    read_tag_payload_with::<R, BigEndian>(reader, id, 0)
+}
+
+pub fn read_le_tag_payload<R>(reader: &mut R, id: &u8) -> Result<Tag, Error> where R: Read {
+    read_tag_payload_with::<R, LittleEndian>(reader, id, 0)
+}
+
+fn read_tag_payload_with<R, E>(reader: &mut R, id: &u8, depth: usize) -> Result<Tag, Error>
+where R: Read, E: Encoding {
+    check_depth(depth)?;
     Ok(match id {
-        1 => Tag::Byte(reader.read_u8()?),
-        2 => Tag::Short(reader.read_u16::<BigEndian>()?),
-        3 => Tag::Int(reader.read_u32::<BigEndian>()?),
-        4 => Tag::Long(reader.read_u64::<BigEndian>()?),
-        5 => Tag::Float(reader.read_f32::<BigEndian>()?),
-        6 => Tag::Double(reader.read_f64::<BigEndian>()?),
+        1 => Tag::Byte(reader.read_i8()?),
+        2 => Tag::Short(reader.read_i16::<E>()?),
+        3 => Tag::Int(reader.read_i32::<E>()?),
+        4 => Tag::Long(reader.read_i64::<E>()?),
+        5 => Tag::Float(reader.read_f32::<E>()?),
+        6 => Tag::Double(reader.read_f64::<E>()?),
         7 => {
-            let len = reader.read_u32::<BigEndian>()? as usize;
+            let len = reader.read_u32::<E>()? as usize;
             let mut array = vec![0u8; len];
             reader.read_exact(&mut array)?;
             Tag::ByteArray(array)
         },
-        8 => {
-            let len = reader.read_u16::<BigEndian>()? as usize;
-            if len == 0 {
-                Tag::String("".to_string())
-            } else {
-                let mut bytes = vec![0u8; len];
-                reader.read_exact(&mut bytes)?;
-                Tag::String(String::from_utf8(bytes)
-                    .map_err(|_| Error::new(ErrorKind::InvalidData, "Bad string."))?)
-            }
-        },
+        8 => Tag::String(read_string::<R, E>(reader)?),
         9 => {
             let sub_id = reader.read_u8()?;
-            let len = reader.read_u32::<BigEndian>()? as usize;
+            let len = reader.read_u32::<E>()? as usize;
             let mut list = Vec::new();
             for _ in 0..len {
-                list.push(read_tag_payload(reader, &sub_id)?);
+                list.push(read_tag_payload_with::<R, E>(reader, &sub_id, depth + 1)?);
             }
             Tag::List(list)
         },
-        10 => Tag::Compound(read_compound_tag(reader)?),
+        10 => Tag::Compound(read_compound_tag::<R, E>(reader, depth)?),
         11 => {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            let mut array = vec![0u32; len];
+            let len = reader.read_u32::<E>()? as usize;
+            let mut array = vec![0i32; len];
             for i in 0..len {
-                array[i] = reader.read_u32::<BigEndian>()?;
+                array[i] = reader.read_i32::<E>()?;
             }
             Tag::IntArray(array)
         },
         12 => {
-            let len = reader.read_u32::<BigEndian>()? as usize;
-            let mut array = vec![0u64; len];
+            let len = reader.read_u32::<E>()? as usize;
+            let mut array = vec![0i64; len];
             for i in 0..len {
-                array[i] = reader.read_u64::<BigEndian>()?;
+                array[i] = reader.read_i64::<E>()?;
             }
             Tag::LongArray(array)
         },
@@ -215,26 +290,6 @@ pub fn read_tag_payload<R>(reader: &mut R, id: &u8) -> Result<Tag, Error> where
     })
 }
 
-pub fn seek_compound_tag_names<R>(reader: &mut R, names: Vec<&str>)
--> Result<Option<(u8, String)>, Error> where R: Read {
-    loop {
-        let (id, name) = read_tag_header(reader)?;
-        // println!("Found subtag: {} {}", id, name);
-
-        if id == 0 {
-            return Ok(None);
-        }
-        if id > 12 {
-            return Err(Error::new(ErrorKind::InvalidData, format!("Invalid tag id: {}", id)));
-        }
-        if names.contains(&name.as_str()) {
-            return Ok(Some((id, name)));
-        }
-
-        skip_tag_payload(reader, &id)?;
-    }
-}
-
 pub fn seek_compound_tag_name<R>(reader: &mut R, tag_name: &str)
 -> Result<Option<(u8, String)>, Error> where R: Read {
     loop {
@@ -255,44 +310,288 @@ pub fn seek_compound_tag_name<R>(reader: &mut R, tag_name: &str)
     }
 }
 
-pub fn read_compound_tag<R>(reader: &mut R) -> Result<HashMap<String, Tag>, Error> where R: Read {
+fn read_compound_tag<R, E>(reader: &mut R, depth: usize) -> Result<HashMap<String, Tag>, Error>
+where R: Read, E: Encoding {
     let mut values = HashMap::new();
 
     loop {
-        let (id, name) = read_tag_header(reader)?;
+        let (id, name) = read_tag_header_with::<R, E>(reader)?;
         // println!("Found subtag: {} {}", id, name);
 
         if id == 0 {
             break;
         }
-        values.insert(name, read_tag_payload(reader, &id)?);
+        values.insert(name, read_tag_payload_with::<R, E>(reader, &id, depth + 1)?);
     }
 
     Ok(values)
 }
 
-pub fn read_compound_tag_names<R>(reader: &mut R, names: Vec<&str>)
--> Result<HashMap<String, Tag>, Error> where R: Read {
-    let mut values = HashMap::new();
+pub fn read_list_length<R>(reader: &mut R) -> Result<usize, Error> where R: Read {
+    reader.read_u8()?;
+    Ok(reader.read_u32::<BigEndian>()? as usize)
+}
 
-    loop {
-        let (id, name) = read_tag_header(reader)?;
-        // println!("Found subtag: {} {}", id, name);
+fn write_string<W>(writer: &mut W, value: &str) -> Result<(), Error> where W: Write {
+    let bytes = mutf8::encode(value);
+    if bytes.len() > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "String is too long for NBT"));
+    }
+    writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+    writer.write_all(&bytes)
+}
 
-        if id == 0 {
-            break;
+fn write_length<W>(writer: &mut W, len: usize) -> Result<(), Error> where W: Write {
+    if len > i32::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "Array is too long for NBT"));
+    }
+    writer.write_u32::<BigEndian>(len as u32)
+}
+
+pub fn write_tag_header<W>(writer: &mut W, id: u8, name: &str) -> Result<(), Error>
+where W: Write {
+    writer.write_u8(id)?;
+    if id != 0 {
+        write_string(writer, name)?;
+    }
+    Ok(())
+}
+
+pub fn write_tag_payload<W>(writer: &mut W, tag: &Tag) -> Result<(), Error> where W: Write {
+    match tag {
+        Tag::Byte(value) => writer.write_i8(*value)?,
+        Tag::Short(value) => writer.write_i16::<BigEndian>(*value)?,
+        Tag::Int(value) => writer.write_i32::<BigEndian>(*value)?,
+        Tag::Long(value) => writer.write_i64::<BigEndian>(*value)?,
+        Tag::Float(value) => writer.write_f32::<BigEndian>(*value)?,
+        Tag::Double(value) => writer.write_f64::<BigEndian>(*value)?,
+        Tag::ByteArray(array) => {
+            write_length(writer, array.len())?;
+            writer.write_all(array)?;
+        },
+        Tag::String(value) => write_string(writer, value)?,
+        Tag::List(list) => {
+            // Empty lists are written with the end tag as their element type.
+            let sub_id = list.first().map_or(0, |tag| tag.id());
+            if list.iter().any(|tag| tag.id() != sub_id) {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    "List elements must all have the same type"));
+            }
+            writer.write_u8(sub_id)?;
+            write_length(writer, list.len())?;
+            for tag in list {
+                write_tag_payload(writer, tag)?;
+            }
+        },
+        Tag::Compound(values) => {
+            for (name, tag) in values {
+                write_tag_header(writer, tag.id(), name)?;
+                write_tag_payload(writer, tag)?;
+            }
+            writer.write_u8(0)?;
+        },
+        Tag::IntArray(array) => {
+            write_length(writer, array.len())?;
+            for value in array {
+                writer.write_i32::<BigEndian>(*value)?;
+            }
+        },
+        Tag::LongArray(array) => {
+            write_length(writer, array.len())?;
+            for value in array {
+                writer.write_i64::<BigEndian>(*value)?;
+            }
+        },
+    };
+    Ok(())
+}
+
+// Write a named root tag, as found at the start of .dat files and chunks.
+pub fn write_root_tag<W>(writer: &mut W, name: &str, tag: &Tag) -> Result<(), Error>
+where W: Write {
+    write_tag_header(writer, tag.id(), name)?;
+    write_tag_payload(writer, tag)
+}
+
+// Write a tag to an NBT file as an unnamed root tag, compressed with one of the compression types
+// used for chunks. .dat files are gzipped.
+pub fn write_nbt_file(path: &Path, tag: &Tag, compression: u8) -> Result<(), Error> {
+    let mut bytes = vec![];
+    write_root_tag(&mut bytes, "", tag)?;
+    std::fs::write(path, compression::compress(compression, &bytes)?)
+}
+
+// Helpers for building NBT data in tests.
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+
+    pub fn compound(values: Vec<(&str, Tag)>) -> Tag {
+        Tag::Compound(values.into_iter().map(|(key, tag)| (key.to_string(), tag)).collect())
+    }
+
+    // Write a tag as an unnamed root tag, the way files and chunks store it.
+    pub fn to_bytes(tag: &Tag) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_root_tag(&mut bytes, "", tag).unwrap();
+        bytes
+    }
+
+    // The payload of a list nested in as many lists as the depth given, with an empty list
+    // of bytes at the bottom. Building it as a Tag would need as deep a recursion to drop it.
+    pub fn nested_lists(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        for _ in 0..depth {
+            bytes.extend_from_slice(&[9, 0, 0, 0, 1]);
         }
-        if names.contains(&name.as_str()) {
-            values.insert(name, read_tag_payload(reader, &id)?);
-        } else {
-            skip_tag_payload(reader, &id)?;
+        bytes.extend_from_slice(&[1, 0, 0, 0, 0]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::compression;
+
+    fn sample_tag() -> Tag {
+        let mut level = HashMap::new();
+        level.insert("xPos".to_string(), Tag::Int(-3));
+        level.insert("LastUpdate".to_string(), Tag::Long(1 << 40));
+        level.insert("Empty".to_string(), Tag::List(vec![]));
+        level.insert("Nested".to_string(), Tag::List(vec![
+            Tag::List(vec![Tag::Short(1), Tag::Short(-1)]),
+            Tag::List(vec![]),
+        ]));
+        level.insert("Sections".to_string(), Tag::List(vec![
+            Tag::Compound(vec![
+                ("Y".to_string(), Tag::Byte(-4)),
+                ("BlockStates".to_string(), Tag::LongArray(vec![0, i64::MIN, -12345])),
+            ].into_iter().collect()),
+            Tag::Compound(HashMap::new()),
+        ]));
+
+        let mut root = HashMap::new();
+        root.insert("Level".to_string(), Tag::Compound(level));
+        root.insert("DataVersion".to_string(), Tag::Int(2975));
+        root.insert("Float".to_string(), Tag::Float(0.5));
+        root.insert("Double".to_string(), Tag::Double(-1.25e100));
+        root.insert("Bytes".to_string(), Tag::ByteArray(vec![0, 1, 127, 128, 255]));
+        root.insert("Ints".to_string(), Tag::IntArray(vec![]));
+        root.insert("Name".to_string(), Tag::String("minecraft:stone_bricks ✓".to_string()));
+        Tag::Compound(root)
+    }
+
+    fn read_root_tag(bytes: Vec<u8>) -> Result<(String, Tag), Error> {
+        let mut reader = Cursor::new(bytes);
+        let (id, name) = read_tag_header(&mut reader)?;
+        let tag = read_tag_payload(&mut reader, &id)?;
+        assert_eq!(reader.position() as usize, reader.get_ref().len());
+        Ok((name, tag))
+    }
+
+    #[test]
+    fn round_trip() {
+        let tag = sample_tag();
+        let mut bytes = Vec::new();
+        write_root_tag(&mut bytes, "root", &tag).unwrap();
+        assert_eq!(read_root_tag(bytes).unwrap(), ("root".to_string(), tag));
+    }
+
+    #[test]
+    fn round_trip_every_type() {
+        let tags = vec![
+            Tag::Byte(7), Tag::Short(300), Tag::Int(70000), Tag::Long(1 << 50), Tag::Float(1.5),
+            Tag::Double(2.25), Tag::ByteArray(vec![1, 2]), Tag::String(String::new()),
+            Tag::List(vec![Tag::Byte(1)]), Tag::Compound(HashMap::new()),
+            Tag::IntArray(vec![1, -1]), Tag::LongArray(vec![i64::MAX]),
+        ];
+        for tag in tags {
+            let mut bytes = Vec::new();
+            write_tag_payload(&mut bytes, &tag).unwrap();
+            let mut reader = Cursor::new(bytes);
+            assert_eq!(read_tag_payload(&mut reader, &tag.id()).unwrap(), tag);
         }
     }
 
-    Ok(values)
-}
+    #[test]
+    fn round_trip_compressed() {
+        let tag = sample_tag();
+        let mut bytes = Vec::new();
+        write_root_tag(&mut bytes, "", &tag).unwrap();
+        for compression in &[compression::GZIP, compression::ZLIB, compression::UNCOMPRESSED] {
+            let data = compression::compress(*compression, &bytes).unwrap();
+            let data = compression::decompress_chunk(*compression, data).unwrap();
+            assert_eq!(read_root_tag(data).unwrap(), ("".to_string(), sample_tag()));
+        }
+    }
 
-pub fn read_list_length<R>(reader: &mut R) -> Result<usize, Error> where R: Read {
-    reader.read_u8()?;
-    Ok(reader.read_u32::<BigEndian>()? as usize)
+    #[test]
+    fn write_gzipped_file() {
+        let path = std::env::temp_dir().join(format!("rmapbash-nbt-{}.dat", std::process::id()));
+        write_nbt_file(&path, &sample_tag(), compression::GZIP).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(&[0x1f, 0x8b]));
+        let data = crate::data::read_nbt_file(&path).unwrap().into_inner();
+        assert_eq!(read_root_tag(data).unwrap(), ("".to_string(), sample_tag()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn nesting_limit() {
+        use testing::nested_lists;
+
+        assert!(read_tag_payload(&mut Cursor::new(nested_lists(MAX_DEPTH)), &9).is_ok());
+        let err = read_tag_payload(&mut Cursor::new(nested_lists(MAX_DEPTH + 1)), &9)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(read_le_tag_payload(&mut Cursor::new(nested_lists(100_000)), &9).is_err());
+
+        assert!(skip_tag_payload(&mut Cursor::new(nested_lists(MAX_DEPTH)), &9).is_ok());
+        let err = skip_tag_payload(&mut Cursor::new(nested_lists(MAX_DEPTH + 1)), &9)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn strings_use_modified_utf8() {
+        let tag = Tag::String("Sign text 😀\0".to_string());
+        let mut bytes = Vec::new();
+        write_tag_payload(&mut bytes, &tag).unwrap();
+        assert_eq!(&bytes[..2], &[0, 18]);
+        assert_eq!(&bytes[12..], &[0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80, 0xc0, 0x80]);
+        assert_eq!(read_tag_payload(&mut Cursor::new(bytes), &8).unwrap(), tag);
+    }
+
+    #[test]
+    fn bedrock_strings_use_utf8() {
+        let mut bytes = vec![4, 0];
+        bytes.extend("😀".as_bytes());
+        assert_eq!(read_le_tag_payload(&mut Cursor::new(bytes), &8).unwrap(),
+            Tag::String("😀".to_string()));
+        assert!(read_le_tag_payload(&mut Cursor::new(vec![2, 0, 0xc0, 0x80]), &8).is_err());
+    }
+
+    #[test]
+    fn empty_list_uses_end_tag() {
+        let mut bytes = Vec::new();
+        write_tag_payload(&mut bytes, &Tag::List(vec![])).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn mixed_list_is_an_error() {
+        let mut bytes = Vec::new();
+        let tag = Tag::List(vec![Tag::Byte(1), Tag::Int(1)]);
+        assert!(write_tag_payload(&mut bytes, &tag).is_err());
+    }
+
+    #[test]
+    fn long_string_is_an_error() {
+        let mut bytes = Vec::new();
+        let tag = Tag::String("a".repeat(u16::MAX as usize + 1));
+        assert!(write_tag_payload(&mut bytes, &tag).is_err());
+    }
 }
@@ -0,0 +1,276 @@
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::mutf8;
+use super::nbt;
+
+// A tag borrowed from a buffer of big-endian NBT data, like a decompressed chunk. Unlike
+// nbt::Tag, names, strings and arrays point into the buffer instead of being copied out of it.
+#[derive(Debug, PartialEq)]
+pub enum TagRef<'a> {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(&'a [u8]),
+    String(Cow<'a, str>),
+    List(Vec<TagRef<'a>>),
+    Compound(CompoundRef<'a>),
+    IntArray(IntArrayRef<'a>),
+    LongArray(LongArrayRef<'a>),
+}
+
+// A compound's entries, in the order they were saved. Compounds in chunks only have a few
+// entries, so finding one by name is quicker than hashing them all.
+#[derive(Debug, PartialEq)]
+pub struct CompoundRef<'a>(Vec<(Cow<'a, str>, TagRef<'a>)>);
+
+// Arrays of big-endian numbers, which are only converted as they are read.
+#[derive(Debug, PartialEq)]
+pub struct IntArrayRef<'a>(&'a [u8]);
+
+#[derive(Debug, PartialEq)]
+pub struct LongArrayRef<'a>(&'a [u8]);
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl<'a> TagRef<'a> {
+    pub fn to_i8(&self) -> Result<i8, Error> {
+        match self {
+            TagRef::Byte(byte) => Ok(*byte),
+            _ => Err(invalid_data("Invalid byte tag")),
+        }
+    }
+
+    pub fn to_byte_array(&self) -> Result<&'a [u8], Error> {
+        match self {
+            TagRef::ByteArray(array) => Ok(array),
+            _ => Err(invalid_data("Invalid byte array tag")),
+        }
+    }
+
+    pub fn to_str(&self) -> Result<&str, Error> {
+        match self {
+            TagRef::String(string) => Ok(string),
+            _ => Err(invalid_data("Invalid string tag")),
+        }
+    }
+
+    pub fn to_list(&self) -> Result<&[TagRef<'a>], Error> {
+        match self {
+            TagRef::List(list) => Ok(list),
+            _ => Err(invalid_data("Invalid list tag")),
+        }
+    }
+
+    pub fn to_compound(&self) -> Result<&CompoundRef<'a>, Error> {
+        match self {
+            TagRef::Compound(compound) => Ok(compound),
+            _ => Err(invalid_data("Invalid compound tag")),
+        }
+    }
+
+    pub fn to_long_array(&self) -> Result<&LongArrayRef<'a>, Error> {
+        match self {
+            TagRef::LongArray(array) => Ok(array),
+            _ => Err(invalid_data("Invalid long array tag")),
+        }
+    }
+}
+
+impl<'a> CompoundRef<'a> {
+    pub fn get(&self, name: &str) -> Option<&TagRef<'a>> {
+        self.0.iter().find(|(key, _)| key == name).map(|(_, tag)| tag)
+    }
+
+    pub fn get_tag(&self, name: &str) -> Result<&TagRef<'a>, Error> {
+        self.get(name).ok_or_else(|| invalid_data(&format!("Missing {} tag", name)))
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TagRef<'a>)> {
+        self.0.iter().map(|(key, tag)| (key.as_ref(), tag))
+    }
+}
+
+impl<'a> IntArrayRef<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = i32> + 'a {
+        self.0.chunks_exact(4).map(BigEndian::read_i32)
+    }
+}
+
+impl<'a> LongArrayRef<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len() / 8
+    }
+
+    pub fn get(&self, i: usize) -> i64 {
+        BigEndian::read_i64(&self.0[(i * 8)..])
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid_data("Unexpected end of NBT data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_length(&mut self, size: usize) -> Result<usize, Error> {
+        let len = BigEndian::read_i32(self.take(4)?).max(0) as usize;
+        if len * size > self.data.len() - self.pos {
+            return Err(invalid_data("Unexpected end of NBT data"));
+        }
+        Ok(len)
+    }
+
+    fn read_string(&mut self) -> Result<Cow<'a, str>, Error> {
+        let len = BigEndian::read_u16(self.take(2)?) as usize;
+        let bytes = self.take(len)?;
+        // Modified UTF-8 only differs from UTF-8 in how it stores nulls and characters outside
+        // the BMP, which are invalid UTF-8, so any string that is valid UTF-8 can be borrowed.
+        Ok(match std::str::from_utf8(bytes) {
+            Ok(string) => Cow::Borrowed(string),
+            Err(_) => Cow::Owned(mutf8::decode(bytes.to_vec())?),
+        })
+    }
+
+    fn read_payload(&mut self, id: u8, depth: usize) -> Result<TagRef<'a>, Error> {
+        nbt::check_depth(depth)?;
+        Ok(match id {
+            1 => TagRef::Byte(self.read_u8()? as i8),
+            2 => TagRef::Short(BigEndian::read_i16(self.take(2)?)),
+            3 => TagRef::Int(BigEndian::read_i32(self.take(4)?)),
+            4 => TagRef::Long(BigEndian::read_i64(self.take(8)?)),
+            5 => TagRef::Float(BigEndian::read_f32(self.take(4)?)),
+            6 => TagRef::Double(BigEndian::read_f64(self.take(8)?)),
+            7 => {
+                let len = self.read_length(1)?;
+                TagRef::ByteArray(self.take(len)?)
+            },
+            8 => TagRef::String(self.read_string()?),
+            9 => {
+                let sub_id = self.read_u8()?;
+                // Every element takes at least a byte, except for empty compounds and lists.
+                let len = self.read_length(0)?;
+                let mut list = Vec::with_capacity(len.min(self.data.len() - self.pos));
+                for _ in 0..len {
+                    list.push(self.read_payload(sub_id, depth + 1)?);
+                }
+                TagRef::List(list)
+            },
+            10 => {
+                let mut entries = Vec::new();
+                loop {
+                    let sub_id = self.read_u8()?;
+                    if sub_id == 0 {
+                        break;
+                    }
+                    let name = self.read_string()?;
+                    entries.push((name, self.read_payload(sub_id, depth + 1)?));
+                }
+                TagRef::Compound(CompoundRef(entries))
+            },
+            11 => {
+                let len = self.read_length(4)?;
+                TagRef::IntArray(IntArrayRef(self.take(len * 4)?))
+            },
+            12 => {
+                let len = self.read_length(8)?;
+                TagRef::LongArray(LongArrayRef(self.take(len * 8)?))
+            },
+            _ => return Err(invalid_data(&format!("Invalid tag id: {}", id))),
+        })
+    }
+}
+
+// Read a tag's payload from a buffer, starting at the given position, such as just after the
+// root tag's header.
+pub fn read_payload(data: &[u8], pos: usize, id: u8) -> Result<TagRef<'_>, Error> {
+    Parser { data, pos }.read_payload(id, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::Tag;
+    use crate::nbt::testing::{compound, nested_lists, to_bytes};
+
+    #[test]
+    fn borrow_from_buffer() {
+        let tag = compound(vec![
+            ("Status", Tag::String("minecraft:full".to_string())),
+            ("sections", Tag::List(vec![compound(vec![
+                ("Y", Tag::Byte(-4)),
+                ("SkyLight", Tag::ByteArray(vec![0xff, 0x0f])),
+                ("data", Tag::LongArray(vec![-1, 2])),
+                ("Biomes", Tag::IntArray(vec![1, -2])),
+            ])])),
+        ]);
+        let bytes = to_bytes(&tag);
+        // The root header is the tag ID and an empty name.
+        let root = read_payload(&bytes, 3, 10).unwrap();
+        let root = root.to_compound().unwrap();
+
+        let status = root.get_tag("Status").unwrap();
+        assert!(matches!(status, TagRef::String(Cow::Borrowed("minecraft:full"))));
+        let section = root.get_tag("sections").unwrap().to_list().unwrap()[0].to_compound()
+            .unwrap();
+        assert_eq!(section.get_tag("Y").unwrap().to_i8().unwrap(), -4);
+        assert_eq!(section.get_tag("SkyLight").unwrap().to_byte_array().unwrap(), [0xff, 0x0f]);
+        let data = section.get_tag("data").unwrap().to_long_array().unwrap();
+        assert_eq!((data.len(), data.get(0), data.get(1)), (2, -1, 2));
+        match section.get_tag("Biomes").unwrap() {
+            TagRef::IntArray(biomes) => assert_eq!(biomes.iter().collect::<Vec<_>>(), [1, -2]),
+            _ => panic!("Expected an int array"),
+        }
+        assert!(!section.contains_key("BlockLight"));
+        assert!(section.get_tag("BlockLight").is_err());
+    }
+
+    #[test]
+    fn modified_utf8_strings() {
+        let bytes = to_bytes(&compound(vec![("name", Tag::String("a\0😀".to_string()))]));
+        let root = read_payload(&bytes, 3, 10).unwrap();
+        let name = root.to_compound().unwrap().get_tag("name").unwrap();
+        assert!(matches!(name, TagRef::String(Cow::Owned(name)) if name == "a\0😀"));
+    }
+
+    #[test]
+    fn truncated_data() {
+        let bytes = to_bytes(&compound(vec![("data", Tag::LongArray(vec![1, 2, 3]))]));
+        for len in 3..bytes.len() {
+            let err = read_payload(&bytes[..len], 3, 10).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        assert!(read_payload(&bytes, 3, 13).is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        assert!(read_payload(&nested_lists(nbt::MAX_DEPTH), 0, 9).is_ok());
+        let err = read_payload(&nested_lists(nbt::MAX_DEPTH + 1), 0, 9).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(read_payload(&nested_lists(100_000), 0, 9).is_err());
+    }
+}
@@ -32,13 +32,34 @@ impl fmt::Display for Lighting {
     }
 }
 
+// How to draw chunks that haven't finished generating.
+#[derive(Debug, PartialEq)]
+pub enum PartialChunks {
+    Show,
+    Hide,
+    Tint,
+}
+
+impl fmt::Display for PartialChunks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 pub struct Options<'a> {
     pub inpath: &'a Path,
     pub outpath: &'a Path,
     pub view: View,
-    pub lighting: Lighting,
+    pub night: bool,
+    pub dimension: Option<&'a str>,
+    pub partial: PartialChunks,
     pub blimits: Option<Edges<isize>>,
-    pub ylimits: Range<usize>,
+    // Defaults to the dimension's height range.
+    pub ylimits: Option<Range<usize>>,
+    pub spawn: bool,
+    pub players: bool,
+    pub usercache: Option<&'a Path>,
+    pub incremental: bool,
 }
 
 pub fn get_options<'a>(matches: &'a clap::ArgMatches) -> Options<'a> {
@@ -49,10 +70,12 @@ pub fn get_options<'a>(matches: &'a clap::ArgMatches) -> Options<'a> {
         inpath,
         outpath,
         view: if matches.is_present("i") { View::Isometric } else { View::Orthographic },
-        lighting: match inpath.file_stem().unwrap().to_str() {
-            Some("DIM-1") => Lighting::Nether,
-            Some("DIM1") => Lighting::End,
-            _ => if matches.is_present("n") { Lighting::Night } else { Lighting::Day },
+        night: matches.is_present("n"),
+        dimension: matches.value_of("d"),
+        partial: match matches.value_of("p") {
+            Some("hide") => PartialChunks::Hide,
+            Some("tint") => PartialChunks::Tint,
+            _ => PartialChunks::Show,
         },
         blimits: matches.values_of("b").and_then(|mut b| {
             let x1 = b.next().unwrap().parse::<isize>().unwrap();
@@ -66,13 +89,14 @@ pub fn get_options<'a>(matches: &'a clap::ArgMatches) -> Options<'a> {
                 w: min(x1, x2),
             })
         }),
-        ylimits: match matches.values_of("y") {
-            Some(mut y) => {
-                let y1 = min(y.next().unwrap().parse::<usize>().unwrap(), MAX_BLOCK_IN_CHUNK_Y);
-                let y2 = min(y.next().unwrap().parse::<usize>().unwrap(), MAX_BLOCK_IN_CHUNK_Y);
-                min(y1, y2)..(max(y1, y2) + 1)
-            },
-            None => 0..BLOCKS_IN_CHUNK_Y,
-        },
+        ylimits: matches.values_of("y").map(|mut y| {
+            let y1 = block_y_to_offset(y.next().unwrap().parse::<isize>().unwrap());
+            let y2 = block_y_to_offset(y.next().unwrap().parse::<isize>().unwrap());
+            min(y1, y2)..(max(y1, y2) + 1)
+        }),
+        spawn: matches.is_present("s"),
+        players: matches.is_present("P"),
+        usercache: matches.value_of("u").map(Path::new),
+        incremental: matches.is_present("I"),
     }
 }
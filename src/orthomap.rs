@@ -1,3 +1,4 @@
+use std::cmp::{min, max};
 use std::ops::Range;
 
 use super::blocktypes::BlockType;
@@ -32,7 +33,10 @@ pub fn draw_chunk(pixels: &mut [u8], blocktypes: &[BlockType], chunk: &region::C
     for bz in cblimits.n..(cblimits.s + 1) {
         for bx in cblimits.w..(cblimits.e + 1) {
             let po = (co + (bz * width + bx) as isize) as usize * 4;
-            let color = get_block_color(bx, bz, blocktypes, chunk, ylimits);
+            let mut color = get_block_color(bx, bz, blocktypes, chunk, ylimits);
+            if let Some(tint) = &chunk.tint {
+                color = color::tint_color(&color, tint);
+            }
             pixels[po] = color.r;
             pixels[po + 1] = color.g;
             pixels[po + 2] = color.b;
@@ -78,7 +82,7 @@ fn get_block_color(bx: usize, bz: usize, blocktypes: &[BlockType], chunk: &regio
             (false, true) => 3,
             _ => 1,
         };
-        let blockcolor = &blocktype.colors[biome][tblock.slight][tblock.blight][shade];
+        let blockcolor = &blocktype.get_colors(biome)[tblock.slight][tblock.blight][shade];
 
         color = color::blend_alpha_color(&color, blockcolor);
         if color.a == MAX_CHANNEL_VALUE {
@@ -88,3 +92,62 @@ fn get_block_color(bx: usize, bz: usize, blocktypes: &[BlockType], chunk: &regio
 
     color
 }
+
+// Clear the part of the map covered by a region, so that it can be drawn again.
+pub fn clear_region(pixels: &mut [u8], world: &World, width: &usize, r: &Pair<isize>) {
+    let rbedges = Edges {
+        n: max(r.z * BLOCKS_IN_REGION as isize, world.bedges.n),
+        e: min((r.x + 1) * BLOCKS_IN_REGION as isize - 1, world.bedges.e),
+        s: min((r.z + 1) * BLOCKS_IN_REGION as isize - 1, world.bedges.s),
+        w: max(r.x * BLOCKS_IN_REGION as isize, world.bedges.w),
+    };
+    if rbedges.n > rbedges.s || rbedges.w > rbedges.e {
+        return;
+    }
+    let len = (rbedges.e - rbedges.w + 1) as usize * 4;
+    for bz in rbedges.n..(rbedges.s + 1) {
+        let po = ((bz - world.bedges.n) as usize * width + (rbedges.w - world.bedges.w) as usize)
+            * 4;
+        pixels[po..(po + len)].fill(0);
+    }
+}
+
+// Move a map drawn with some block edges onto a blank map with other edges, leaving out the
+// parts that are no longer within them.
+pub fn move_map(pixels: &[u8], from: &Edges<isize>, to: &Edges<isize>) -> Vec<u8> {
+    let fromsize = from.size();
+    let tosize = to.size();
+    let mut moved = vec![0u8; tosize.x * tosize.z * 4];
+
+    let w = max(from.w, to.w);
+    let e = min(from.e, to.e);
+    if w > e {
+        return moved;
+    }
+    let len = (e - w + 1) as usize * 4;
+    for bz in max(from.n, to.n)..(min(from.s, to.s) + 1) {
+        let fo = ((bz - from.n) as usize * fromsize.x + (w - from.w) as usize) * 4;
+        let mo = ((bz - to.n) as usize * tosize.x + (w - to.w) as usize) * 4;
+        moved[mo..(mo + len)].copy_from_slice(&pixels[fo..(fo + len)]);
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_maps() {
+        // A 3x2 map, with one byte per pixel standing for its 4 channels.
+        let from = Edges { n: 0, e: 2, s: 1, w: 0 };
+        let pixels: Vec<u8> = [1, 2, 3, 4, 5, 6].iter().flat_map(|p| [*p; 4]).collect();
+
+        let to = Edges { n: -1, e: 1, s: 1, w: -1 };
+        let moved: Vec<u8> = move_map(&pixels, &from, &to).chunks(4).map(|p| p[0]).collect();
+        assert_eq!(moved, [0, 0, 0, 0, 1, 2, 0, 4, 5]);
+
+        let to = Edges { n: 5, e: 5, s: 5, w: 5 };
+        assert_eq!(move_map(&pixels, &from, &to), [0; 4]);
+    }
+}
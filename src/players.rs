@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::data;
+use super::deserializer;
+use super::dimension::DimensionId;
+
+#[derive(Deserialize)]
+struct DeathLocation {
+    dimension: String,
+    pos: Vec<i32>,
+}
+
+// The parts of a player's .dat file that are marked on the map.
+#[derive(Deserialize)]
+struct PlayerDat {
+    #[serde(rename = "Pos")]
+    pos: Option<Vec<f64>>,
+    #[serde(rename = "Dimension")]
+    dimension: Option<DimensionId>,
+    #[serde(rename = "SpawnX")]
+    spawn_x: Option<i32>,
+    #[serde(rename = "SpawnY")]
+    spawn_y: Option<i32>,
+    #[serde(rename = "SpawnZ")]
+    spawn_z: Option<i32>,
+    #[serde(rename = "SpawnDimension")]
+    spawn_dimension: Option<DimensionId>,
+    // Since 1.19
+    #[serde(rename = "LastDeathLocation")]
+    last_death: Option<DeathLocation>,
+}
+
+#[derive(Deserialize)]
+struct UserCacheEntry {
+    name: String,
+    uuid: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    pub dimension: String,
+    pub x: isize,
+    pub y: isize,
+    pub z: isize,
+}
+
+impl Location {
+    fn new(dimension: &DimensionId, x: isize, y: isize, z: isize) -> Location {
+        Location { dimension: dimension.name().to_string(), x, y, z }
+    }
+}
+
+// A player file that couldn't be read, and why.
+pub type PlayerError = (String, Error);
+
+pub struct Player {
+    pub name: String,
+    pub position: Option<Location>,
+    pub spawn: Option<Location>,
+    pub death: Option<Location>,
+}
+
+impl PlayerDat {
+    fn into_player(self, name: String) -> Player {
+        let overworld = DimensionId::Id(0);
+        Player {
+            name,
+            position: match self.pos.as_deref() {
+                Some([x, y, z]) => Some(Location::new(
+                    self.dimension.as_ref().unwrap_or(&overworld),
+                    x.floor() as isize, y.floor() as isize, z.floor() as isize)),
+                _ => None,
+            },
+            // Players who haven't slept in a bed or set a respawn anchor have no spawn point.
+            spawn: match (self.spawn_x, self.spawn_y, self.spawn_z) {
+                (Some(x), Some(y), Some(z)) => Some(Location::new(
+                    self.spawn_dimension.as_ref().unwrap_or(&overworld),
+                    x as isize, y as isize, z as isize)),
+                _ => None,
+            },
+            death: self.last_death.and_then(|death| match death.pos[..] {
+                [x, y, z] => Some(Location::new(&DimensionId::Name(death.dimension),
+                    x as isize, y as isize, z as isize)),
+                _ => None,
+            }),
+        }
+    }
+}
+
+// Read a server's usercache.json, which maps the UUIDs of players who have joined to their names.
+pub fn read_user_cache(path: &Path) -> Result<HashMap<String, String>, Error> {
+    let entries: Vec<UserCacheEntry> = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    Ok(entries.into_iter().map(|entry| (entry.uuid, entry.name)).collect())
+}
+
+fn read_player_file(path: &Path) -> Result<PlayerDat, Error> {
+    deserializer::from_reader(&mut data::read_nbt_file(path)?)
+}
+
+// Read every player's .dat file in a save directory. Since 1.7.6 these are named by UUID in the
+// playerdata directory, and before that by player name in the players directory. Files that
+// can't be read are listed with their errors instead.
+pub fn read_players(savepath: &Path, names: &HashMap<String, String>)
+-> Result<(Vec<Player>, Vec<PlayerError>), Error> {
+    let mut players = vec![];
+    let mut errors = vec![];
+
+    for dirname in &["playerdata", "players"] {
+        let dirpath = savepath.join(dirname);
+        if !dirpath.is_dir() {
+            continue;
+        }
+
+        let mut paths: Vec<_> = std::fs::read_dir(dirpath)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+            let name = names.get(&stem).cloned().unwrap_or(stem);
+            match read_player_file(&path) {
+                Ok(player) => players.push(player.into_player(name)),
+                Err(err) => errors.push((name, err)),
+            }
+        }
+    }
+
+    Ok((players, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::dimension::{END, NETHER, OVERWORLD};
+    use crate::nbt::Tag;
+    use crate::nbt::testing::{compound, to_bytes};
+
+    fn read_player(tag: Tag) -> Player {
+        let player: PlayerDat = deserializer::from_reader(&mut Cursor::new(to_bytes(&tag)))
+            .unwrap();
+        player.into_player("Alex".to_string())
+    }
+
+    #[test]
+    fn read_player_locations() {
+        let player = read_player(compound(vec![
+            ("Pos", Tag::List(vec![Tag::Double(-10.5), Tag::Double(64.0), Tag::Double(3.2)])),
+            ("Dimension", Tag::String(NETHER.to_string())),
+            ("SpawnX", Tag::Int(100)),
+            ("SpawnY", Tag::Int(70)),
+            ("SpawnZ", Tag::Int(-200)),
+            ("LastDeathLocation", compound(vec![
+                ("dimension", Tag::String(END.to_string())),
+                ("pos", Tag::IntArray(vec![5, 50, 6])),
+            ])),
+            ("Inventory", Tag::List(vec![])),
+        ]));
+        assert_eq!(player.name, "Alex");
+        assert_eq!(player.position,
+            Some(Location { dimension: NETHER.to_string(), x: -11, y: 64, z: 3 }));
+        assert_eq!(player.spawn,
+            Some(Location { dimension: OVERWORLD.to_string(), x: 100, y: 70, z: -200 }));
+        assert_eq!(player.death,
+            Some(Location { dimension: END.to_string(), x: 5, y: 50, z: 6 }));
+    }
+
+    #[test]
+    fn read_old_player_locations() {
+        let player = read_player(compound(vec![
+            ("Pos", Tag::List(vec![Tag::Double(0.5), Tag::Double(80.0), Tag::Double(0.5)])),
+            ("Dimension", Tag::Int(1)),
+        ]));
+        assert_eq!(player.position,
+            Some(Location { dimension: END.to_string(), x: 0, y: 80, z: 0 }));
+        assert!(player.spawn.is_none());
+        assert!(player.death.is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use std::error::Error;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use super::data;
+use super::nbt;
+use super::nbt::Tag;
+use super::region;
+use super::snbt;
+use super::types::*;
+
+// One step of a query path: a key in a compound, or an index or every index in a list or array.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    All,
+}
+
+impl Step {
+    fn matches_index(&self, i: usize) -> bool {
+        match self {
+            Step::Index(index) => *index == i,
+            _ => true,
+        }
+    }
+}
+
+fn path_error(msg: &str, path: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, format!("{} in query path: {}", msg, path))
+}
+
+// Parse a query path like `Level.Sections[*].Palette[0].Name` into steps. Keys containing dots
+// or brackets can be quoted, as in `Data."key.with.dots"`. An empty path matches the root tag.
+fn parse_path(path: &str) -> Result<Vec<Step>, std::io::Error> {
+    let mut steps = vec![];
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(|| path_error("Unclosed bracket", path))?;
+            steps.push(match &after[..end] {
+                "*" => Step::All,
+                index => Step::Index(index.parse()
+                    .map_err(|_| path_error(&format!("Invalid index '{}'", index), path))?),
+            });
+            rest = &after[(end + 1)..];
+        } else {
+            // Dots separate keys from whatever comes before them.
+            if !steps.is_empty() {
+                rest = rest.strip_prefix('.').ok_or_else(|| path_error("Expected '.'", path))?;
+            }
+            let (key, after) = if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted.find('"').ok_or_else(|| path_error("Unclosed quote", path))?;
+                (&quoted[..end], &quoted[(end + 1)..])
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+            if key.is_empty() {
+                return Err(path_error("Empty key", path));
+            }
+            steps.push(Step::Key(key.to_string()));
+            rest = after;
+        }
+    }
+    Ok(steps)
+}
+
+fn join_key(location: &str, key: &str) -> String {
+    let key = if key.contains(['.', '[']) { format!("\"{}\"", key) }
+        else { key.to_string() };
+    if location.is_empty() { key } else { format!("{}.{}", location, key) }
+}
+
+// Follow the query steps through a tag's payload, reading only the tags that match and skipping
+// everything else, and collect the matching tags along with their full paths.
+fn query_payload<R>(reader: &mut R, id: u8, steps: &[Step], location: &str,
+    matches: &mut Vec<(String, Tag)>)
+-> Result<(), std::io::Error> where R: Read {
+    let (step, rest) = match steps.split_first() {
+        Some(split) => split,
+        None => {
+            matches.push((location.to_string(), nbt::read_tag_payload(reader, &id)?));
+            return Ok(());
+        },
+    };
+
+    match (step, id) {
+        (Step::Key(key), 10) => loop {
+            let (sub_id, name) = nbt::read_tag_header(reader)?;
+            if sub_id == 0 {
+                break;
+            }
+            if name == *key {
+                query_payload(reader, sub_id, rest, &join_key(location, key), matches)?;
+            } else {
+                nbt::skip_tag_payload(reader, &sub_id)?;
+            }
+        },
+        (Step::Index(_), 9) | (Step::All, 9) => {
+            let sub_id = reader.read_u8()?;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            for i in 0..len {
+                if step.matches_index(i) {
+                    let location = format!("{}[{}]", location, i);
+                    query_payload(reader, sub_id, rest, &location, matches)?;
+                } else {
+                    nbt::skip_tag_payload(reader, &sub_id)?;
+                }
+            }
+        },
+        // Array values can't be queried any further, so only match them at the end of the path.
+        (Step::Index(_), 7) | (Step::All, 7) | (Step::Index(_), 11) | (Step::All, 11) |
+        (Step::Index(_), 12) | (Step::All, 12) if rest.is_empty() => {
+            let values: Vec<Tag> = match nbt::read_tag_payload(reader, &id)? {
+                Tag::ByteArray(array) => array.into_iter().map(|v| Tag::Byte(v as i8)).collect(),
+                Tag::IntArray(array) => array.into_iter().map(Tag::Int).collect(),
+                Tag::LongArray(array) => array.into_iter().map(Tag::Long).collect(),
+                _ => vec![],
+            };
+            for (i, tag) in values.into_iter().enumerate() {
+                if step.matches_index(i) {
+                    matches.push((format!("{}[{}]", location, i), tag));
+                }
+            }
+        },
+        _ => nbt::skip_tag_payload(reader, &id)?,
+    };
+    Ok(())
+}
+
+// Find the tags matching a query path in an NBT file, or in a chunk if coords are given.
+pub fn run_query(inpath: &Path, chunk: Option<Pair<isize>>, path: &str)
+-> Result<(), Box<dyn Error>> {
+    let steps = parse_path(path)?;
+
+    let mut matches = vec![];
+    match chunk {
+        Some(c) => {
+            let (_, mut reader) = region::open_world_chunk(inpath, &c)?;
+            query_payload(&mut reader, 10, &steps, "", &mut matches)?;
+        },
+        None => {
+            let mut reader = data::read_nbt_file(inpath)?;
+            let (id, _) = nbt::read_tag_header(&mut reader)?;
+            query_payload(&mut reader, id, &steps, "", &mut matches)?;
+        },
+    };
+
+    for (location, tag) in &matches {
+        println!("{}: {}", location, snbt::format_tag(tag));
+    }
+    if matches.is_empty() {
+        println!("No matches for {}", path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn query(tag: &Tag, path: &str) -> Vec<(String, Tag)> {
+        let mut bytes = Vec::new();
+        nbt::write_tag_payload(&mut bytes, tag).unwrap();
+        // Add another tag after the queried one, to check that skipped tags are fully read.
+        nbt::write_tag_payload(&mut bytes, &Tag::Int(12345)).unwrap();
+
+        let mut reader = Cursor::new(bytes);
+        let mut matches = vec![];
+        query_payload(&mut reader, tag.id(), &parse_path(path).unwrap(), "", &mut matches)
+            .unwrap();
+        assert_eq!(nbt::read_tag_payload(&mut reader, &3).unwrap(), Tag::Int(12345));
+        matches
+    }
+
+    fn sample_chunk() -> Tag {
+        let section = |y: i8, names: Vec<&str>| Tag::Compound(vec![
+            ("Y".to_string(), Tag::Byte(y)),
+            ("Palette".to_string(), Tag::List(names.into_iter()
+                .map(|name| Tag::Compound(vec![
+                    ("Name".to_string(), Tag::String(name.to_string())),
+                ].into_iter().collect()))
+                .collect())),
+            ("BlockStates".to_string(), Tag::LongArray(vec![1, 2, 3])),
+        ].into_iter().collect());
+
+        Tag::Compound(vec![
+            ("DataVersion".to_string(), Tag::Int(2230)),
+            ("Level".to_string(), Tag::Compound(vec![
+                ("Sections".to_string(), Tag::List(vec![
+                    section(0, vec!["minecraft:air", "minecraft:stone"]),
+                    section(1, vec!["minecraft:air"]),
+                ])),
+                ("dotted.key".to_string(), Tag::Byte(1)),
+            ].into_iter().collect())),
+        ].into_iter().collect())
+    }
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(parse_path("").unwrap(), vec![]);
+        assert_eq!(parse_path("Data.Player.Pos[1]").unwrap(), vec![
+            Step::Key("Data".to_string()),
+            Step::Key("Player".to_string()),
+            Step::Key("Pos".to_string()),
+            Step::Index(1),
+        ]);
+        assert_eq!(parse_path("[*][0].\"a.b\"").unwrap(),
+            vec![Step::All, Step::Index(0), Step::Key("a.b".to_string())]);
+        assert!(parse_path("Data..Player").is_err());
+        assert!(parse_path("Pos[x]").is_err());
+        assert!(parse_path("Pos[0").is_err());
+        assert!(parse_path("Pos[0]Name").is_err());
+    }
+
+    #[test]
+    fn query_wildcards() {
+        let names = query(&sample_chunk(), "Level.Sections[*].Palette[*].Name");
+        assert_eq!(names, vec![
+            ("Level.Sections[0].Palette[0].Name".to_string(),
+                Tag::String("minecraft:air".to_string())),
+            ("Level.Sections[0].Palette[1].Name".to_string(),
+                Tag::String("minecraft:stone".to_string())),
+            ("Level.Sections[1].Palette[0].Name".to_string(),
+                Tag::String("minecraft:air".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn query_indices_and_keys() {
+        let chunk = sample_chunk();
+        assert_eq!(query(&chunk, "DataVersion"), vec![("DataVersion".to_string(), Tag::Int(2230))]);
+        assert_eq!(query(&chunk, "Level.Sections[1].Y"),
+            vec![("Level.Sections[1].Y".to_string(), Tag::Byte(1))]);
+        assert_eq!(query(&chunk, "Level.Sections[0].BlockStates[2]"),
+            vec![("Level.Sections[0].BlockStates[2]".to_string(), Tag::Long(3))]);
+        assert_eq!(query(&chunk, "Level.\"dotted.key\""),
+            vec![("Level.\"dotted.key\"".to_string(), Tag::Byte(1))]);
+        assert_eq!(query(&chunk, "").len(), 1);
+    }
+
+    #[test]
+    fn query_without_matches() {
+        let chunk = sample_chunk();
+        assert!(query(&chunk, "Level.Entities").is_empty());
+        assert!(query(&chunk, "Level.Sections[5]").is_empty());
+        assert!(query(&chunk, "DataVersion[0]").is_empty());
+        assert!(query(&chunk, "Level.Sections[0].BlockStates[0].Name").is_empty());
+    }
+}
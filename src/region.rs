@@ -1,27 +1,36 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{prelude::*, Error, SeekFrom};
+use std::io::{prelude::*, Cursor, Error, ErrorKind, SeekFrom};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::result::Result;
 
-use bitreader::BitReader;
-
-use byteorder::{BigEndian, ReadBytesExt};
-
-use flate2::read::ZlibDecoder;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 use regex::Regex;
 
+use serde::Deserialize;
+
+use super::biometypes::BiomeRegistry;
+use super::blocktypes;
 use super::blocktypes::BlockType;
+use super::color::RGBA;
+use super::compression;
+use super::deserializer;
+use super::light;
 use super::nbt;
+use super::nbtref::{self, LongArrayRef, TagRef};
 use super::sizes::*;
 use super::types::*;
 use super::world::World;
 
 // Data version number
+const V_17W47A: u32 = 1451; // 1.13 snapshot that replaced numeric block IDs with palettes
 const V_1_16: u32 = 2566;
+const V_21W43A: u32 = 2844; // 1.18 snapshot that removed the Level compound
 
 #[derive(Clone, Copy)]
 pub struct Block {
@@ -34,17 +43,47 @@ pub struct ChunkData {
     pub blocks: [u16; BLOCKS_IN_CHUNK_3D],
     pub lights: [u8; BLOCKS_IN_CHUNK_3D],
     pub biomes: [u8; BIOMES_IN_CHUNK_3D],
+    // Whether the chunk has finished generating, rather than being a proto-chunk.
+    pub full: bool,
+    // Whether the chunk stands in for one that couldn't be read.
+    pub corrupt: bool,
 }
 
 const EMPTY_CHUNK: ChunkData = ChunkData {
     blocks: [0u16; BLOCKS_IN_CHUNK_3D],
     lights: [0u8; BLOCKS_IN_CHUNK_3D],
     biomes: [0u8; BIOMES_IN_CHUNK_3D],
+    full: true,
+    corrupt: false,
 };
 
+// Stand in for chunks that couldn't be read with a layer of stone at the top of the rendered
+// height range, so that they can be drawn in a placeholder color over everything else.
+pub fn add_placeholder_chunks(chunks: &mut RegionChunks, errors: &[ChunkError],
+    blocktypes: &[BlockType], ylimits: &Range<usize>) {
+    let stone = blocktypes::find_block_type(blocktypes, "minecraft:stone", &HashMap::new())
+        .unwrap_or(0);
+    for (c, _) in errors {
+        let mut chunk = ChunkData {
+            blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+            lights: [MAX_LIGHT_LEVEL; BLOCKS_IN_CHUNK_3D],
+            biomes: [0u8; BIOMES_IN_CHUNK_3D],
+            full: true,
+            corrupt: true,
+        };
+        let bo = (ylimits.end - 1) * BLOCKS_IN_CHUNK_2D;
+        for block in chunk.blocks[bo..(bo + BLOCKS_IN_CHUNK_2D)].iter_mut() {
+            *block = stone;
+        }
+        chunks.insert(*c, chunk);
+    }
+}
+
 pub struct Chunk<'a> {
     pub data: &'a ChunkData,
     pub ndata: Edges<&'a ChunkData>,
+    // A color to blend into every pixel drawn for the chunk.
+    pub tint: Option<RGBA>,
 }
 
 impl<'a> Chunk<'a> {
@@ -143,9 +182,17 @@ impl<'a> Chunk<'a> {
     }
 }
 
+// Chunks in a region, keyed by their coords in the region.
+pub type RegionChunks = HashMap<Pair<usize>, ChunkData>;
+
+// A chunk that couldn't be read, with its coords in the region.
+pub type ChunkError = (Pair<usize>, Error);
+
 pub struct RegionData {
-    pub chunks: HashMap<Pair<usize>, ChunkData>,
-    pub nchunks: Edges<HashMap<Pair<usize>, ChunkData>>,
+    pub chunks: RegionChunks,
+    pub nchunks: Edges<RegionChunks>,
+    // Chunks in the region that couldn't be read. Neighbouring chunks with errors are left out.
+    pub errors: Vec<ChunkError>,
 }
 
 impl RegionData {
@@ -169,17 +216,33 @@ impl RegionData {
                     0 => self.nchunks.w.get(&Pair { x: MAX_CHUNK_IN_REGION, z: c.z }),
                     _ => self.chunks.get(&Pair { x: c.x - 1, z: c.z }),
                 }.unwrap_or_else(|| &EMPTY_CHUNK),
-            }
+            },
+            tint: None,
         }))
     }
 }
 
-fn get_path_from_coords(worldpath: &Path, r: &Pair<isize>) -> PathBuf {
-    worldpath.join("region").join(format!("r.{}.{}.mca", r.x, r.z))
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionFormat {
+    Anvil,
+    McRegion,
+}
+
+impl RegionFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RegionFormat::Anvil => "mca",
+            RegionFormat::McRegion => "mcr",
+        }
+    }
+}
+
+pub fn get_path_from_coords(worldpath: &Path, r: &Pair<isize>, format: RegionFormat) -> PathBuf {
+    worldpath.join("region").join(format!("r.{}.{}.{}", r.x, r.z, format.extension()))
 }
 
-pub fn get_coords_from_path(path_str: &str) -> Option<Pair<isize>> {
-    Regex::new(r"r\.([-\d]+)\.([-\d]+)\.mca$").unwrap()
+pub fn get_coords_from_path(path_str: &str, format: RegionFormat) -> Option<Pair<isize>> {
+    Regex::new(&format!(r"r\.([-\d]+)\.([-\d]+)\.{}$", format.extension())).unwrap()
         .captures(path_str)
         .map(|caps| Pair {
             x: caps.get(1).unwrap().as_str().parse::<isize>().unwrap(),
@@ -187,195 +250,544 @@ pub fn get_coords_from_path(path_str: &str) -> Option<Pair<isize>> {
         })
 }
 
+// List the chunks in a region file from the offsets in its header, without reading them. Some
+// may turn out to have no blocks, which is only found out when they are read.
 pub fn read_region_chunk_coords(path: &Path, rclimits: &Option<Edges<usize>>)
 -> Result<Vec<Pair<usize>>, Error> {
     let mut file = File::open(path)?;
     let mut chunks = vec![];
 
-    let climits = rclimits.unwrap_or_else(|| Edges::<usize>::full(CHUNKS_IN_REGION));
+    // Treat a file too short to hold the chunk offsets as an empty region, as the game does.
+    if file.metadata()?.len() < SECTOR_SIZE as u64 {
+        return Ok(chunks);
+    }
 
-    let size = climits.size();
-    let bar = ProgressBar::with_draw_target((size.x * size.z) as u64, ProgressDrawTarget::stdout())
-        .with_style(ProgressStyle::default_bar().template("{wide_bar}").progress_chars("▪■ "));
+    let mut header = [0u8; SECTOR_SIZE];
+    file.read_exact(&mut header)?;
 
+    let climits = rclimits.unwrap_or_else(|| Edges::<usize>::full(CHUNKS_IN_REGION));
     for cz in climits.n..(climits.s + 1) {
         for cx in climits.w..(climits.e + 1) {
-            bar.inc(1);
-
-            if let Some((mut reader, _)) = get_region_chunk_reader(&mut file, cx, cz)? {
-                if nbt::seek_compound_tag_name(&mut reader, "Level")?.is_some() &&
-                    nbt::seek_compound_tag_name(&mut reader, "Sections")?.is_some() &&
-                    nbt::read_list_length(&mut reader)? > 0 {
-                    chunks.push(Pair { x: cx, z: cz });
-                }
+            let co = (cz * CHUNKS_IN_REGION + cx) * 4;
+            if BigEndian::read_u32(&header[co..(co + 4)]) >> 8 != 0 {
+                chunks.push(Pair { x: cx, z: cz });
             }
         }
     }
 
-    bar.finish_and_clear();
-
     Ok(chunks)
 }
 
-fn get_region_chunk_reader(file: &mut File, cx: usize, cz: usize)
--> Result<Option<(ZlibDecoder<&mut File>, u32)>, Error> {
+// Read the time each chunk in a region file was last saved, in seconds since the Unix epoch,
+// from the second table in its header. Chunks that aren't there have a time of 0.
+pub fn read_region_timestamps(path: &Path) -> Result<Vec<u32>, Error> {
+    let mut file = File::open(path)?;
+    let mut timestamps = vec![0u32; CHUNKS_IN_REGION_2D];
+    if file.metadata()?.len() >= (SECTOR_SIZE * 2) as u64 {
+        file.seek(SeekFrom::Start(SECTOR_SIZE as u64))?;
+        file.read_u32_into::<BigEndian>(&mut timestamps)?;
+    }
+    Ok(timestamps)
+}
+
+// Read a chunk from an open region file to check whether it has any blocks.
+pub fn chunk_has_blocks(file: &mut File, path: &Path, c: &Pair<usize>, format: RegionFormat)
+-> Result<bool, Error> {
+    Ok(match get_region_chunk_reader(file, path, c.x, c.z)? {
+        Some((mut reader, version)) => if format == RegionFormat::McRegion {
+            // McRegion chunks always store a full array of blocks.
+            nbt::seek_compound_tag_name(&mut reader, "Level")?.is_some() &&
+                nbt::seek_compound_tag_name(&mut reader, "Blocks")?.is_some()
+        } else if version >= V_21W43A {
+            nbt::seek_compound_tag_name(&mut reader, "sections")?.is_some() &&
+                nbt::read_list_length(&mut reader)? > 0
+        } else {
+            nbt::seek_compound_tag_name(&mut reader, "Level")?.is_some() &&
+                nbt::seek_compound_tag_name(&mut reader, "Sections")?.is_some() &&
+                nbt::read_list_length(&mut reader)? > 0
+        },
+        None => false,
+    })
+}
+
+// Chunks too large for a region file are stored in their own file in the region directory,
+// named with the chunk's absolute coords.
+pub fn get_external_chunk_path(path: &Path, cx: usize, cz: usize) -> Result<PathBuf, Error> {
+    let r = path.file_name().and_then(|filename| filename.to_str())
+        .and_then(|filename| get_coords_from_path(filename, RegionFormat::Anvil))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid region file name"))?;
+    Ok(path.with_file_name(format!("c.{}.{}.mcc",
+        r.x * CHUNKS_IN_REGION as isize + cx as isize,
+        r.z * CHUNKS_IN_REGION as isize + cz as isize)))
+}
+
+// A reader over a chunk's decompressed NBT data, positioned after the root tag header.
+pub type ChunkReader = Cursor<Vec<u8>>;
+
+// Read a chunk's location from the region header: its offset and length, both in sectors.
+pub fn read_chunk_location<R>(reader: &mut R, cx: usize, cz: usize)
+-> Result<(usize, usize), Error> where R: Read + Seek {
     let co = (cz * CHUNKS_IN_REGION + cx) * 4;
-    file.seek(SeekFrom::Start(co as u64))?;
+    reader.seek(SeekFrom::Start(co as u64))?;
+
+    let location = reader.read_u32::<BigEndian>()? as usize;
+    Ok((location >> 8, location & 0xff))
+}
 
-    let offset = (file.read_u32::<BigEndian>()? >> 8) as usize * SECTOR_SIZE;
+fn get_region_chunk_reader(file: &mut File, path: &Path, cx: usize, cz: usize)
+-> Result<Option<(ChunkReader, u32)>, Error> {
+    let offset = read_chunk_location(file, cx, cz)?.0 * SECTOR_SIZE;
     Ok(if offset == 0 { None } else {
+        let file_len = file.metadata()?.len() as usize;
+        if offset + 5 > file_len {
+            return Err(Error::new(ErrorKind::InvalidData, "Chunk offset is past end of file"));
+        }
         file.seek(SeekFrom::Start(offset as u64))?;
-        let size = file.read_u32::<BigEndian>()? as usize; // Size of compressed chunk
-        file.seek(SeekFrom::Current(1))?; // Skip compression type
+        // Size of compressed chunk, including the compression type
+        let size = file.read_u32::<BigEndian>()? as usize;
+        let compression = file.read_u8()?;
+        if offset + 4 + size > file_len {
+            return Err(Error::new(ErrorKind::InvalidData, "Chunk extends past end of file"));
+        }
+
+        let data = if compression::is_external(compression) {
+            std::fs::read(get_external_chunk_path(path, cx, cz)?)?
+        } else {
+            let mut data = vec![0u8; size.saturating_sub(1)];
+            file.read_exact(&mut data)?;
+            data
+        };
+        let mut reader = Cursor::new(compression::decompress_chunk(compression, data)?);
 
-        let version = get_chunk_version(file, &size)?;
-        file.seek(SeekFrom::Start(offset as u64 + 5))?; // Skip size and compression type
+        let version = get_chunk_version(&mut reader)?;
 
-        // Create a new reader to start from the beginning,
+        // Start again from the beginning,
         // since version and level data could be in any order in the chunk.
-        let mut reader = ZlibDecoder::new_with_buf(file, vec![0u8; size - 1]);
+        reader.set_position(0);
         nbt::read_tag_header(&mut reader)?;
 
         Some((reader, version))
     })
 }
 
-fn get_chunk_version(file: &mut File, size: &usize) -> Result<u32, Error> {
-    let mut reader = ZlibDecoder::new_with_buf(file, vec![0u8; size - 1]);
-    nbt::read_tag_header(&mut reader)?;
-    let (id, _) = nbt::seek_compound_tag_name(&mut reader, "DataVersion")?.unwrap();
-    let version = nbt::read_tag_payload(&mut reader, &id)?;
-    Ok(*version.to_u32()?)
+// Find a chunk by its absolute coords in a world's Anvil or McRegion files, and return the
+// region file's path along with a reader positioned at the start of the chunk's root compound.
+pub fn open_world_chunk(worldpath: &Path, c: &Pair<isize>)
+-> Result<(PathBuf, ChunkReader), Error> {
+    let r = Pair {
+        x: c.x.div_euclid(CHUNKS_IN_REGION as isize),
+        z: c.z.div_euclid(CHUNKS_IN_REGION as isize),
+    };
+    let path = [RegionFormat::Anvil, RegionFormat::McRegion].iter()
+        .map(|format| get_path_from_coords(worldpath, &r, *format))
+        .find(|path| path.exists())
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Region file not found"))?;
+
+    let mut file = File::open(&path)?;
+    let cx = c.x.rem_euclid(CHUNKS_IN_REGION as isize) as usize;
+    let cz = c.z.rem_euclid(CHUNKS_IN_REGION as isize) as usize;
+    match get_region_chunk_reader(&mut file, &path, cx, cz)? {
+        Some((reader, _)) => Ok((path, reader)),
+        None => Err(Error::new(ErrorKind::NotFound, "Chunk not found")),
+    }
 }
 
-pub fn read_region_chunk<R>(reader: &mut R, version: u32, blocktypes: &[BlockType])
--> Result<Option<ChunkData>, Error> where R: Read {
-    if nbt::seek_compound_tag_name(reader, "Level")?.is_none() {
-        return Ok(None);
+fn get_chunk_version<R>(reader: &mut R) -> Result<u32, Error> where R: Read {
+    nbt::read_tag_header(reader)?;
+    // Chunks saved before 1.9 have no data version.
+    Ok(match nbt::seek_compound_tag_name(reader, "DataVersion")? {
+        Some((id, _)) => *nbt::read_tag_payload(reader, &id)?.to_i32()? as u32,
+        None => 0,
+    })
+}
+
+// Unpack an array of longs into indices of the given bit width.
+// Before 1.16, indices could span two longs; since then, each long is padded at the top instead.
+// The longs are signed in NBT, so they are treated as unsigned to shift them. They are read
+// through a function so that borrowed arrays don't need to be copied first.
+pub fn unpack_indices<F>(long: F, bits: usize, count: usize, padded: bool) -> Vec<usize>
+where F: Fn(usize) -> i64 {
+    let mask = (1u64 << bits) - 1;
+    let per_long = 64 / bits;
+
+    (0..count).map(|i| {
+        if padded {
+            ((long(i / per_long) as u64 >> ((i % per_long) * bits)) & mask) as usize
+        } else {
+            let bit = i * bits;
+            let (li, shift) = (bit / 64, bit % 64);
+            let mut value = long(li) as u64 >> shift;
+            if shift + bits > 64 {
+                value |= (long(li + 1) as u64) << (64 - shift);
+            }
+            (value & mask) as usize
+        }
+    }).collect()
+}
+
+// Get the number of bits needed to index a palette of the given length.
+pub fn palette_bits(len: usize, min_bits: usize) -> usize {
+    let mut bits = 0;
+    while (1 << bits) < len {
+        bits += 1;
+    }
+    bits.max(min_bits)
+}
+
+fn find_palette_block(blocktypes: &[BlockType], name: &str, props: &HashMap<&str, &str>) -> u16 {
+    blocktypes::find_block_type(blocktypes, name, props).unwrap_or_else(|| {
+        println!("Unknown block type {}", name);
+        blocktypes::get_unknown_block_type(blocktypes)
+    })
+}
+
+pub fn get_palette_blocks(palette: &[nbt::Tag], blocktypes: &[BlockType])
+-> Result<Vec<u16>, Error> {
+    let mut pblocks = Vec::with_capacity(palette.len());
+    for ptag in palette {
+        let pblock = ptag.to_hashmap()?;
+        let name = get_tag(pblock, "Name")?.to_str()?;
+        let mut props = HashMap::new();
+        if pblock.contains_key("Properties") {
+            for (k, v) in pblock["Properties"].to_hashmap()?.iter() {
+                props.insert(k.as_str(), v.to_str()?);
+            }
+        }
+        pblocks.push(find_palette_block(blocktypes, name, &props));
+    }
+    Ok(pblocks)
+}
+
+// The same as get_palette_blocks, for a palette borrowed from a chunk.
+fn get_chunk_palette_blocks(palette: &[TagRef], blocktypes: &[BlockType])
+-> Result<Vec<u16>, Error> {
+    let mut pblocks = Vec::with_capacity(palette.len());
+    for ptag in palette {
+        let pblock = ptag.to_compound()?;
+        let name = pblock.get_tag("Name")?.to_str()?;
+        let mut props = HashMap::new();
+        if let Some(props_tag) = pblock.get("Properties") {
+            for (k, v) in props_tag.to_compound()?.iter() {
+                props.insert(k, v.to_str()?);
+            }
+        }
+        pblocks.push(find_palette_block(blocktypes, name, &props));
+    }
+    Ok(pblocks)
+}
+
+fn get_tag<'a>(compound: &'a HashMap<String, nbt::Tag>, name: &str)
+-> Result<&'a nbt::Tag, Error> {
+    compound.get(name).ok_or_else(|| Error::new(ErrorKind::InvalidData,
+        format!("Missing {} tag", name)))
+}
+
+fn get_first<T: Copy>(palette: &[T]) -> Result<T, Error> {
+    palette.first().copied().ok_or_else(|| Error::new(ErrorKind::InvalidData, "Empty palette"))
+}
+
+// Unpack an array of longs into palette entries, checking that the array is long enough
+// and that every index is within the palette.
+fn unpack_palette<T: Copy>(palette: &[T], longs: &LongArrayRef, bits: usize, count: usize,
+    padded: bool)
+-> Result<Vec<T>, Error> {
+    let per_long = 64 / bits;
+    let len = if padded { count.div_ceil(per_long) } else { (count * bits).div_ceil(64) };
+    if longs.len() < len {
+        return Err(Error::new(ErrorKind::InvalidData, "Palette index array is too short"));
     }
+    unpack_indices(|i| longs.get(i), bits, count, padded).iter()
+        .map(|i| palette.get(*i).copied()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Palette index out of range")))
+        .collect()
+}
+
+// Check that an optional array of nibbles covers a whole section.
+fn check_nibbles(nibbles: Option<&[u8]>) -> Result<(), Error> {
+    match nibbles {
+        Some(nibbles) if nibbles.len() < BLOCKS_IN_SECTION_3D / 2 =>
+            Err(Error::new(ErrorKind::InvalidData, "Nibble array is too short")),
+        _ => Ok(()),
+    }
+}
+
+fn get_palette_biomes(palette: &[TagRef], biome_registry: &BiomeRegistry)
+-> Result<Vec<u8>, Error> {
+    let mut pbiomes = Vec::with_capacity(palette.len());
+    for ptag in palette {
+        let name = ptag.to_str()?;
+        pbiomes.push(biome_registry.get(name).copied().unwrap_or_else(|| {
+            println!("Unknown biome type {}", name);
+            0
+        }));
+    }
+    Ok(pbiomes)
+}
+
+// Read an Anvil chunk from its decompressed data, positioned just after the root tag's header.
+// The hot path of rendering, so the chunk is parsed into tags that borrow from its data,
+// instead of copying out every name, string and array.
+pub fn read_region_chunk(reader: &ChunkReader, version: u32, blocktypes: &[BlockType],
+    biome_registry: &BiomeRegistry, legacy_blocktypes: &[u16])
+-> Result<Option<ChunkData>, Error> {
+    let root = nbtref::read_payload(reader.get_ref(), reader.position() as usize, 10)?;
+    let root = root.to_compound()?;
+
+    // Since 21w43a, chunk data is stored at the top level rather than in a Level compound.
+    let flattened = version >= V_21W43A;
+    let level = if flattened { root } else {
+        match root.get("Level") {
+            Some(level) => level.to_compound()?,
+            None => return Ok(None),
+        }
+    };
 
     let mut chunk = ChunkData {
         blocks: [0u16; BLOCKS_IN_CHUNK_3D],
         lights: [0x0fu8; BLOCKS_IN_CHUNK_3D], // Default to full skylight and zero blocklight.
         biomes: [0u8; BIOMES_IN_CHUNK_3D],
+        full: true, // Chunks with no generation status are assumed to be complete.
+        corrupt: false,
     };
     // Default to zero skylight and blocklight for sections that exist but don't contain those tags.
     let light_bytes_default = vec![0u8; BLOCKS_IN_SECTION_3D / 2];
 
-    while let Some((id, name)) = nbt::seek_compound_tag_names(reader, vec!["Sections", "Biomes"])? {
-        if name == "Sections" {
-            let slen = nbt::read_list_length(reader)?;
-            if slen == 0 {
-                return Ok(None);
-            }
+    // Chunks may have been saved before their light was calculated, or without any light data.
+    let mut light_on = true;
+    let mut has_light = false;
 
-            for _ in 0..slen {
-                let section = nbt::read_compound_tag_names(reader,
-                    vec!["Y", "Palette", "BlockStates", "BlockLight", "SkyLight"])?;
-                let y = *section["Y"].to_u8()? as usize;
-                if y > MAX_SECTION_IN_CHUNK_Y {
-                    continue;
+    if let Some(sections) = level.get(if flattened { "sections" } else { "Sections" }) {
+        let sections = sections.to_list()?;
+        if sections.is_empty() {
+            return Ok(None);
+        }
+
+        for section in sections {
+            let section = section.to_compound()?;
+            // Section Y is a signed byte; sections outside the build limits only hold light.
+            let sy = section.get_tag("Y")?.to_i8()? as isize - MIN_SECTION_Y;
+            if sy < 0 || sy > MAX_SECTION_IN_CHUNK_Y as isize {
+                continue;
+            }
+            let so = sy as usize * BLOCKS_IN_SECTION_3D;
+
+            // Read blocks.
+            let states = if flattened {
+                match section.get("block_states") {
+                    Some(tag) => {
+                        let states = tag.to_compound()?;
+                        Some((states.get_tag("palette")?, states.get("data")))
+                    },
+                    None => None,
                 }
-                let so = y * BLOCKS_IN_SECTION_3D;
-
-                // Read blocks.
-                if section.contains_key("BlockStates") {
-                    let palette = section["Palette"].to_list()?;
-                    let states_longs = section["BlockStates"].to_long_array()?;
-
-                    let mut pblocks = Vec::with_capacity(palette.len());
-                    for ptag in palette {
-                        let pblock = ptag.to_hashmap()?;
-                        let name = pblock["Name"].to_str()?;
-                        let mut props = HashMap::new();
-                        if pblock.contains_key("Properties") {
-                            for (k, v) in pblock["Properties"].to_hashmap()?.iter() {
-                                props.insert(k, v.to_str()?);
-                            }
-                        }
-                        pblocks.push(blocktypes.iter()
-                            .position(|b| {
-                                b.name == name &&
-                                b.state.iter().all(|(k, v)| props[k] == v)
-                            })
-                            .or_else(|| {
-                                println!("Unknown block type {}", name);
-                                Some(0)
-                            })
-                            .unwrap() as u16);
-                    }
-
-                    // BlockStates is an array of i64 representing 4096 blocks;
-                    // check the array length to determine the # of bits per block state.
-                    let long_count = states_longs.len();
-                    let bits = ((long_count * 64) / BLOCKS_IN_SECTION_3D) as u8;
-                    let states_per_long = (64 / bits) as usize;
-                    let bit_remainder = (64 % bits) as u64;
-                    let state_remainder = long_count * states_per_long % BLOCKS_IN_SECTION_3D;
-
-                    // Convert the states array (i64) to bytes (u8) and input into BitReader.
-                    let mut bytes = vec![0u8; long_count * 8];
-                    for i in 0..long_count {
-                        let long = states_longs[long_count - i - 1];
-                        for b in 0..8 {
-                            bytes[i * 8 + b] = (long >> ((7 - b) * 8)) as u8;
-                        }
-                    }
-                    let mut br = BitReader::new(&bytes);
-
-                    // Version 1.16+ only
-                    // Skip unused states from the top of the first i64, if there is a remainder.
-                    if version >= V_1_16 && state_remainder > 0 {
-                        br.skip(bit_remainder).unwrap();
-                        br.skip(state_remainder as u64 * bits as u64).unwrap();
-                    }
-
-                    for i in 0..BLOCKS_IN_SECTION_3D {
-                        // Version 1.16+ only
-                        // Skip unused bits from the top of each i64, if there is a remainder.
-                        if version >= V_1_16 && bit_remainder > 0 &&
-                            (i + state_remainder) % states_per_long == 0 {
-                            br.skip(bit_remainder).unwrap();
-                        }
-
-                        let b = br.read_u16(bits).unwrap() as usize;
-                        chunk.blocks[so + BLOCKS_IN_SECTION_3D - i - 1] = pblocks[b];
-                    }
+            } else if version >= V_17W47A {
+                match section.get("BlockStates") {
+                    Some(tag) => Some((section.get_tag("Palette")?, Some(tag))),
+                    None => None,
                 }
+            } else {
+                None
+            };
+            if let Some((palette_tag, data_tag)) = states {
+                let pblocks = get_chunk_palette_blocks(palette_tag.to_list()?, blocktypes)?;
+
+                // A section with a single palette entry may omit its data array.
+                let blocks = match data_tag {
+                    Some(data_tag) => unpack_palette(&pblocks, data_tag.to_long_array()?,
+                        palette_bits(pblocks.len(), 4), BLOCKS_IN_SECTION_3D,
+                        version >= V_1_16)?,
+                    None => vec![get_first(&pblocks)?; BLOCKS_IN_SECTION_3D],
+                };
+                chunk.blocks[so..(so + BLOCKS_IN_SECTION_3D)].copy_from_slice(&blocks);
+            }
 
-                // Read lights.
-                let sbytes = section.get("SkyLight")
-                    .map_or(&light_bytes_default, |tag| tag.to_u8_array().unwrap());
-                let bbytes = section.get("BlockLight")
-                    .map_or(&light_bytes_default, |tag| tag.to_u8_array().unwrap());
-
-                for i in 0..(BLOCKS_IN_SECTION_3D / 2) {
-                    // The bottom half of each byte, moving blocklight to the top.
-                    chunk.lights[so + i * 2] = ((bbytes[i] & 0x0f) << 4) | (sbytes[i] & 0x0f);
-                    // The top half of each byte, moving skylight to the bottom.
-                    chunk.lights[so + i * 2 + 1] = (bbytes[i] & 0xf0) | (sbytes[i] >> 4);
+            // Read pre-1.13 blocks, which are stored as byte IDs plus nibble data values,
+            // with an optional extra nibble for IDs above 255.
+            if let Some(blocks_tag) = section.get("Blocks").filter(|_| version < V_17W47A) {
+                let ids = blocks_tag.to_byte_array()?;
+                let data = section.get("Data").map(|tag| tag.to_byte_array()).transpose()?;
+                let add = section.get("Add").map(|tag| tag.to_byte_array()).transpose()?;
+                check_nibbles(data)?;
+                check_nibbles(add)?;
+                let nibble = |bytes: &[u8], i: usize| (bytes[i / 2] >> ((i % 2) * 4)) & 0x0f;
+
+                for (i, byte) in ids.iter().enumerate().take(BLOCKS_IN_SECTION_3D) {
+                    let id = *byte as usize |
+                        add.map_or(0, |add| (nibble(add, i) as usize) << 8);
+                    let value = data.map_or(0, |data| nibble(data, i)) as usize;
+                    // IDs from the Add nibble were only used by mods.
+                    chunk.blocks[so + i] = if id < LEGACY_BLOCK_IDS {
+                        legacy_blocktypes[id * LEGACY_DATA_VALUES + value]
+                    } else { blocktypes::get_unknown_block_type(blocktypes) };
                 }
             }
-        } else if name == "Biomes" {
-            // Read biomes and cast to an array of bytes.
-            let cbiomes = match nbt::read_tag_payload(reader, &id)? {
-                nbt::Tag::ByteArray(cbiomes_tag) => cbiomes_tag,
-                nbt::Tag::IntArray(cbiomes_tag) => cbiomes_tag.iter().map(|v| *v as u8).collect(),
-                _ => continue,
-            };
 
-            if cbiomes.len() == BIOMES_IN_CHUNK_3D {
-                chunk.biomes.copy_from_slice(&cbiomes);
+            // Read biomes, which since 1.18 are stored per section.
+            if let Some(biomes_tag) = section.get("biomes").filter(|_| flattened) {
+                let biomes = biomes_tag.to_compound()?;
+                let pbiomes = get_palette_biomes(biomes.get_tag("palette")?.to_list()?,
+                    biome_registry)?;
+                let bio = sy as usize * BIOMES_IN_SECTION_3D;
+
+                let sbiomes = match biomes.get("data") {
+                    Some(data_tag) => unpack_palette(&pbiomes, data_tag.to_long_array()?,
+                        palette_bits(pbiomes.len(), 1), BIOMES_IN_SECTION_3D, true)?,
+                    None => vec![get_first(&pbiomes)?; BIOMES_IN_SECTION_3D],
+                };
+                chunk.biomes[bio..(bio + BIOMES_IN_SECTION_3D)].copy_from_slice(&sbiomes);
+            }
+
+            // Read lights.
+            has_light |= section.contains_key("SkyLight") || section.contains_key("BlockLight");
+            let sbytes = section.get("SkyLight").map(|tag| tag.to_byte_array()).transpose()?
+                .unwrap_or(&light_bytes_default);
+            let bbytes = section.get("BlockLight").map(|tag| tag.to_byte_array()).transpose()?
+                .unwrap_or(&light_bytes_default);
+            check_nibbles(Some(sbytes))?;
+            check_nibbles(Some(bbytes))?;
+
+            for i in 0..(BLOCKS_IN_SECTION_3D / 2) {
+                // The bottom half of each byte, moving blocklight to the top.
+                chunk.lights[so + i * 2] = ((bbytes[i] & 0x0f) << 4) | (sbytes[i] & 0x0f);
+                // The top half of each byte, moving skylight to the bottom.
+                chunk.lights[so + i * 2 + 1] = (bbytes[i] & 0xf0) | (sbytes[i] >> 4);
             }
         }
     }
 
+    // Read biomes and cast to an array of bytes.
+    let cbiomes = match level.get("Biomes").filter(|_| !flattened) {
+        Some(TagRef::ByteArray(cbiomes)) => Cow::Borrowed(*cbiomes),
+        Some(TagRef::IntArray(cbiomes)) => Cow::Owned(cbiomes.iter().map(|v| v as u8).collect()),
+        _ => Cow::Borrowed(&[][..]),
+    };
+    match cbiomes.len() {
+        // Pre-1.18 biomes start at Y=0, so they go above the sections below that.
+        LEGACY_BIOMES_IN_CHUNK_3D => {
+            let bio = (-MIN_SECTION_Y) as usize * BIOMES_IN_SECTION_3D;
+            chunk.biomes[bio..(bio + LEGACY_BIOMES_IN_CHUNK_3D)].copy_from_slice(&cbiomes);
+        },
+        // Before 1.15, biomes were stored per column. Take the column nearest the middle
+        // of each 4x4 area, and use it for the full height of the chunk.
+        BLOCKS_IN_CHUNK_2D => {
+            for (bio, biome) in chunk.biomes.iter_mut().enumerate() {
+                let bix = bio % BIOMES_IN_CHUNK;
+                let biz = bio / BIOMES_IN_CHUNK % BIOMES_IN_CHUNK;
+                let bo2 = (biz * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2) * BLOCKS_IN_CHUNK +
+                    bix * BLOCKS_IN_BIOME + BLOCKS_IN_BIOME / 2;
+                *biome = cbiomes[bo2];
+            }
+        },
+        _ => {},
+    }
+
+    if let Some(status) = level.get("Status") {
+        // Since 1.14 a finished chunk is "full", and in 1.13 it was "postprocessed".
+        chunk.full = matches!(status.to_str()?.trim_start_matches("minecraft:"),
+            "full" | "postprocessed" | "fullchunk");
+    }
+    if let Some(populated) = level.get("TerrainPopulated").filter(|_| !flattened) {
+        // Before 1.13, chunks were marked once trees, ores and so on had been added.
+        chunk.full = populated.to_i8()? != 0;
+    }
+    if let Some(light_on_tag) = level.get("isLightOn") {
+        light_on = light_on_tag.to_i8()? != 0;
+    }
+
+    if !has_light || !light_on {
+        light::compute_lights(&mut chunk, blocktypes);
+    }
+
     Ok(Some(chunk))
 }
 
-fn read_region_chunk_data(path: &Path, rclimits: &Edges<usize>, blocktypes: &[BlockType])
--> Result<HashMap<Pair<usize>, ChunkData>, Box<dyn std::error::Error>> {
+// The parts of a McRegion chunk that are rendered.
+#[derive(Deserialize)]
+struct McRegionChunk {
+    #[serde(rename = "Level")]
+    level: Option<McRegionLevel>,
+}
+
+#[derive(Deserialize)]
+struct McRegionLevel {
+    #[serde(rename = "Blocks")]
+    blocks: Option<Vec<u8>>,
+    #[serde(rename = "Data")]
+    data: Option<Vec<u8>>,
+    #[serde(rename = "SkyLight")]
+    sky_light: Option<Vec<u8>>,
+    #[serde(rename = "BlockLight")]
+    block_light: Option<Vec<u8>>,
+    #[serde(rename = "TerrainPopulated")]
+    populated: Option<bool>,
+}
+
+// Read a chunk from a McRegion file, used before 1.2.
+// These store a single 128-block-tall array of blocks per chunk, with Y changing fastest, then Z,
+// then X.
+pub fn read_mcregion_chunk<R>(reader: &mut R, blocktypes: &[BlockType],
+    biome_registry: &BiomeRegistry, legacy_blocktypes: &[u16])
+-> Result<Option<ChunkData>, Error> where R: Read {
+    let chunk: McRegionChunk = deserializer::from_payload(reader, 10)?;
+    let level = match chunk.level {
+        Some(level) => level,
+        None => return Ok(None),
+    };
+    let ids = match level.blocks {
+        Some(ids) => ids,
+        None => return Ok(None),
+    };
+    if ids.len() != MCREGION_BLOCKS_IN_CHUNK_3D {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid McRegion block array length"));
+    }
+    let data = level.data.as_ref();
+    let slight = level.sky_light.as_ref();
+    let blight = level.block_light.as_ref();
+    for nibbles in [data, slight, blight].iter().flatten() {
+        if nibbles.len() < MCREGION_BLOCKS_IN_CHUNK_3D / 2 {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid McRegion nibble array length"));
+        }
+    }
+    let nibble = |bytes: &[u8], i: usize| (bytes[i / 2] >> ((i % 2) * 4)) & 0x0f;
+
+    // McRegion chunks don't store biomes, so use plains throughout.
+    let plains = biome_registry.get("minecraft:plains").copied().unwrap_or(0);
+
+    let mut chunk = ChunkData {
+        blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+        lights: [0x0fu8; BLOCKS_IN_CHUNK_3D], // Default to full skylight and zero blocklight.
+        biomes: [plains; BIOMES_IN_CHUNK_3D],
+        full: level.populated != Some(false),
+        corrupt: false,
+    };
+    // The blocks below Y=0 and above Y=127 are left empty, with full skylight, as they are in
+    // Anvil sections that weren't saved.
+    let yo = block_y_to_offset(0);
+
+    for (i, byte) in ids.iter().enumerate() {
+        let by = i % MCREGION_BLOCKS_IN_CHUNK_Y;
+        let bz = i / MCREGION_BLOCKS_IN_CHUNK_Y % BLOCKS_IN_CHUNK;
+        let bx = i / MCREGION_BLOCKS_IN_CHUNK_Y / BLOCKS_IN_CHUNK;
+        let bo3 = (yo + by) * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx;
+
+        let value = data.map_or(0, |data| nibble(data, i)) as usize;
+        chunk.blocks[bo3] = legacy_blocktypes[*byte as usize * LEGACY_DATA_VALUES + value];
+        chunk.lights[bo3] = (blight.map_or(0, |blight| nibble(blight, i)) << 4) |
+            slight.map_or(MAX_LIGHT_LEVEL, |slight| nibble(slight, i));
+    }
+
+    if slight.is_none() && blight.is_none() {
+        light::compute_lights(&mut chunk, blocktypes);
+    }
+
+    Ok(Some(chunk))
+}
+
+fn read_region_chunk_data(path: &Path, rclimits: &Edges<usize>, format: RegionFormat,
+    blocktypes: &[BlockType], biome_registry: &BiomeRegistry, legacy_blocktypes: &[u16])
+-> Result<(RegionChunks, Vec<ChunkError>), Error> {
     let mut chunks = HashMap::new();
+    let mut errors = vec![];
     let size = rclimits.size();
 
     if path.exists() {
@@ -389,10 +801,23 @@ fn read_region_chunk_data(path: &Path, rclimits: &Edges<usize>, blocktypes: &[Bl
             for cx in rclimits.w..(rclimits.e + 1) {
                 bar.inc(1);
 
-                if let Some((mut reader, version)) = get_region_chunk_reader(&mut file, cx, cz)? {
-                    if let Some(chunk) = read_region_chunk(&mut reader, version, blocktypes)? {
-                        chunks.insert(Pair { x: cx, z: cz }, chunk);
-                    }
+                let c = Pair { x: cx, z: cz };
+                let chunk = get_region_chunk_reader(&mut file, path, cx, cz)
+                    .and_then(|reader| match reader {
+                        Some((mut reader, version)) => match format {
+                            RegionFormat::Anvil => read_region_chunk(&reader, version,
+                                blocktypes, biome_registry, legacy_blocktypes),
+                            RegionFormat::McRegion => read_mcregion_chunk(&mut reader,
+                                blocktypes, biome_registry, legacy_blocktypes),
+                        },
+                        None => Ok(None),
+                    });
+                match chunk {
+                    Ok(Some(chunk)) => {
+                        chunks.insert(c, chunk);
+                    },
+                    Ok(None) => {},
+                    Err(err) => errors.push((c, err)),
                 }
             }
         }
@@ -400,48 +825,489 @@ fn read_region_chunk_data(path: &Path, rclimits: &Edges<usize>, blocktypes: &[Bl
         bar.finish_and_clear();
     }
 
-    Ok(chunks)
+    Ok((chunks, errors))
+}
+
+// Find the chunk limits within a region that fall within the world's block edges.
+pub fn get_region_chunk_limits(world: &World, r: &Pair<isize>) -> Edges<usize> {
+    Edges {
+        n: chunk_pos_in_region(block_to_chunk(world.bedges.n), Some(r.z)),
+        e: chunk_pos_in_region(block_to_chunk(world.bedges.e), Some(r.x)),
+        s: chunk_pos_in_region(block_to_chunk(world.bedges.s), Some(r.z)),
+        w: chunk_pos_in_region(block_to_chunk(world.bedges.w), Some(r.x)),
+    }
 }
 
-pub fn read_region_data(world: &World, r: &Pair<isize>, blocktypes: &[BlockType])
+// Find the chunk limits of the row or column of chunks bordering a region's chunk limits
+// in each neighbouring region.
+pub fn get_neighbour_chunk_limits(rclimits: &Edges<usize>) -> Edges<Edges<usize>> {
+    Edges {
+        n: Edges { n: MAX_CHUNK_IN_REGION, e: rclimits.e, s: MAX_CHUNK_IN_REGION, w: rclimits.w },
+        e: Edges { n: rclimits.n, e: 0, s: rclimits.s, w: 0 },
+        s: Edges { n: 0, e: rclimits.e, s: 0, w: rclimits.w },
+        w: Edges { n: rclimits.n, e: MAX_CHUNK_IN_REGION, s: rclimits.s, w: MAX_CHUNK_IN_REGION },
+    }
+}
+
+// Neighbouring chunks left empty, for regions read without them.
+pub fn no_neighbour_chunks() -> Edges<RegionChunks> {
+    Edges { n: HashMap::new(), e: HashMap::new(), s: HashMap::new(), w: HashMap::new() }
+}
+
+pub fn read_region_data(world: &World, r: &Pair<isize>, format: RegionFormat,
+    blocktypes: &[BlockType], biome_registry: &BiomeRegistry, legacy_blocktypes: &[u16],
+    neighbours: bool)
 -> Result<Option<RegionData>, Box<dyn std::error::Error>> {
-    let regionpath = get_path_from_coords(world.path, &r);
+    let regionpath = get_path_from_coords(world.path, r, format);
     if !regionpath.exists() {
         return Ok(None);
     }
 
-    let rclimits = Edges {
-        n: chunk_pos_in_region(block_to_chunk(world.bedges.n), Some(r.z)),
-        e: chunk_pos_in_region(block_to_chunk(world.bedges.e), Some(r.x)),
-        s: chunk_pos_in_region(block_to_chunk(world.bedges.s), Some(r.z)),
-        w: chunk_pos_in_region(block_to_chunk(world.bedges.w), Some(r.x)),
-    };
+    let rclimits = get_region_chunk_limits(world, r);
 
-    let chunks = read_region_chunk_data(&regionpath, &rclimits, blocktypes)?;
+    let (mut chunks, errors) = read_region_chunk_data(&regionpath, &rclimits, format, blocktypes,
+        biome_registry, legacy_blocktypes)?;
+    add_placeholder_chunks(&mut chunks, &errors, blocktypes, world.ylimits);
     if chunks.len() == 0 {
         return Ok(None);
     }
+    if !neighbours {
+        return Ok(Some(RegionData { chunks, nchunks: no_neighbour_chunks(), errors }));
+    }
 
     let npaths = Edges {
-        n: get_path_from_coords(world.path, &Pair { x: r.x, z: r.z - 1 }),
-        e: get_path_from_coords(world.path, &Pair { x: r.x + 1, z: r.z }),
-        s: get_path_from_coords(world.path, &Pair { x: r.x, z: r.z + 1 }),
-        w: get_path_from_coords(world.path, &Pair { x: r.x - 1, z: r.z }),
-    };
-    let nrclimits = Edges {
-        n: Edges { n: MAX_CHUNK_IN_REGION, e: rclimits.e, s: MAX_CHUNK_IN_REGION, w: rclimits.w },
-        e: Edges { n: rclimits.n, e: 0, s: rclimits.s, w: 0 },
-        s: Edges { n: 0, e: rclimits.e, s: 0, w: rclimits.w },
-        w: Edges { n: rclimits.n, e: MAX_CHUNK_IN_REGION, s: rclimits.s, w: MAX_CHUNK_IN_REGION },
+        n: get_path_from_coords(world.path, &Pair { x: r.x, z: r.z - 1 }, format),
+        e: get_path_from_coords(world.path, &Pair { x: r.x + 1, z: r.z }, format),
+        s: get_path_from_coords(world.path, &Pair { x: r.x, z: r.z + 1 }, format),
+        w: get_path_from_coords(world.path, &Pair { x: r.x - 1, z: r.z }, format),
     };
+    let nrclimits = get_neighbour_chunk_limits(&rclimits);
 
+    // Errors reading the neighbouring regions' chunks are left out of the report. Those chunks
+    // are only used for the edges of this region's chunks, and any that fall within the map are
+    // reported, and drawn as placeholders, when their own region is read.
     Ok(Some(RegionData {
         chunks,
         nchunks: Edges {
-            n: read_region_chunk_data(&npaths.n, &nrclimits.n, blocktypes)?,
-            e: read_region_chunk_data(&npaths.e, &nrclimits.e, blocktypes)?,
-            s: read_region_chunk_data(&npaths.s, &nrclimits.s, blocktypes)?,
-            w: read_region_chunk_data(&npaths.w, &nrclimits.w, blocktypes)?,
+            n: read_region_chunk_data(&npaths.n, &nrclimits.n, format, blocktypes,
+                biome_registry, legacy_blocktypes)?.0,
+            e: read_region_chunk_data(&npaths.e, &nrclimits.e, format, blocktypes,
+                biome_registry, legacy_blocktypes)?.0,
+            s: read_region_chunk_data(&npaths.s, &nrclimits.s, format, blocktypes,
+                biome_registry, legacy_blocktypes)?.0,
+            w: read_region_chunk_data(&npaths.w, &nrclimits.w, format, blocktypes,
+                biome_registry, legacy_blocktypes)?.0,
         },
+        errors,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+    use crate::biometypes;
+    use crate::nbt::testing::{compound, to_bytes};
+    use crate::nbt::Tag;
+    use crate::options::Lighting;
+    use crate::world::WorldFormat;
+
+    struct Types {
+        blocktypes: Vec<BlockType>,
+        biome_registry: BiomeRegistry,
+        legacy_blocktypes: Vec<u16>,
+    }
+
+    fn get_types() -> Types {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+        let legacy_blocktypes = blocktypes::get_legacy_block_types(&blocktypes);
+        Types { blocktypes, biome_registry: biometypes::get_biome_registry(&biometypes),
+            legacy_blocktypes }
+    }
+
+    fn find_block(types: &Types, name: &str) -> u16 {
+        types.blocktypes.iter().position(|blocktype| blocktype.name == name).unwrap() as u16
+    }
+
+    fn find_biome(types: &Types, name: &str) -> u8 {
+        types.biome_registry[name]
+    }
+
+    fn palette(names: &[&str]) -> Tag {
+        Tag::List(names.iter()
+            .map(|name| compound(vec![("Name", Tag::String(name.to_string()))]))
+            .collect())
+    }
+
+    fn nibbles(first: u8) -> Tag {
+        let mut bytes = vec![0u8; BLOCKS_IN_SECTION_3D / 2];
+        bytes[0] = first;
+        Tag::ByteArray(bytes)
+    }
+
+    // Chunks are read on a thread with a bigger stack than tests get, as unoptimized builds
+    // keep several copies of them on it.
+    fn with_big_stack<T, F>(read: F) -> T where T: Send, F: FnOnce() -> T + Send {
+        std::thread::scope(|scope| std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn_scoped(scope, read)
+            .unwrap()
+            .join()
+            .unwrap())
+    }
+
+    // Write a chunk as it would be saved, and return a reader past the root tag's type and
+    // empty name.
+    fn chunk_reader(tag: &Tag) -> ChunkReader {
+        let mut reader = Cursor::new(to_bytes(tag));
+        reader.set_position(3);
+        reader
+    }
+
+    // Read an Anvil chunk from the tag it would be saved as.
+    fn read_chunk(tag: &Tag, version: u32, types: &Types) -> Option<Box<ChunkData>> {
+        let reader = chunk_reader(tag);
+        with_big_stack(|| read_region_chunk(&reader, version, &types.blocktypes,
+            &types.biome_registry, &types.legacy_blocktypes).unwrap().map(Box::new))
+    }
+
+    fn offset(bx: usize, y: isize, bz: usize) -> usize {
+        block_y_to_offset(y) * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx
+    }
+
+    #[test]
+    fn read_flattened_chunk() {
+        let types = get_types();
+        let section = |y: i8, states: Tag, biomes: Tag| vec![
+            ("Y", Tag::Byte(y)),
+            ("block_states", states),
+            ("biomes", biomes),
+        ];
+        let plains = || compound(vec![
+            ("palette", Tag::List(vec![Tag::String("minecraft:plains".to_string())])),
+        ]);
+        let blocks = |names: &[&str], data: Option<Vec<i64>>| {
+            let mut values = vec![("palette", palette(names))];
+            values.extend(data.map(|data| ("data", Tag::LongArray(data))));
+            compound(values)
+        };
+
+        // The lowest section, with a dirt block at X=1 among stone, and some light.
+        let mut bottom = vec![0i64; BLOCKS_IN_SECTION_3D * 4 / 64];
+        bottom[0] = 0x10;
+        let mut bottom = section(-4, blocks(&["minecraft:stone", "minecraft:dirt"], Some(bottom)),
+            plains());
+        bottom.push(("SkyLight", nibbles(0x21)));
+        bottom.push(("BlockLight", nibbles(0x30)));
+        let tag = compound(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("Status", Tag::String("minecraft:full".to_string())),
+            ("sections", Tag::List(vec![
+                compound(bottom),
+                compound(section(0, blocks(&["minecraft:glass"], None), plains())),
+                // Sections outside the build limits only hold light, and are skipped.
+                compound(section(-5, blocks(&["minecraft:stone"], None), plains())),
+                compound(section(20, blocks(&["minecraft:stone"], None), plains())),
+            ])),
+        ]);
+        let chunk = read_chunk(&tag, 3465, &types).unwrap();
+
+        let stone = find_block(&types, "minecraft:stone");
+        assert_eq!(chunk.blocks[offset(0, -64, 0)], stone);
+        assert_eq!(chunk.blocks[offset(1, -64, 0)], find_block(&types, "minecraft:dirt"));
+        assert_eq!(chunk.blocks[offset(15, -49, 15)], stone);
+        assert_eq!(chunk.blocks[offset(0, -48, 0)], 0);
+        assert_eq!(chunk.blocks[offset(7, 0, 9)], find_block(&types, "minecraft:glass"));
+        assert_eq!(chunk.blocks[offset(7, 16, 9)], 0);
+        assert_eq!(chunk.blocks[offset(0, MAX_BLOCK_Y, 0)], 0);
+        let plains = find_biome(&types, "minecraft:plains");
+        assert_eq!(chunk.biomes[0], plains);
+        assert_eq!(chunk.biomes[4 * BIOMES_IN_SECTION_3D + BIOMES_IN_SECTION_3D - 1], plains);
+        assert_eq!(chunk.biomes[5 * BIOMES_IN_SECTION_3D], 0);
+        assert!(chunk.full);
+
+        // Block light in the high nibble, skylight in the low one.
+        assert_eq!(chunk.lights[offset(0, -64, 0)], 0x01);
+        assert_eq!(chunk.lights[offset(1, -64, 0)], 0x32);
+        assert_eq!(chunk.lights[offset(2, -64, 0)], 0);
+        // Sections saved without light are dark, and those not saved at all get full skylight.
+        assert_eq!(chunk.lights[offset(7, 0, 9)], 0);
+        assert_eq!(chunk.lights[offset(7, 16, 9)], 0x0f);
+    }
+
+    #[test]
+    fn read_palette_biomes() {
+        let types = get_types();
+        // 17 biomes need 5 bits for each index, which fit twelve to a long.
+        let mut names: Vec<&String> = types.biome_registry.keys().collect();
+        names.sort();
+        let mut ids = std::collections::HashSet::new();
+        names.retain(|name| ids.insert(types.biome_registry[*name]));
+        let names = &names[..17];
+        let indices: Vec<usize> = (0..BIOMES_IN_SECTION_3D).map(|i| i * 7 % 17).collect();
+        let mut longs = vec![0i64; BIOMES_IN_SECTION_3D.div_ceil(12)];
+        for (i, index) in indices.iter().enumerate() {
+            longs[i / 12] |= (*index as i64) << (i % 12 * 5);
+        }
+
+        let biomes = |names: &[&String], data: Option<Vec<i64>>| {
+            let mut values = vec![("palette", Tag::List(names.iter()
+                .map(|name| Tag::String(name.to_string()))
+                .collect()))];
+            values.extend(data.map(|data| ("data", Tag::LongArray(data))));
+            compound(values)
+        };
+        let tag = compound(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("sections", Tag::List(vec![
+                compound(vec![("Y", Tag::Byte(0)), ("biomes", biomes(names, Some(longs)))]),
+                // A single biome is stored without any indices.
+                compound(vec![("Y", Tag::Byte(1)), ("biomes", biomes(&names[16..], None))]),
+            ])),
+        ]);
+        let chunk = read_chunk(&tag, 3465, &types).unwrap();
+
+        let bio = (-MIN_SECTION_Y) as usize * BIOMES_IN_SECTION_3D;
+        for (i, index) in indices.iter().enumerate() {
+            assert_eq!(chunk.biomes[bio + i], types.biome_registry[names[*index]]);
+        }
+        let bio = bio + BIOMES_IN_SECTION_3D;
+        assert!(chunk.biomes[bio..(bio + BIOMES_IN_SECTION_3D)].iter()
+            .all(|biome| *biome == types.biome_registry[names[16]]));
+        assert_eq!(chunk.biomes[bio - BIOMES_IN_SECTION_3D - 1], 0);
+    }
+
+    #[test]
+    fn read_column_biomes() {
+        let types = get_types();
+        // Each column's biome is its own index, Z * 16 + X.
+        let tag = compound(vec![
+            ("DataVersion", Tag::Int(1631)),
+            ("Level", compound(vec![
+                ("Biomes", Tag::IntArray((0..BLOCKS_IN_CHUNK_2D as i32).collect())),
+            ])),
+        ]);
+        let chunk = read_chunk(&tag, 1631, &types).unwrap();
+
+        // Each 4x4 area takes the column at X and Z 2 within it, for the full height.
+        let top = BIOMES_IN_CHUNK_3D - BIOMES_IN_CHUNK_2D;
+        for (bio, column) in [(0, 34), (1, 38), (7, 110), (15, 238), (top, 34), (top + 7, 110)] {
+            assert_eq!(chunk.biomes[bio], column);
+        }
+    }
+
+    #[test]
+    fn read_legacy_chunk() {
+        let types = get_types();
+        let mut ids = vec![0u8; BLOCKS_IN_SECTION_3D];
+        ids[..5].copy_from_slice(&[1, 35, 1, 253, 35]);
+        // Granite and red wool, then stone with an Add nibble that makes it ID 257.
+        let mut data = vec![0u8; BLOCKS_IN_SECTION_3D / 2];
+        data[0] = 0xe1;
+        let mut add = vec![0u8; BLOCKS_IN_SECTION_3D / 2];
+        add[1] = 0x01;
+
+        let tag = compound(vec![
+            ("DataVersion", Tag::Int(1343)),
+            ("Level", compound(vec![
+                ("TerrainPopulated", Tag::Byte(1)),
+                ("Sections", Tag::List(vec![compound(vec![
+                    ("Y", Tag::Byte(0)),
+                    ("Blocks", Tag::ByteArray(ids)),
+                    ("Data", Tag::ByteArray(data)),
+                    ("Add", Tag::ByteArray(add)),
+                    ("SkyLight", nibbles(0)),
+                    ("BlockLight", nibbles(0)),
+                ])])),
+            ])),
+        ]);
+        let chunk = read_chunk(&tag, 1343, &types).unwrap();
+
+        let unknown = blocktypes::get_unknown_block_type(&types.blocktypes);
+        let blocks: Vec<u16> = (0..6).map(|bx| chunk.blocks[offset(bx, 0, 0)]).collect();
+        assert_eq!(blocks, [
+            find_block(&types, "minecraft:granite"),
+            find_block(&types, "minecraft:red_wool"),
+            unknown,
+            unknown,
+            find_block(&types, "minecraft:white_wool"),
+            find_block(&types, "minecraft:air"),
+        ]);
+        assert_eq!(chunk.blocks[offset(0, -1, 0)], 0);
+        assert_eq!(chunk.lights[offset(0, -1, 0)], 0x0f);
+        assert!(chunk.full);
+    }
+
+    #[test]
+    fn read_mcregion() {
+        let types = get_types();
+        // Y changes fastest, then Z, then X.
+        let index = |bx: usize, by: usize, bz: usize| (bx * BLOCKS_IN_CHUNK + bz) *
+            MCREGION_BLOCKS_IN_CHUNK_Y + by;
+        let mut ids = vec![0u8; MCREGION_BLOCKS_IN_CHUNK_3D];
+        ids[index(0, 0, 0)] = 7;
+        ids[index(1, 5, 2)] = 1;
+        ids[index(15, 127, 15)] = 20;
+        // Granite, lit by skylight 9, which is in the top half of the byte at an odd index.
+        let mut data = vec![0u8; MCREGION_BLOCKS_IN_CHUNK_3D / 2];
+        data[index(1, 5, 2) / 2] = 0x10;
+        let mut slight = vec![0u8; MCREGION_BLOCKS_IN_CHUNK_3D / 2];
+        slight[index(1, 5, 2) / 2] = 0x90;
+        assert_eq!(index(1, 5, 2) % 2, 1);
+
+        let tag = compound(vec![("Level", compound(vec![
+            ("Blocks", Tag::ByteArray(ids)),
+            ("Data", Tag::ByteArray(data)),
+            ("SkyLight", Tag::ByteArray(slight)),
+            ("BlockLight", Tag::ByteArray(vec![0u8; MCREGION_BLOCKS_IN_CHUNK_3D / 2])),
+        ]))]);
+        let mut reader = chunk_reader(&tag);
+        let chunk = with_big_stack(|| read_mcregion_chunk(&mut reader, &types.blocktypes,
+            &types.biome_registry, &types.legacy_blocktypes).unwrap().map(Box::new)).unwrap();
+
+        assert_eq!(chunk.blocks[offset(0, 0, 0)], find_block(&types, "minecraft:bedrock"));
+        assert_eq!(chunk.blocks[offset(1, 5, 2)], find_block(&types, "minecraft:granite"));
+        assert_eq!(chunk.blocks[offset(15, 127, 15)], find_block(&types, "minecraft:glass"));
+        assert_eq!(chunk.blocks[offset(2, 5, 1)], find_block(&types, "minecraft:air"));
+        assert_eq!(chunk.blocks[offset(0, 128, 0)], 0);
+        assert_eq!(chunk.blocks[offset(0, -1, 0)], 0);
+        assert_eq!(chunk.lights[offset(1, 5, 2)], 9);
+        assert_eq!(chunk.lights[offset(2, 5, 1)], 0);
+        // Like Anvil sections that weren't saved, the blocks above and below get full skylight.
+        assert_eq!(chunk.lights[offset(0, 128, 0)], 0x0f);
+        assert_eq!(chunk.lights[offset(0, -1, 0)], 0x0f);
+    }
+
+    const VALUES: [usize; 14] = [0, 7, 14, 21, 28, 3, 10, 17, 24, 31, 6, 13, 20, 27];
+
+    #[test]
+    fn unpack_padded_indices() {
+        // Twelve 5-bit indices to a long, with the top four bits unused.
+        let longs = [0x069bf88a87cab8e0i64, 0x374];
+        assert_eq!(unpack_indices(|i| longs[i], 5, 14, true), VALUES);
+
+        let longs = [0xfedcba9876543210u64 as i64];
+        assert_eq!(unpack_indices(|i| longs[i], 4, 16, true), (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unpack_unpadded_indices() {
+        // The 13th index takes the top four bits of the first long and the low bit of the next.
+        let longs = [0x469bf88a87cab8e0i64, 0x37];
+        assert_eq!(unpack_indices(|i| longs[i], 5, 14, false), VALUES);
+    }
+
+    #[test]
+    fn palette_bit_widths() {
+        assert_eq!(palette_bits(1, 0), 0);
+        assert_eq!(palette_bits(2, 0), 1);
+        assert_eq!(palette_bits(5, 0), 3);
+        assert_eq!(palette_bits(2, 4), 4);
+        assert_eq!(palette_bits(16, 4), 4);
+        assert_eq!(palette_bits(17, 4), 5);
+        assert_eq!(palette_bits(64, 1), 6);
+    }
+
+    #[test]
+    fn read_external_chunk() {
+        let dir = std::env::temp_dir().join(format!("rmapbash-mcc-{}", std::process::id()));
+        let regionpath = dir.join("region");
+        std::fs::create_dir_all(&regionpath).unwrap();
+
+        // Chunk 1, 2 of region -1, 0 is in sector 2, with only its compression type in the region.
+        let mut bytes = vec![0u8; SECTOR_SIZE * 3];
+        BigEndian::write_u32(&mut bytes[((2 * CHUNKS_IN_REGION + 1) * 4)..], 2 << 8 | 1);
+        let mut chunk = &mut bytes[(SECTOR_SIZE * 2)..];
+        chunk.write_u32::<BigEndian>(1).unwrap();
+        chunk.write_u8(compression::ZLIB | compression::EXTERNAL_FLAG).unwrap();
+        std::fs::write(regionpath.join("r.-1.0.mca"), bytes).unwrap();
+
+        let tag = compound(vec![("DataVersion", Tag::Int(3465)), ("xPos", Tag::Int(-31))]);
+        let data = compression::compress(compression::ZLIB, &to_bytes(&tag)).unwrap();
+        std::fs::write(regionpath.join("c.-31.2.mcc"), data).unwrap();
+
+        let result = open_world_chunk(&dir, &Pair { x: -31, z: 2 });
+        let missing = open_world_chunk(&dir, &Pair { x: -31, z: 3 });
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (path, mut reader) = result.unwrap();
+        assert_eq!(path, regionpath.join("r.-1.0.mca"));
+        assert_eq!(nbt::read_tag_payload(&mut reader, &tag.id()).unwrap(), tag);
+        assert!(missing.is_err());
+    }
+
+    // Write a region file with each chunk's data in its own sector, compressed with zlib.
+    fn write_region(path: &Path, chunks: &[(usize, Vec<u8>)]) {
+        let mut bytes = vec![0u8; SECTOR_SIZE * 2];
+        for (index, data) in chunks {
+            let sector = bytes.len() / SECTOR_SIZE;
+            BigEndian::write_u32(&mut bytes[(index * 4)..], (sector << 8 | 1) as u32);
+            bytes.write_u32::<BigEndian>(data.len() as u32 + 1).unwrap();
+            bytes.write_u8(compression::ZLIB).unwrap();
+            bytes.extend_from_slice(data);
+            bytes.resize((sector + 1) * SECTOR_SIZE, 0);
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn read_corrupt_chunk() {
+        let types = get_types();
+        let dir = std::env::temp_dir().join(format!("rmapbash-corrupt-{}", std::process::id()));
+        let regionpath = dir.join("region");
+        std::fs::create_dir_all(&regionpath).unwrap();
+
+        let tag = compound(vec![
+            ("DataVersion", Tag::Int(3465)),
+            ("Status", Tag::String("minecraft:full".to_string())),
+            ("sections", Tag::List(vec![compound(vec![
+                ("Y", Tag::Byte(0)),
+                ("block_states", compound(vec![("palette", palette(&["minecraft:glass"]))])),
+            ])])),
+        ]);
+        let good = compression::compress(compression::ZLIB, &to_bytes(&tag)).unwrap();
+        // Chunk 1, 0 isn't zlib data.
+        write_region(&regionpath.join("r.0.0.mca"), &[(0, good.clone()), (1, vec![0; 100])]);
+        // Nor is the chunk in the next region east that borders chunk 1, 0.
+        write_region(&regionpath.join("r.1.0.mca"), &[(0, vec![0; 100]), (1, good)]);
+
+        let ylimits = 0..BLOCKS_IN_CHUNK_Y;
+        let world = World {
+            path: &dir,
+            format: WorldFormat::Java(RegionFormat::Anvil),
+            regions: HashMap::new(),
+            redges: Edges { n: 0, e: 0, s: 0, w: 0 },
+            cedges: Edges { n: 0, e: 1, s: 0, w: 0 },
+            bedges: Edges { n: 0, e: 31, s: 15, w: 0 },
+            rsize: Pair { x: 1, z: 1 },
+            csize: Pair { x: 2, z: 1 },
+            bsize: Pair { x: 32, z: 16 },
+            ylimits: &ylimits,
+            level: None,
+        };
+        let result = with_big_stack(|| read_region_data(&world, &Pair { x: 0, z: 0 },
+            RegionFormat::Anvil, &types.blocktypes, &types.biome_registry,
+            &types.legacy_blocktypes, true).unwrap().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let good = &result.chunks[&Pair { x: 0, z: 0 }];
+        assert!(!good.corrupt);
+        assert_eq!(good.blocks[offset(0, 0, 0)], find_block(&types, "minecraft:glass"));
+
+        // The chunk that can't be read is replaced by a fully lit layer of stone at the top.
+        let placeholder = &result.chunks[&Pair { x: 1, z: 0 }];
+        assert!(placeholder.corrupt);
+        let stone = find_block(&types, "minecraft:stone");
+        let top = (BLOCKS_IN_CHUNK_Y - 1) * BLOCKS_IN_CHUNK_2D;
+        assert!(placeholder.blocks[top..].iter().all(|block| *block == stone));
+        assert!(placeholder.blocks[..top].iter().all(|block| *block == 0));
+        assert!(placeholder.lights.iter().all(|light| *light == MAX_LIGHT_LEVEL));
+
+        // Only this region's errors are reported, and the bad neighbouring chunk is left out.
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].0, Pair { x: 1, z: 0 });
+        assert!(result.nchunks.e.is_empty());
+    }
+}
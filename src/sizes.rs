@@ -1,17 +1,21 @@
 pub const BLOCKS_IN_CHUNK: usize = 16;
 pub const MAX_BLOCK_IN_CHUNK: usize = BLOCKS_IN_CHUNK - 1; // 15
 
+// Chunk arrays start from the lowest section, so Y offsets within them start at MIN_BLOCK_Y.
 pub const BLOCKS_IN_SECTION_Y: usize = 16;
-pub const SECTIONS_IN_CHUNK_Y: usize = 16;
-pub const MAX_SECTION_IN_CHUNK_Y: usize = SECTIONS_IN_CHUNK_Y - 1; // 15
-pub const BLOCKS_IN_CHUNK_Y: usize = BLOCKS_IN_SECTION_Y * SECTIONS_IN_CHUNK_Y; // 256
-pub const MAX_BLOCK_IN_CHUNK_Y: usize = BLOCKS_IN_CHUNK_Y - 1; // 255
+pub const MIN_SECTION_Y: isize = -4;
+pub const SECTIONS_IN_CHUNK_Y: usize = 24;
+pub const MAX_SECTION_IN_CHUNK_Y: usize = SECTIONS_IN_CHUNK_Y - 1; // 23
+pub const BLOCKS_IN_CHUNK_Y: usize = BLOCKS_IN_SECTION_Y * SECTIONS_IN_CHUNK_Y; // 384
+pub const MAX_BLOCK_IN_CHUNK_Y: usize = BLOCKS_IN_CHUNK_Y - 1; // 383
+pub const MIN_BLOCK_Y: isize = MIN_SECTION_Y * BLOCKS_IN_SECTION_Y as isize; // -64
+pub const MAX_BLOCK_Y: isize = MIN_BLOCK_Y + MAX_BLOCK_IN_CHUNK_Y as isize; // 319
 
 pub const BLOCKS_IN_CHUNK_2D: usize = BLOCKS_IN_CHUNK * BLOCKS_IN_CHUNK; // 256
-pub const BLOCKS_IN_CHUNK_3D: usize = BLOCKS_IN_CHUNK_2D * BLOCKS_IN_CHUNK_Y; // 65536
+pub const BLOCKS_IN_CHUNK_3D: usize = BLOCKS_IN_CHUNK_2D * BLOCKS_IN_CHUNK_Y; // 98304
 
 pub const BLOCKS_IN_SECTION_3D: usize = BLOCKS_IN_CHUNK_2D * BLOCKS_IN_SECTION_Y; // 4096
-// pub const BLOCKS_IN_CHUNK_3D: usize = BLOCKS_IN_SECTION_3D * SECTIONS_Y; // 65536
+// pub const BLOCKS_IN_CHUNK_3D: usize = BLOCKS_IN_SECTION_3D * SECTIONS_Y; // 98304
 
 pub const CHUNKS_IN_REGION: usize = 32;
 pub const CHUNKS_IN_REGION_2D: usize = CHUNKS_IN_REGION * CHUNKS_IN_REGION; // 1024
@@ -20,12 +24,28 @@ pub const MAX_CHUNK_IN_REGION: usize = CHUNKS_IN_REGION - 1; // 31
 
 pub const SECTOR_SIZE: usize = 4096;
 
+// Before 1.13, blocks were stored as an 8-bit ID and a 4-bit data value.
+pub const LEGACY_BLOCK_IDS: usize = 256;
+pub const LEGACY_DATA_VALUES: usize = 16;
+
+// Before 1.2, McRegion chunks were 128 blocks tall and stored without sections.
+pub const MCREGION_BLOCKS_IN_CHUNK_Y: usize = 128;
+pub const MCREGION_BLOCKS_IN_CHUNK_3D: usize =
+    BLOCKS_IN_CHUNK_2D * MCREGION_BLOCKS_IN_CHUNK_Y; // 32768
+
 // Biomes are stored as 4x4x4 block volumes.
 pub const BLOCKS_IN_BIOME: usize = 4;
 pub const BIOMES_IN_CHUNK: usize = BLOCKS_IN_CHUNK / BLOCKS_IN_BIOME; // 4
 pub const BIOMES_IN_CHUNK_2D: usize = BIOMES_IN_CHUNK * BIOMES_IN_CHUNK; // 16
-pub const BIOMES_IN_CHUNK_Y: usize = BLOCKS_IN_CHUNK_Y / BLOCKS_IN_BIOME; // 64
-pub const BIOMES_IN_CHUNK_3D: usize = BIOMES_IN_CHUNK_2D * BIOMES_IN_CHUNK_Y; // 1024
+pub const BIOMES_IN_CHUNK_Y: usize = BLOCKS_IN_CHUNK_Y / BLOCKS_IN_BIOME; // 96
+pub const BIOMES_IN_CHUNK_3D: usize = BIOMES_IN_CHUNK_2D * BIOMES_IN_CHUNK_Y; // 1536
+pub const BIOMES_IN_SECTION_Y: usize = BLOCKS_IN_SECTION_Y / BLOCKS_IN_BIOME; // 4
+pub const BIOMES_IN_SECTION_3D: usize = BIOMES_IN_CHUNK_2D * BIOMES_IN_SECTION_Y; // 64
+
+// Before 1.18, chunks were 256 blocks tall, starting at Y=0.
+pub const LEGACY_BLOCKS_IN_CHUNK_Y: usize = 256;
+pub const LEGACY_BIOMES_IN_CHUNK_3D: usize =
+    BIOMES_IN_CHUNK_2D * LEGACY_BLOCKS_IN_CHUNK_Y / BLOCKS_IN_BIOME; // 1024
 
 
 // pixel dimensions for isometric rendering
@@ -38,12 +58,12 @@ pub const ISO_BLOCK_X_MARGIN: usize = ISO_BLOCK_WIDTH / 2; // 2
 pub const ISO_BLOCK_Y_MARGIN: usize = ISO_BLOCK_TOP_HEIGHT / 2; // 1
 
 pub const ISO_CHUNK_WIDTH: usize = ISO_BLOCK_WIDTH * BLOCKS_IN_CHUNK; // 64
-pub const ISO_CHUNK_SIDE_HEIGHT: usize = ISO_BLOCK_SIDE_HEIGHT * BLOCKS_IN_CHUNK_Y; // 768
+pub const ISO_CHUNK_SIDE_HEIGHT: usize = ISO_BLOCK_SIDE_HEIGHT * BLOCKS_IN_CHUNK_Y; // 1152
 pub const ISO_CHUNK_X_MARGIN: usize = ISO_CHUNK_WIDTH / 2; // 32
 pub const ISO_CHUNK_Y_MARGIN: usize = ISO_BLOCK_Y_MARGIN * BLOCKS_IN_CHUNK; // 16
 
 // pub const ISO_REGION_WIDTH: usize = ISO_CHUNK_WIDTH * CHUNKS_IN_REGION; // 2048
-// pub const ISO_REGION_SIDE_HEIGHT: usize = ISO_CHUNK_SIDE_HEIGHT; // 768
+// pub const ISO_REGION_SIDE_HEIGHT: usize = ISO_CHUNK_SIDE_HEIGHT; // 1152
 // pub const ISO_REGION_X_MARGIN: usize = ISO_REGION_WIDTH / 2; // 1024
 // pub const ISO_REGION_Y_MARGIN: usize = ISO_CHUNK_Y_MARGIN * CHUNKS_IN_REGION; // 512
 
@@ -51,8 +71,6 @@ pub const ISO_CHUNK_Y_MARGIN: usize = ISO_BLOCK_Y_MARGIN * BLOCKS_IN_CHUNK; // 1
 pub const LIGHT_LEVELS: usize = 16;
 pub const MAX_LIGHT_LEVEL: u8 = LIGHT_LEVELS as u8 - 1;
 
-pub const BIOME_ARRAY_SIZE: usize = 170;
-
 pub const MAX_CHANNEL_VALUE: u8 = 255;
 
 
@@ -72,6 +90,16 @@ pub fn chunk_to_region(c: isize) -> isize {
 }
 
 
+// convert between a world Y coord and its offset within a chunk's block arrays
+
+pub fn block_y_to_offset(y: isize) -> usize {
+    (y.clamp(MIN_BLOCK_Y, MAX_BLOCK_Y) - MIN_BLOCK_Y) as usize
+}
+pub fn offset_to_block_y(by: usize) -> isize {
+    by as isize + MIN_BLOCK_Y
+}
+
+
 // convert a block or chunk coord to its local coord within a chunk or region
 
 fn signed_modulo(value: isize, ratio: usize) -> usize {
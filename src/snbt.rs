@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::ErrorKind;
+
+use super::nbt::Tag;
+
+const INDENT: &str = "    ";
+
+// Characters that keys and values can use without quotes.
+fn is_unquoted_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-.+".contains(c)
+}
+
+fn format_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Minecraft has no literals for infinity or NaN. Infinities are written as numbers too big for
+// their type, which Minecraft and parse_tag both read as infinity. NaN is written the way Java
+// prints it, which only parse_tag reads back; Minecraft would take it as a string.
+fn format_non_finite(value: f64) -> &'static str {
+    if value.is_nan() {
+        "NaN"
+    } else if value > 0.0 {
+        "1e999"
+    } else {
+        "-1e999"
+    }
+}
+
+fn format_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(is_unquoted_char) {
+        key.to_string()
+    } else {
+        format_string(key)
+    }
+}
+
+// Format a tag as SNBT, the syntax used in commands, with each compound value on its own line.
+pub fn format_tag(tag: &Tag) -> String {
+    let mut snbt = String::new();
+    write_tag(&mut snbt, tag, 0);
+    snbt
+}
+
+fn write_tag(snbt: &mut String, tag: &Tag, depth: usize) {
+    match tag {
+        Tag::Byte(value) => write!(snbt, "{}b", value),
+        Tag::Short(value) => write!(snbt, "{}s", value),
+        Tag::Int(value) => write!(snbt, "{}", value),
+        Tag::Long(value) => write!(snbt, "{}L", value),
+        Tag::Float(value) if !value.is_finite() =>
+            write!(snbt, "{}f", format_non_finite(*value as f64)),
+        Tag::Float(value) => write!(snbt, "{:?}f", value),
+        Tag::Double(value) if !value.is_finite() =>
+            write!(snbt, "{}d", format_non_finite(*value)),
+        Tag::Double(value) => write!(snbt, "{:?}d", value),
+        Tag::ByteArray(array) => write!(snbt, "[B; {}]", array.iter()
+            .map(|value| format!("{}b", *value as i8)).collect::<Vec<String>>().join(", ")),
+        Tag::String(value) => write!(snbt, "{}", format_string(value)),
+        Tag::IntArray(array) => write!(snbt, "[I; {}]", array.iter()
+            .map(|value| value.to_string()).collect::<Vec<String>>().join(", ")),
+        Tag::LongArray(array) => write!(snbt, "[L; {}]", array.iter()
+            .map(|value| format!("{}L", value)).collect::<Vec<String>>().join(", ")),
+        Tag::List(list) => {
+            // Lists of numbers and strings fit on one line.
+            if list.iter().all(|tag| !matches!(tag, Tag::List(_) | Tag::Compound(_))) {
+                let values: Vec<String> = list.iter().map(format_tag).collect();
+                write!(snbt, "[{}]", values.join(", "))
+            } else {
+                snbt.push('[');
+                for (i, tag) in list.iter().enumerate() {
+                    write_line_start(snbt, i, depth + 1);
+                    write_tag(snbt, tag, depth + 1);
+                }
+                write_line_start(snbt, 0, depth);
+                write!(snbt, "]")
+            }
+        },
+        Tag::Compound(values) => {
+            if values.is_empty() {
+                write!(snbt, "{{}}")
+            } else {
+                // Sort keys so that the same data is always formatted the same way.
+                let mut keys: Vec<&String> = values.keys().collect();
+                keys.sort();
+                snbt.push('{');
+                for (i, key) in keys.into_iter().enumerate() {
+                    write_line_start(snbt, i, depth + 1);
+                    write!(snbt, "{}: ", format_key(key)).unwrap();
+                    write_tag(snbt, &values[key], depth + 1);
+                }
+                write_line_start(snbt, 0, depth);
+                write!(snbt, "}}")
+            }
+        },
+    }.unwrap();
+}
+
+// Start a new indented line, after a comma if this isn't the first value.
+fn write_line_start(snbt: &mut String, i: usize, depth: usize) {
+    if i > 0 {
+        snbt.push(',');
+    }
+    snbt.push('\n');
+    snbt.push_str(&INDENT.repeat(depth));
+}
+
+struct Parser<'a> {
+    snbt: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> std::io::Error {
+        std::io::Error::new(ErrorKind::InvalidData, format!("{} at position {}", msg, self.pos))
+    }
+
+    // Skip whitespace and return the next character without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.snbt[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        self.snbt[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), std::io::Error> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected '{}'", c)));
+        }
+        self.pos += c.len_utf8();
+        Ok(())
+    }
+
+    fn read_unquoted(&mut self) -> &'a str {
+        self.peek();
+        let start = self.pos;
+        let rest = &self.snbt[start..];
+        self.pos += rest.find(|c| !is_unquoted_char(c)).unwrap_or(rest.len());
+        &self.snbt[start..self.pos]
+    }
+
+    fn read_quoted(&mut self) -> Result<String, std::io::Error> {
+        let quote = self.peek().unwrap();
+        self.pos += 1;
+        let mut value = String::new();
+        let mut chars = self.snbt[self.pos..].chars();
+        while let Some(c) = chars.next() {
+            self.pos += c.len_utf8();
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) if c == '\\' || c == quote => {
+                        self.pos += 1;
+                        value.push(c);
+                    },
+                    _ => return Err(self.error("Invalid escape sequence")),
+                },
+                c if c == quote => return Ok(value),
+                c => value.push(c),
+            }
+        }
+        Err(self.error("Unterminated string"))
+    }
+
+    fn read_string(&mut self) -> Result<String, std::io::Error> {
+        match self.peek() {
+            Some('"') | Some('\'') => self.read_quoted(),
+            _ => match self.read_unquoted() {
+                "" => Err(self.error("Expected a key or value")),
+                value => Ok(value.to_string()),
+            },
+        }
+    }
+
+    fn read_value(&mut self) -> Result<Tag, std::io::Error> {
+        match self.peek() {
+            Some('{') => self.read_compound(),
+            Some('[') => self.read_list(),
+            Some('"') | Some('\'') => Ok(Tag::String(self.read_quoted()?)),
+            _ => match self.read_unquoted() {
+                "" => Err(self.error("Expected a value")),
+                value => Ok(parse_primitive(value)),
+            },
+        }
+    }
+
+    fn read_compound(&mut self) -> Result<Tag, std::io::Error> {
+        self.expect('{')?;
+        let mut values = HashMap::new();
+        if self.peek() != Some('}') {
+            loop {
+                let key = self.read_string()?;
+                self.expect(':')?;
+                values.insert(key, self.read_value()?);
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect('}')?;
+        Ok(Tag::Compound(values))
+    }
+
+    // Read the values of a list or array, up to the closing bracket.
+    fn read_list_values(&mut self) -> Result<Vec<Tag>, std::io::Error> {
+        let mut list: Vec<Tag> = vec![];
+        if self.peek() != Some(']') {
+            loop {
+                let start = self.pos;
+                let tag = self.read_value()?;
+                if list.first().is_some_and(|first| first.id() != tag.id()) {
+                    self.pos = start;
+                    return Err(self.error("Values must all have the same type"));
+                }
+                list.push(tag);
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect(']')?;
+        Ok(list)
+    }
+
+    fn read_list(&mut self) -> Result<Tag, std::io::Error> {
+        self.expect('[')?;
+
+        // Arrays start with their type, as in [I; 1, 2, 3].
+        let mut prefix = self.snbt[self.pos..].chars();
+        let array_id = match (prefix.next(), prefix.next()) {
+            (Some('B'), Some(';')) => 1,
+            (Some('I'), Some(';')) => 3,
+            (Some('L'), Some(';')) => 4,
+            _ => return Ok(Tag::List(self.read_list_values()?)),
+        };
+        self.pos += 2;
+        let list = self.read_list_values()?;
+        if list.first().is_some_and(|tag| tag.id() != array_id) {
+            return Err(self.error("Array values don't match the array type"));
+        }
+
+        Ok(match array_id {
+            1 => Tag::ByteArray(list.iter().map(|tag| tag.to_i8().map(|value| *value as u8))
+                .collect::<Result<_, _>>()?),
+            3 => Tag::IntArray(list.iter().map(|tag| tag.to_i32().copied())
+                .collect::<Result<_, _>>()?),
+            _ => Tag::LongArray(list.iter().map(|tag| tag.to_i64().copied())
+                .collect::<Result<_, _>>()?),
+        })
+    }
+}
+
+// Parse an unquoted value as a number with an optional type suffix, or a boolean.
+// As in Minecraft, anything else (including numbers out of range) is a string.
+fn parse_primitive(value: &str) -> Tag {
+    match value {
+        "true" => return Tag::Byte(1),
+        "false" => return Tag::Byte(0),
+        _ => {},
+    }
+
+    let (number, suffix) = match value.chars().last() {
+        Some(c) if "bBsSlLfFdD".contains(c) => (&value[..(value.len() - 1)], Some(c)),
+        _ => (value, None),
+    };
+    match (number, suffix.map(|c| c.to_ascii_lowercase())) {
+        ("NaN", Some('f')) => return Tag::Float(f32::NAN),
+        ("NaN", Some('d')) => return Tag::Double(f64::NAN),
+        _ => {},
+    }
+    let is_number = number.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c))
+        && number.contains(|c: char| c.is_ascii_digit())
+        && number.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+    if !is_number {
+        return Tag::String(value.to_string());
+    }
+
+    match suffix.map(|c| c.to_ascii_lowercase()) {
+        Some('b') => number.parse::<i8>().ok().map(Tag::Byte),
+        Some('s') => number.parse::<i16>().ok().map(Tag::Short),
+        Some('l') => number.parse::<i64>().ok().map(Tag::Long),
+        Some('f') => number.parse::<f32>().ok().map(Tag::Float),
+        Some('d') => number.parse::<f64>().ok().map(Tag::Double),
+        _ if number.contains('.') => number.parse::<f64>().ok().map(Tag::Double),
+        _ => number.parse::<i32>().ok().map(Tag::Int),
+    }.unwrap_or_else(|| Tag::String(value.to_string()))
+}
+
+pub fn parse_tag(snbt: &str) -> Result<Tag, std::io::Error> {
+    let mut parser = Parser { snbt, pos: 0 };
+    let tag = parser.read_value()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("Unexpected data after value"));
+    }
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::testing::compound;
+
+    #[test]
+    fn format_values() {
+        let tag = compound(vec![
+            ("Count", Tag::Byte(-1)),
+            ("Damage", Tag::Short(3)),
+            ("id", Tag::String("minecraft:stone".to_string())),
+            ("Time", Tag::Long(3)),
+            ("Pos", Tag::List(vec![Tag::Double(0.5), Tag::Double(64.0)])),
+            ("UUID", Tag::IntArray(vec![1, -1])),
+            ("custom name", Tag::String("say \"hi\"".to_string())),
+            ("Items", Tag::List(vec![compound(vec![]), Tag::Compound(HashMap::new())])),
+        ]);
+        assert_eq!(format_tag(&tag), [
+            "{",
+            "    Count: -1b,",
+            "    Damage: 3s,",
+            "    Items: [",
+            "        {},",
+            "        {}",
+            "    ],",
+            "    Pos: [0.5d, 64.0d],",
+            "    Time: 3L,",
+            "    UUID: [I; 1, -1],",
+            "    \"custom name\": \"say \\\"hi\\\"\",",
+            "    id: \"minecraft:stone\"",
+            "}",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn parse_values() {
+        let tag = parse_tag("{Count:1b, 'quoted key' : 3L, Pos:[0.5d,-2.0], UUID:[I;1,-1],
+            Bytes: [B; 1b, true], Longs: [L;], Name: \"a \\\"b\\\"\", id: stone, f: 1.5F}")
+            .unwrap();
+        assert_eq!(tag, compound(vec![
+            ("Count", Tag::Byte(1)),
+            ("quoted key", Tag::Long(3)),
+            ("Pos", Tag::List(vec![Tag::Double(0.5), Tag::Double(-2.0)])),
+            ("UUID", Tag::IntArray(vec![1, -1])),
+            ("Bytes", Tag::ByteArray(vec![1, 1])),
+            ("Longs", Tag::LongArray(vec![])),
+            ("Name", Tag::String("a \"b\"".to_string())),
+            ("id", Tag::String("stone".to_string())),
+            ("f", Tag::Float(1.5)),
+        ]));
+    }
+
+    #[test]
+    fn parse_strings_that_look_like_numbers() {
+        assert_eq!(parse_tag("300b").unwrap(), Tag::String("300b".to_string()));
+        assert_eq!(parse_tag("1e5").unwrap(), Tag::String("1e5".to_string()));
+        assert_eq!(parse_tag("infd").unwrap(), Tag::String("infd".to_string()));
+        assert_eq!(parse_tag("1e5d").unwrap(), Tag::Double(1e5));
+    }
+
+    #[test]
+    fn round_trip() {
+        let tag = compound(vec![
+            ("a", Tag::List(vec![Tag::List(vec![Tag::Short(-1)]), Tag::List(vec![])])),
+            ("b", Tag::ByteArray(vec![0, 128, 255])),
+            ("c", Tag::LongArray(vec![-1, 1 << 62])),
+            ("d", Tag::Float(-0.1)),
+            ("e", Tag::Double(1e100)),
+            ("", Tag::String("back\\slash 'quote' ✓".to_string())),
+        ]);
+        assert_eq!(parse_tag(&format_tag(&tag)).unwrap(), tag);
+    }
+
+    #[test]
+    fn round_trip_non_finite() {
+        let tag = Tag::List(vec![Tag::Float(f32::INFINITY), Tag::Float(f32::NEG_INFINITY)]);
+        assert_eq!(format_tag(&tag), "[1e999f, -1e999f]");
+        assert_eq!(parse_tag(&format_tag(&tag)).unwrap(), tag);
+
+        let tag = Tag::List(vec![Tag::Double(f64::INFINITY), Tag::Double(f64::NEG_INFINITY)]);
+        assert_eq!(format_tag(&tag), "[1e999d, -1e999d]");
+        assert_eq!(parse_tag(&format_tag(&tag)).unwrap(), tag);
+
+        assert_eq!(format_tag(&Tag::Float(f32::NAN)), "NaNf");
+        assert!(matches!(parse_tag("NaNf").unwrap(), Tag::Float(value) if value.is_nan()));
+        assert_eq!(format_tag(&Tag::Double(f64::NAN)), "NaNd");
+        assert!(matches!(parse_tag("NaNd").unwrap(), Tag::Double(value) if value.is_nan()));
+        assert_eq!(parse_tag("NaN").unwrap(), Tag::String("NaN".to_string()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_tag("{a:1,}").is_err());
+        assert!(parse_tag("[1b, 2s]").is_err());
+        assert!(parse_tag("[I; 1b]").is_err());
+        assert!(parse_tag("{a:\"unterminated}").is_err());
+        assert!(parse_tag("{a:1} b").is_err());
+    }
+}
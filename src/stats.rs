@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use serde::Serialize;
+
+use super::biometypes::{self, BiomeType};
+use super::blocktypes::{self, BlockType};
+use super::dimension;
+use super::options::Options;
+use super::region::ChunkData;
+use super::sizes::*;
+use super::types::*;
+use super::world;
+
+// Ores whose heights are counted when no other blocks are chosen.
+pub const DEFAULT_HISTOGRAM_BLOCKS: [&str; 18] = [
+    "coal_ore",
+    "deepslate_coal_ore",
+    "iron_ore",
+    "deepslate_iron_ore",
+    "copper_ore",
+    "deepslate_copper_ore",
+    "gold_ore",
+    "deepslate_gold_ore",
+    "redstone_ore",
+    "deepslate_redstone_ore",
+    "lapis_ore",
+    "deepslate_lapis_ore",
+    "diamond_ore",
+    "deepslate_diamond_ore",
+    "emerald_ore",
+    "deepslate_emerald_ore",
+    "nether_gold_ore",
+    "nether_quartz_ore",
+];
+
+// Block and biome counts, and the count of some blocks at each Y level.
+struct Stats {
+    chunks: usize,
+    // Indexed by block type.
+    blocks: Vec<u64>,
+    // Indexed by biome index.
+    biomes: Vec<u64>,
+    // For each chosen block name, the count at each Y offset.
+    histograms: Vec<(String, Vec<u64>)>,
+    // The histogram for each block type with a chosen name.
+    histogram_types: HashMap<u16, usize>,
+}
+
+impl Stats {
+    fn new(blocktypes: &[BlockType], names: &[String]) -> Stats {
+        let mut histograms = vec![];
+        let mut histogram_types = HashMap::new();
+        for name in names {
+            let btypes: Vec<usize> = blocktypes.iter().enumerate()
+                .filter(|(_, blocktype)| &blocktype.name == name)
+                .map(|(btype, _)| btype)
+                .collect();
+            if btypes.is_empty() {
+                println!("Unknown block type {}", name);
+                continue;
+            }
+            for btype in btypes {
+                histogram_types.insert(btype as u16, histograms.len());
+            }
+            histograms.push((name.clone(), vec![0; BLOCKS_IN_CHUNK_Y]));
+        }
+
+        Stats {
+            chunks: 0,
+            blocks: vec![0; blocktypes.len()],
+            biomes: vec![0; 256],
+            histograms,
+            histogram_types,
+        }
+    }
+
+    // Count the blocks within the limits of a chunk, and the biome of each one. Parts of the
+    // chunk with no block data are block type 0, and aren't counted.
+    fn add_chunk(&mut self, chunk: &ChunkData, cblimits: &Edges<usize>, ylimits: &Range<usize>) {
+        self.chunks += 1;
+        for by in ylimits.clone() {
+            for bz in cblimits.n..(cblimits.s + 1) {
+                for bx in cblimits.w..(cblimits.e + 1) {
+                    let btype = chunk.blocks[by * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx];
+                    if btype == 0 {
+                        continue;
+                    }
+                    self.blocks[btype as usize] += 1;
+                    if let Some(h) = self.histogram_types.get(&btype) {
+                        self.histograms[*h].1[by] += 1;
+                    }
+
+                    let bio3 = by / BLOCKS_IN_BIOME * BIOMES_IN_CHUNK_2D +
+                        bz / BLOCKS_IN_BIOME * BIOMES_IN_CHUNK + bx / BLOCKS_IN_BIOME;
+                    self.biomes[chunk.biomes[bio3] as usize] += 1;
+                }
+            }
+        }
+    }
+
+    fn get_report<'a>(&'a self, blocktypes: &'a [BlockType], biometypes: &'a [BiomeType],
+        ylimits: &Range<usize>)
+    -> Report<'a> {
+        let mut blocks: Vec<BlockCount> = blocktypes.iter().zip(&self.blocks)
+            .filter(|(_, count)| **count > 0)
+            .map(|(blocktype, count)| BlockCount {
+                name: &blocktype.name,
+                state: get_state_string(blocktype),
+                count: *count,
+            })
+            .collect();
+        blocks.sort_by(|a, b| a.name.cmp(b.name).then_with(|| a.state.cmp(&b.state)));
+
+        let mut biomes: Vec<BiomeCount> = biometypes.iter()
+            .map(|biome| BiomeCount { name: &biome.name, count: self.biomes[biome.index as usize] })
+            .filter(|biome| biome.count > 0)
+            .collect();
+        biomes.sort_by(|a, b| a.name.cmp(b.name));
+
+        let heights = self.histograms.iter()
+            .map(|(name, counts)| (name.as_str(), ylimits.clone()
+                .map(|by| LevelCount { y: offset_to_block_y(by), count: counts[by] })
+                .collect()))
+            .collect();
+
+        Report { chunks: self.chunks, blocks, biomes, heights }
+    }
+}
+
+#[derive(Serialize)]
+struct BlockCount<'a> {
+    name: &'a str,
+    state: String,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct BiomeCount<'a> {
+    name: &'a str,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct LevelCount {
+    y: isize,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    chunks: usize,
+    blocks: Vec<BlockCount<'a>>,
+    biomes: Vec<BiomeCount<'a>>,
+    heights: BTreeMap<&'a str, Vec<LevelCount>>,
+}
+
+// A CSV row holds one count of any kind, with the columns that don't apply left blank.
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    stat: &'a str,
+    name: &'a str,
+    state: &'a str,
+    y: Option<isize>,
+    count: u64,
+}
+
+// Write a block state in the same form as commands do, e.g. `facing=north,half=top`. Only the
+// properties that the renderer tells apart are known.
+fn get_state_string(blocktype: &BlockType) -> String {
+    let mut props: Vec<String> = blocktype.state.iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    props.sort();
+    props.join(",")
+}
+
+fn write_csv(report: &Report, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for block in &report.blocks {
+        writer.serialize(CsvRow {
+            stat: "block",
+            name: block.name,
+            state: &block.state,
+            y: None,
+            count: block.count,
+        })?;
+    }
+    for biome in &report.biomes {
+        writer.serialize(CsvRow { stat: "biome", name: biome.name, state: "", y: None,
+            count: biome.count })?;
+    }
+    for (name, levels) in &report.heights {
+        for level in levels {
+            writer.serialize(CsvRow { stat: "height", name, state: "", y: Some(level.y),
+                count: level.count })?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json(report: &Report, path: &Path) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), report)?;
+    Ok(())
+}
+
+// Count the blocks and biomes in a world, or the part of it within the limits, along with the
+// heights of some blocks, and save them as CSV or JSON.
+pub fn write_stats(options: &Options, names: &[&str]) -> Result<(), Box<dyn Error>> {
+    let dimension = dimension::get_dimension(options.inpath, options.dimension)?;
+    let ylimits = options.ylimits.clone().unwrap_or_else(|| dimension.get_ylimits());
+    let names: Vec<String> = names.iter()
+        .map(|name| if name.contains(':') { name.to_string() }
+            else { format!("minecraft:{}", name) })
+        .collect();
+
+    println!("Dimension:         {}", dimension.name);
+    println!("Horizontal limits: {}", match options.blimits {
+        Some(blimits) => format!("({}, {}) - ({}, {})", blimits.w, blimits.n, blimits.e, blimits.s),
+        _ => "none".to_string(),
+    });
+    println!("Vertical limits:   {} - {}", offset_to_block_y(ylimits.start),
+        offset_to_block_y(ylimits.end - 1));
+
+    let start = Instant::now();
+
+    println!("Getting world info from world dir {}", dimension.path.display());
+    let world = world::get_world(&dimension, &options.blimits, &ylimits)?;
+
+    println!("Getting block types");
+    let biometypes = biometypes::get_biome_types();
+    let blocktypes = blocktypes::get_block_types(&dimension.get_lighting(false), &biometypes);
+    let legacy_blocktypes = blocktypes::get_legacy_block_types(&blocktypes);
+    let biome_registry = biometypes::get_biome_registry(&biometypes);
+
+    println!("Counting blocks");
+    let mut stats = Stats::new(&blocktypes, &names);
+    let mut errors = vec![];
+
+    let bar = ProgressBar::with_draw_target(world.regions.len() as u64,
+        ProgressDrawTarget::stdout_nohz())
+        .with_style(ProgressStyle::default_bar()
+            .template("{wide_bar}\n{msg} ({pos}/{len})")
+            .progress_chars("▪■ "));
+
+    let mut reader = world::RegionReader::new(&world, &blocktypes, &biome_registry,
+        &legacy_blocktypes);
+    for r in world.regions.keys() {
+        let msg = format!("Reading block data for region {}, {}", r.x, r.z);
+        bar.set_message(&msg);
+        bar.inc(1);
+
+        // Blocks are only counted within their own region, so the neighbours aren't read.
+        let reg = reader.read(r, false)
+            .unwrap_or_else(|err| {
+                errors.push(format!("Region {}, {}: {}", r.x, r.z, err));
+                None
+            });
+        if let Some(reg) = reg {
+            for (c, err) in &reg.errors {
+                errors.push(format!("Region {}, {}, chunk {}, {}: {}", r.x, r.z, c.x, c.z, err));
+            }
+
+            // Placeholders for chunks that couldn't be read have no real blocks to count.
+            for (c, chunk) in reg.chunks.iter().filter(|(_, chunk)| !chunk.corrupt) {
+                let wc = Pair {
+                    x: r.x * CHUNKS_IN_REGION as isize + c.x as isize,
+                    z: r.z * CHUNKS_IN_REGION as isize + c.z as isize,
+                };
+                let cblimits = Edges {
+                    n: block_pos_in_chunk(world.bedges.n, Some(wc.z)),
+                    e: block_pos_in_chunk(world.bedges.e, Some(wc.x)),
+                    s: block_pos_in_chunk(world.bedges.s, Some(wc.z)),
+                    w: block_pos_in_chunk(world.bedges.w, Some(wc.x)),
+                };
+                stats.add_chunk(chunk, &cblimits, &ylimits);
+            }
+        }
+    }
+
+    bar.finish_and_clear();
+
+    let report = stats.get_report(&blocktypes, &biometypes, &ylimits);
+    match options.outpath.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => write_json(&report, options.outpath)?,
+        Some("csv") => write_csv(&report, options.outpath)?,
+        _ => return Err(Box::new(std::io::Error::new(ErrorKind::InvalidInput,
+            "Output path must be a .csv or .json file"))),
+    };
+    println!("Counted {} chunks; saved stats to {}", report.chunks, options.outpath.display());
+
+    if !errors.is_empty() {
+        println!("Unreadable chunks and regions ({}):", errors.len());
+        for error in errors {
+            println!("  {}", error);
+        }
+    }
+
+    let elapsed = start.elapsed();
+    println!("Time elapsed: {}:{:02}.{:03}", elapsed.as_secs() / 60, elapsed.as_secs() % 60,
+        elapsed.subsec_millis());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Lighting;
+
+    fn find_block(blocktypes: &[BlockType], name: &str) -> u16 {
+        blocktypes.iter().position(|blocktype| blocktype.name == name).unwrap() as u16
+    }
+
+    #[test]
+    fn count_blocks() {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+        let stone = find_block(&blocktypes, "minecraft:stone");
+        let ore = find_block(&blocktypes, "minecraft:diamond_ore");
+        let unknown = blocktypes::get_unknown_block_type(&blocktypes);
+
+        let mut chunk = ChunkData {
+            blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+            lights: [0u8; BLOCKS_IN_CHUNK_3D],
+            biomes: [1u8; BIOMES_IN_CHUNK_3D],
+            full: true,
+            corrupt: false,
+        };
+        let ore_y = block_y_to_offset(-50);
+        chunk.blocks[..BLOCKS_IN_CHUNK_2D].fill(stone);
+        chunk.blocks[ore_y * BLOCKS_IN_CHUNK_2D] = ore;
+        chunk.blocks[ore_y * BLOCKS_IN_CHUNK_2D + BLOCKS_IN_CHUNK_2D - 1] = ore;
+        chunk.blocks[ore_y * BLOCKS_IN_CHUNK_2D + 1] = unknown;
+
+        let names = ["minecraft:diamond_ore".to_string(), "minecraft:unknown".to_string()];
+        let mut stats = Stats::new(&blocktypes, &names);
+        stats.add_chunk(&chunk, &Edges::<usize>::full(BLOCKS_IN_CHUNK), &(0..BLOCKS_IN_CHUNK_Y));
+        // Only the northwest quarter of the chunk, above the bottom layer.
+        stats.add_chunk(&chunk, &Edges { n: 0, e: 7, s: 7, w: 0 }, &(1..BLOCKS_IN_CHUNK_Y));
+
+        let ylimits = block_y_to_offset(-51)..block_y_to_offset(-49);
+        let report = stats.get_report(&blocktypes, &biometypes, &ylimits);
+        assert_eq!(report.chunks, 2);
+        let blocks: Vec<(&str, u64)> = report.blocks.iter()
+            .map(|block| (block.name, block.count))
+            .collect();
+        assert_eq!(blocks,
+            [("minecraft:diamond_ore", 3), ("minecraft:stone", 256), ("unknown", 2)]);
+        assert_eq!(report.biomes.len(), 1);
+        assert_eq!(report.biomes[0].count, 261);
+
+        assert_eq!(report.heights.len(), 1);
+        let heights: Vec<(isize, u64)> = report.heights["minecraft:diamond_ore"].iter()
+            .map(|level| (level.y, level.count))
+            .collect();
+        assert_eq!(heights, [(-51, 0), (-50, 3)]);
+    }
+
+    #[test]
+    fn default_blocks() {
+        let biometypes = biometypes::get_biome_types();
+        let blocktypes = blocktypes::get_block_types(&Lighting::Day, &biometypes);
+        let names: Vec<String> = DEFAULT_HISTOGRAM_BLOCKS.iter()
+            .map(|name| format!("minecraft:{}", name))
+            .collect();
+        let stats = Stats::new(&blocktypes, &names);
+        assert_eq!(stats.histograms.len(), DEFAULT_HISTOGRAM_BLOCKS.len());
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+use super::biometypes::BiomeRegistry;
+use super::blocktypes::BlockType;
+use super::nbt;
+use super::region;
+use super::region::{ChunkData, RegionData};
+use super::sizes::*;
+use super::types::*;
+use super::world::World;
+
+// A build saved outside of a world: a structure block file, a Sponge schematic or a Litematica
+// schematic. Its blocks are placed with their minimum corner at X=0, Y=0, Z=0.
+pub struct Structure {
+    pub size: Pair<usize>,
+    pub height: usize,
+    // Block palette entries, as compounds with Name and Properties tags like chunk palettes.
+    palette: Vec<nbt::Tag>,
+    // Palette indices for each block, with X changing fastest, then Z, then Y. Index 0 means no
+    // block (such as structure voids), so palette entries are offset by 1.
+    blocks: Vec<usize>,
+}
+
+pub fn is_structure_path(path: &Path) -> bool {
+    path.is_file() && match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext == "nbt" || ext == "schem" || ext == "litematic",
+        None => false,
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn get_int(tag: &nbt::Tag) -> Result<isize, Error> {
+    Ok(match tag {
+        nbt::Tag::Byte(value) => *value as isize,
+        nbt::Tag::Short(value) => *value as isize,
+        nbt::Tag::Int(value) => *value as isize,
+        _ => return Err(invalid_data("Invalid integer tag")),
+    })
+}
+
+fn get_child<'a>(compound: &'a HashMap<String, nbt::Tag>, name: &str)
+-> Result<&'a nbt::Tag, Error> {
+    compound.get(name).ok_or_else(|| Error::new(ErrorKind::InvalidData,
+        format!("Missing {} tag", name)))
+}
+
+// Read X, Y and Z from either a list of three ints or a compound with x, y and z tags.
+fn get_coords(tag: &nbt::Tag) -> Result<[isize; 3], Error> {
+    Ok(match tag {
+        nbt::Tag::List(list) if list.len() == 3 =>
+            [get_int(&list[0])?, get_int(&list[1])?, get_int(&list[2])?],
+        nbt::Tag::Compound(compound) => [
+            get_int(get_child(compound, "x")?)?,
+            get_int(get_child(compound, "y")?)?,
+            get_int(get_child(compound, "z")?)?,
+        ],
+        _ => return Err(invalid_data("Invalid coordinates")),
+    })
+}
+
+fn get_size(coords: [isize; 3]) -> Result<[usize; 3], Error> {
+    if coords.iter().any(|c| *c < 0) {
+        return Err(invalid_data("Invalid structure size"));
+    }
+    Ok([coords[0] as usize, coords[1] as usize, coords[2] as usize])
+}
+
+impl Structure {
+    fn new(size: [usize; 3]) -> Structure {
+        Structure {
+            size: Pair { x: size[0], z: size[2] },
+            height: size[1],
+            palette: Vec::new(),
+            blocks: vec![0; size[0] * size[1] * size[2]],
+        }
+    }
+
+    fn set_block(&mut self, x: usize, y: usize, z: usize, pi: usize) {
+        if x < self.size.x && y < self.height && z < self.size.z {
+            self.blocks[(y * self.size.z + z) * self.size.x + x] = pi;
+        }
+    }
+
+    // List the coords of all chunks the structure covers.
+    pub fn get_chunk_coords(&self) -> Vec<Pair<isize>> {
+        let csize = Pair {
+            x: (self.size.x + MAX_BLOCK_IN_CHUNK) / BLOCKS_IN_CHUNK,
+            z: (self.size.z + MAX_BLOCK_IN_CHUNK) / BLOCKS_IN_CHUNK,
+        };
+        (0..csize.z).flat_map(|cz| (0..csize.x).map(move |cx| Pair {
+            x: cx as isize,
+            z: cz as isize,
+        })).collect()
+    }
+}
+
+// Structure block files list each block's position and palette index.
+// Structures with random variants, like shipwrecks, have several palettes; use the first.
+fn read_structure_nbt(root: &HashMap<String, nbt::Tag>) -> Result<Structure, Error> {
+    let mut structure = Structure::new(get_size(get_coords(get_child(root, "size")?)?)?);
+
+    structure.palette = match root.get("palettes") {
+        Some(palettes) => palettes.to_list()?.first()
+            .ok_or_else(|| invalid_data("Empty palette list"))?.to_list()?,
+        None => get_child(root, "palette")?.to_list()?,
+    }.iter().map(clone_palette_entry).collect::<Result<_, _>>()?;
+
+    for btag in get_child(root, "blocks")?.to_list()? {
+        let block = btag.to_hashmap()?;
+        let pos = get_coords(get_child(block, "pos")?)?;
+        let state = *get_child(block, "state")?.to_i32()? as usize;
+        if pos.iter().all(|c| *c >= 0) && state < structure.palette.len() {
+            structure.set_block(pos[0] as usize, pos[1] as usize, pos[2] as usize, state + 1);
+        }
+    }
+
+    Ok(structure)
+}
+
+fn clone_palette_entry(ptag: &nbt::Tag) -> Result<nbt::Tag, Error> {
+    let pblock = ptag.to_hashmap()?;
+    let mut entry = HashMap::new();
+    entry.insert("Name".to_string(), nbt::Tag::String(get_child(pblock, "Name")?.to_str()?
+        .to_string()));
+    if let Some(props) = pblock.get("Properties") {
+        let mut properties = HashMap::new();
+        for (k, v) in props.to_hashmap()?.iter() {
+            properties.insert(k.clone(), nbt::Tag::String(v.to_str()?.to_string()));
+        }
+        entry.insert("Properties".to_string(), nbt::Tag::Compound(properties));
+    }
+    Ok(nbt::Tag::Compound(entry))
+}
+
+// Parse a block state string like "minecraft:oak_stairs[facing=east,half=bottom]".
+fn parse_block_state(state: &str) -> nbt::Tag {
+    let (name, props) = match state.find('[') {
+        Some(i) => (&state[..i], state[(i + 1)..].trim_end_matches(']')),
+        None => (state, ""),
+    };
+    let mut entry = HashMap::new();
+    entry.insert("Name".to_string(), nbt::Tag::String(name.to_string()));
+    let mut properties = HashMap::new();
+    for pair in props.split(',').filter(|pair| !pair.is_empty()) {
+        let mut kv = pair.split('=');
+        properties.insert(kv.next().unwrap().to_string(),
+            nbt::Tag::String(kv.next().unwrap_or("").to_string()));
+    }
+    entry.insert("Properties".to_string(), nbt::Tag::Compound(properties));
+    nbt::Tag::Compound(entry)
+}
+
+// Sponge schematics map block state strings to palette indices, and store the index of each
+// block as a varint, with X changing fastest, then Z, then Y. Version 3 moved these into a Blocks
+// compound.
+fn read_sponge_schematic(root: &HashMap<String, nbt::Tag>) -> Result<Structure, Error> {
+    let schematic = match root.get("Schematic") {
+        Some(tag) => tag.to_hashmap()?,
+        None => root,
+    };
+    let mut structure = Structure::new(get_size([
+        get_int(get_child(schematic, "Width")?)? as u16 as isize,
+        get_int(get_child(schematic, "Height")?)? as u16 as isize,
+        get_int(get_child(schematic, "Length")?)? as u16 as isize,
+    ])?);
+
+    let (palette_tag, data) = match schematic.get("Blocks") {
+        Some(blocks) => {
+            let blocks = blocks.to_hashmap()?;
+            (get_child(blocks, "Palette")?, get_child(blocks, "Data")?.to_u8_array()?)
+        },
+        None => (get_child(schematic, "Palette")?,
+            get_child(schematic, "BlockData")?.to_u8_array()?),
+    };
+
+    let palette = palette_tag.to_hashmap()?;
+    let mut indices: Vec<Option<nbt::Tag>> = (0..palette.len()).map(|_| None).collect();
+    for (state, index) in palette.iter() {
+        let index = get_int(index)? as usize;
+        if index >= palette.len() {
+            return Err(invalid_data("Invalid schematic palette index"));
+        }
+        indices[index] = Some(parse_block_state(state));
+    }
+    structure.palette = indices.into_iter()
+        .map(|entry| entry.ok_or_else(|| invalid_data("Missing schematic palette index")))
+        .collect::<Result<_, _>>()?;
+
+    let mut pos = 0;
+    for block in structure.blocks.iter_mut() {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *data.get(pos).ok_or_else(|| invalid_data("Truncated block data"))?;
+            pos += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if value >= structure.palette.len() {
+            return Err(invalid_data("Invalid schematic block index"));
+        }
+        *block = value + 1;
+    }
+
+    Ok(structure)
+}
+
+// Litematica schematics hold one or more regions, each with its own palette and packed block
+// indices. A region's size is negative along any axis it extends backwards from its position.
+fn read_litematic(root: &HashMap<String, nbt::Tag>) -> Result<Structure, Error> {
+    struct LitematicRegion<'a> {
+        min: [isize; 3],
+        size: [usize; 3],
+        region: &'a HashMap<String, nbt::Tag>,
+    }
+
+    let mut regions = Vec::new();
+    for rtag in get_child(root, "Regions")?.to_hashmap()?.values() {
+        let region = rtag.to_hashmap()?;
+        let pos = get_coords(get_child(region, "Position")?)?;
+        let size = get_coords(get_child(region, "Size")?)?;
+        let mut min = [0; 3];
+        for i in 0..3 {
+            min[i] = if size[i] < 0 { pos[i] + size[i] + 1 } else { pos[i] };
+        }
+        regions.push(LitematicRegion {
+            min,
+            size: get_size([size[0].abs(), size[1].abs(), size[2].abs()])?,
+            region,
+        });
+    }
+    if regions.is_empty() {
+        return Err(invalid_data("No regions in schematic"));
+    }
+
+    let mut min = [isize::MAX; 3];
+    let mut max = [isize::MIN; 3];
+    for lregion in regions.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(lregion.min[i]);
+            max[i] = max[i].max(lregion.min[i] + lregion.size[i] as isize);
+        }
+    }
+    let mut structure = Structure::new(get_size([max[0] - min[0], max[1] - min[1],
+        max[2] - min[2]])?);
+
+    for LitematicRegion { min: rmin, size, region } in regions {
+        let offset = structure.palette.len();
+        for ptag in get_child(region, "BlockStatePalette")?.to_list()? {
+            structure.palette.push(clone_palette_entry(ptag)?);
+        }
+        let palette_len = structure.palette.len() - offset;
+
+        let count = size[0] * size[1] * size[2];
+        let bits = region::palette_bits(palette_len, 2);
+        let longs = get_child(region, "BlockStates")?.to_long_array()?;
+        if longs.len() * 64 < count * bits {
+            return Err(invalid_data("Truncated block states"));
+        }
+
+        let ox = (rmin[0] - min[0]) as usize;
+        let oy = (rmin[1] - min[1]) as usize;
+        let oz = (rmin[2] - min[2]) as usize;
+        for (i, pi) in region::unpack_indices(|i| longs[i], bits, count, false).iter().enumerate() {
+            if *pi < palette_len {
+                let x = i % size[0];
+                let z = i / size[0] % size[2];
+                let y = i / size[0] / size[2];
+                structure.set_block(ox + x, oy + y, oz + z, offset + pi + 1);
+            }
+        }
+    }
+
+    Ok(structure)
+}
+
+pub fn read_structure(path: &Path) -> Result<Structure, Error> {
+    let mut reader = GzDecoder::new(File::open(path)?);
+    let (id, _) = nbt::read_tag_header(&mut reader)?;
+    let root_tag = nbt::read_tag_payload(&mut reader, &id)?;
+    let root = root_tag.to_hashmap()?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("schem") => read_sponge_schematic(root),
+        Some("litematic") => read_litematic(root),
+        _ => read_structure_nbt(root),
+    }
+}
+
+fn read_chunk(structure: &Structure, c: &Pair<isize>, pblocks: &[u16], plains: u8)
+-> Option<ChunkData> {
+    if c.x < 0 || c.z < 0 {
+        return None;
+    }
+    let bx0 = c.x as usize * BLOCKS_IN_CHUNK;
+    let bz0 = c.z as usize * BLOCKS_IN_CHUNK;
+    if bx0 >= structure.size.x || bz0 >= structure.size.z {
+        return None;
+    }
+
+    // Structures aren't lit, so give them full skylight throughout.
+    let mut chunk = ChunkData {
+        blocks: [0u16; BLOCKS_IN_CHUNK_3D],
+        lights: [MAX_LIGHT_LEVEL; BLOCKS_IN_CHUNK_3D],
+        biomes: [plains; BIOMES_IN_CHUNK_3D],
+        full: true,
+        corrupt: false,
+    };
+
+    let yo = block_y_to_offset(0);
+    let height = structure.height.min(BLOCKS_IN_CHUNK_Y - yo);
+    for y in 0..height {
+        for bz in 0..BLOCKS_IN_CHUNK.min(structure.size.z - bz0) {
+            for bx in 0..BLOCKS_IN_CHUNK.min(structure.size.x - bx0) {
+                let pi = structure.blocks[(y * structure.size.z + bz0 + bz) * structure.size.x +
+                    bx0 + bx];
+                chunk.blocks[(yo + y) * BLOCKS_IN_CHUNK_2D + bz * BLOCKS_IN_CHUNK + bx] =
+                    pblocks[pi];
+            }
+        }
+    }
+
+    Some(chunk)
+}
+
+fn read_region_chunk_data(structure: &Structure, r: &Pair<isize>, rclimits: &Edges<usize>,
+    pblocks: &[u16], plains: u8)
+-> HashMap<Pair<usize>, ChunkData> {
+    let mut chunks = HashMap::new();
+    for cz in rclimits.n..(rclimits.s + 1) {
+        for cx in rclimits.w..(rclimits.e + 1) {
+            let c = Pair {
+                x: r.x * CHUNKS_IN_REGION as isize + cx as isize,
+                z: r.z * CHUNKS_IN_REGION as isize + cz as isize,
+            };
+            if let Some(chunk) = read_chunk(structure, &c, pblocks, plains) {
+                chunks.insert(Pair { x: cx, z: cz }, chunk);
+            }
+        }
+    }
+    chunks
+}
+
+pub fn read_region_data(world: &World, structure: &Structure, r: &Pair<isize>,
+    blocktypes: &[BlockType], biome_registry: &BiomeRegistry, neighbours: bool)
+-> Result<Option<RegionData>, Box<dyn std::error::Error>> {
+    if !world.regions.contains_key(r) {
+        return Ok(None);
+    }
+
+    let mut pblocks = vec![0];
+    pblocks.extend(region::get_palette_blocks(&structure.palette, blocktypes)?);
+    let plains = biome_registry.get("minecraft:plains").copied().unwrap_or(0);
+
+    let rclimits = region::get_region_chunk_limits(world, r);
+    let chunks = read_region_chunk_data(structure, r, &rclimits, &pblocks, plains);
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    if !neighbours {
+        return Ok(Some(RegionData { chunks, nchunks: region::no_neighbour_chunks(),
+            errors: vec![] }));
+    }
+
+    let nrclimits = region::get_neighbour_chunk_limits(&rclimits);
+
+    Ok(Some(RegionData {
+        chunks,
+        nchunks: Edges {
+            n: read_region_chunk_data(structure, &Pair { x: r.x, z: r.z - 1 }, &nrclimits.n,
+                &pblocks, plains),
+            e: read_region_chunk_data(structure, &Pair { x: r.x + 1, z: r.z }, &nrclimits.e,
+                &pblocks, plains),
+            s: read_region_chunk_data(structure, &Pair { x: r.x, z: r.z + 1 }, &nrclimits.s,
+                &pblocks, plains),
+            w: read_region_chunk_data(structure, &Pair { x: r.x - 1, z: r.z }, &nrclimits.w,
+                &pblocks, plains),
+        },
+        errors: vec![],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::testing::compound;
+    use crate::nbt::Tag;
+
+    fn int_list(values: [i32; 3]) -> Tag {
+        Tag::List(values.iter().map(|v| Tag::Int(*v)).collect())
+    }
+
+    fn palette_entry(name: &str) -> Tag {
+        compound(vec![("Name", Tag::String(format!("minecraft:{}", name)))])
+    }
+
+    // The name of each block in the structure, without its namespace.
+    fn block_names(structure: &Structure) -> Vec<String> {
+        structure.blocks.iter().map(|pi| match pi {
+            0 => "".to_string(),
+            pi => {
+                let name = structure.palette[pi - 1].to_hashmap().unwrap()["Name"].to_str()
+                    .unwrap();
+                name.trim_start_matches("minecraft:").to_string()
+            },
+        }).collect()
+    }
+
+    #[test]
+    fn structure_blocks() {
+        let block = |pos: [i32; 3], state: i32| compound(vec![
+            ("pos", int_list(pos)),
+            ("state", Tag::Int(state)),
+        ]);
+        let root = compound(vec![
+            ("size", int_list([2, 2, 1])),
+            ("palettes", Tag::List(vec![
+                Tag::List(vec![palette_entry("stone"), palette_entry("dirt")]),
+                Tag::List(vec![palette_entry("sand"), palette_entry("gravel")]),
+            ])),
+            ("blocks", Tag::List(vec![
+                block([0, 0, 0], 0),
+                block([1, 0, 0], 1),
+                block([1, 1, 0], 0),
+                // Out of bounds, or not in the palette.
+                block([2, 0, 0], 0),
+                block([0, 1, 0], 2),
+            ])),
+        ]);
+        let structure = read_structure_nbt(root.to_hashmap().unwrap()).unwrap();
+        assert_eq!((structure.size.x, structure.height, structure.size.z), (2, 2, 1));
+        assert_eq!(block_names(&structure), ["stone", "dirt", "", "stone"]);
+    }
+
+    #[test]
+    fn sponge_schematic_blocks() {
+        // Enough palette entries that some indices take two bytes.
+        let palette = || Tag::Compound((0..130)
+            .map(|i| (format!("minecraft:block_{}[facing=east,half=bottom]", i), Tag::Int(i)))
+            .collect());
+        let data = || Tag::ByteArray(vec![0, 0x81, 0x01, 5, 0x80, 0x01, 127, 0]);
+        let v2 = compound(vec![
+            ("Version", Tag::Int(2)),
+            ("Width", Tag::Short(3)),
+            ("Height", Tag::Short(1)),
+            ("Length", Tag::Short(2)),
+            ("Palette", palette()),
+            ("BlockData", data()),
+        ]);
+        let v3 = compound(vec![("Schematic", compound(vec![
+            ("Version", Tag::Int(3)),
+            ("Width", Tag::Short(3)),
+            ("Height", Tag::Short(1)),
+            ("Length", Tag::Short(2)),
+            ("Blocks", compound(vec![("Palette", palette()), ("Data", data())])),
+        ]))]);
+
+        for root in [v2, v3] {
+            let structure = read_sponge_schematic(root.to_hashmap().unwrap()).unwrap();
+            assert_eq!((structure.size.x, structure.height, structure.size.z), (3, 1, 2));
+            assert_eq!(block_names(&structure),
+                ["block_0", "block_129", "block_5", "block_128", "block_127", "block_0"]
+                    .map(|name| name.to_string()));
+            let props = structure.palette[0].to_hashmap().unwrap()["Properties"].to_hashmap()
+                .unwrap().get("facing").unwrap().to_str().unwrap().to_string();
+            assert_eq!(props, "east");
+        }
+
+        // The last block's index is missing.
+        let truncated = compound(vec![
+            ("Width", Tag::Short(2)),
+            ("Height", Tag::Short(1)),
+            ("Length", Tag::Short(1)),
+            ("Palette", compound(vec![("minecraft:stone", Tag::Int(0))])),
+            ("BlockData", Tag::ByteArray(vec![0])),
+        ]);
+        assert!(read_sponge_schematic(truncated.to_hashmap().unwrap()).is_err());
+    }
+
+    #[test]
+    fn litematic_blocks() {
+        // Five palette entries take 3 bits each, packed without padding, so the 22nd index
+        // starts in the first long and ends in the second.
+        let indices: Vec<u64> = (0..24).map(|i| i % 5).collect();
+        let mut longs = vec![0i64; 2];
+        for (i, index) in indices.iter().enumerate() {
+            let bit = i * 3;
+            longs[bit / 64] |= (index << (bit % 64)) as i64;
+            if bit % 64 > 61 {
+                longs[bit / 64 + 1] |= (index >> (64 - bit % 64)) as i64;
+            }
+        }
+        let palette = ["air", "stone", "dirt", "sand", "gravel"];
+        let region = |pos: [i32; 3], size: [i32; 3], palette: &[&str], longs: Vec<i64>|
+            compound(vec![
+                ("Position", compound(vec![
+                    ("x", Tag::Int(pos[0])), ("y", Tag::Int(pos[1])), ("z", Tag::Int(pos[2])),
+                ])),
+                ("Size", compound(vec![
+                    ("x", Tag::Int(size[0])), ("y", Tag::Int(size[1])), ("z", Tag::Int(size[2])),
+                ])),
+                ("BlockStatePalette", Tag::List(palette.iter()
+                    .map(|name| palette_entry(name))
+                    .collect())),
+                ("BlockStates", Tag::LongArray(longs)),
+            ]);
+        let root = compound(vec![("Regions", compound(vec![
+            ("Main", region([0, 0, 0], [3, 2, 4], &palette, longs)),
+            // Extends backwards along X, from X=5 to X=4.
+            ("Side", region([5, 1, 0], [-2, 1, 1], &["air", "glass"], vec![0b0101])),
+        ]))]);
+
+        let structure = read_litematic(root.to_hashmap().unwrap()).unwrap();
+        assert_eq!((structure.size.x, structure.height, structure.size.z), (6, 2, 4));
+        let names = block_names(&structure);
+        for (i, index) in indices.iter().enumerate() {
+            let (x, z, y) = (i % 3, i / 3 % 4, i / 12);
+            assert_eq!(names[(y * 4 + z) * 6 + x], palette[*index as usize], "block {}", i);
+        }
+        assert_eq!(names[(4 * 6 + 4)..(4 * 6 + 6)], ["glass", "glass"]);
+        assert_eq!(names[3], "");
+    }
+}
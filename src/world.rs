@@ -1,13 +1,20 @@
 use std::cmp::{min, max};
 use std::collections::HashMap;
+use std::fs::File;
 use std::io::{Error, ErrorKind};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 
+use super::bedrock;
+use super::biometypes::BiomeRegistry;
+use super::blocktypes::BlockType;
+use super::dimension::Dimension;
+use super::level::{self, LevelInfo};
 use super::region;
 use super::sizes::*;
+use super::structure;
 use super::types::*;
 
 struct RegionFile {
@@ -19,8 +26,15 @@ pub struct Region {
     pub cedges: Edges<usize>,
 }
 
+pub enum WorldFormat {
+    Java(region::RegionFormat),
+    Bedrock(bedrock::Db),
+    Structure(structure::Structure),
+}
+
 pub struct World<'a> {
     pub path: &'a Path,
+    pub format: WorldFormat,
     pub regions: HashMap<Pair<isize>, Region>,
     pub redges: Edges<isize>,
     pub cedges: Edges<isize>,
@@ -29,10 +43,24 @@ pub struct World<'a> {
     pub csize: Pair<usize>,
     pub bsize: Pair<usize>,
     pub ylimits: &'a Range<usize>,
+    pub level: Option<LevelInfo>,
 }
 
-pub fn read_world_regions(path: &Path, blimits: &Option<Edges<isize>>)
--> Result<HashMap<Pair<isize>, Region>, Error> {
+// Worlds converted from McRegion keep their old .mcr files, so only use them if there are no
+// Anvil files.
+fn get_region_format(region_path: &Path) -> Result<region::RegionFormat, Error> {
+    let has_anvil = std::fs::read_dir(region_path)?
+        .filter_map(|dir_entry| dir_entry.ok())
+        .any(|entry| entry.file_name().to_str()
+            .and_then(|filename| region::get_coords_from_path(filename,
+                region::RegionFormat::Anvil))
+            .is_some());
+    Ok(if has_anvil { region::RegionFormat::Anvil } else { region::RegionFormat::McRegion })
+}
+
+pub fn read_world_regions(path: &Path, blimits: &Option<Edges<isize>>,
+    format: Option<region::RegionFormat>)
+-> Result<(HashMap<Pair<isize>, Region>, region::RegionFormat), Error> {
     if !path.is_dir() {
         return Err(Error::new(ErrorKind::NotFound, "Directory not found."));
     }
@@ -50,10 +78,15 @@ pub fn read_world_regions(path: &Path, blimits: &Option<Edges<isize>>)
         w: block_to_region(blimits.w),
     }));
 
+    let format = match format {
+        Some(format) => format,
+        None => get_region_format(&region_path)?,
+    };
+
     let region_files: Vec<RegionFile> = std::fs::read_dir(region_path)?
         .filter_map(|dir_entry| dir_entry.ok()
             .and_then(|entry| entry.file_name().to_str()
-                .and_then(|filename| region::get_coords_from_path(filename)
+                .and_then(|filename| region::get_coords_from_path(filename, format)
                     .and_then(|r| if rlimits.is_none() || rlimits.unwrap().contains(&r) {
                         Some(RegionFile {
                             path: entry.path(),
@@ -62,14 +95,13 @@ pub fn read_world_regions(path: &Path, blimits: &Option<Edges<isize>>)
                     } else { None }))))
         .collect();
 
-    let mut regions = HashMap::new();
-
     let bar = ProgressBar::with_draw_target(region_files.len() as u64,
         ProgressDrawTarget::stdout_nohz())
         .with_style(ProgressStyle::default_bar()
             .template("{wide_bar}\n{msg} ({pos}/{len})")
             .progress_chars("▪■ "));
 
+    let mut chunks = vec![];
     for RegionFile { coords: r, path } in region_files {
         let msg = format!("Reading chunk list for region {}, {}", r.x, r.z);
         bar.set_message(&msg);
@@ -83,26 +115,155 @@ pub fn read_world_regions(path: &Path, blimits: &Option<Edges<isize>>)
             w: chunk_pos_in_region(block_to_chunk(blimits.w), Some(r.x)),
         }));
         let chunklist = region::read_region_chunk_coords(path.as_path(), &rclimits)?;
-        if chunklist.len() > 0 {
-            regions.insert(r, Region {
-                cedges: Edges {
-                    n: chunklist.iter().map(|c| c.z).min().unwrap(),
-                    e: chunklist.iter().map(|c| c.x).max().unwrap(),
-                    s: chunklist.iter().map(|c| c.z).max().unwrap(),
-                    w: chunklist.iter().map(|c| c.x).min().unwrap(),
-                },
-            });
-        }
+        chunks.extend(chunklist.iter().map(|c| Pair {
+            x: r.x * CHUNKS_IN_REGION as isize + c.x as isize,
+            z: r.z * CHUNKS_IN_REGION as isize + c.z as isize,
+        }));
     }
 
+    // Keep chunks that can't be read, so they show up as placeholders. Each region file is
+    // opened once, for the first of its chunks to be checked.
+    bar.set_message("Checking chunks at the edges of the map");
+    let mut files = HashMap::new();
+    trim_empty_edges(&mut chunks, |c| {
+        let r = Pair { x: chunk_to_region(c.x), z: chunk_to_region(c.z) };
+        let cr = Pair { x: chunk_pos_in_region(c.x, None), z: chunk_pos_in_region(c.z, None) };
+        let rpath = region::get_path_from_coords(path, &r, format);
+        match files.entry(r).or_insert_with(|| File::open(&rpath).ok()) {
+            Some(file) => region::chunk_has_blocks(file, &rpath, &cr, format).unwrap_or(true),
+            None => true,
+        }
+    });
     bar.finish_and_clear();
 
-    Ok(regions)
+    Ok((get_chunk_regions(&chunks, &None), format))
 }
 
-pub fn get_world<'a>(worldpath: &'a Path, blimits: &Option<Edges<isize>>, ylimits: &'a Range<usize>)
+// Chunks without any blocks would pad out the map if they were at the edges of the world, so
+// drop rows and columns of them from the edges until each edge has a chunk with blocks. Only
+// the chunks at the edges are read to check; the rest are found to be empty when drawn.
+fn trim_empty_edges<F>(chunks: &mut Vec<Pair<isize>>, mut has_blocks: F)
+where F: FnMut(&Pair<isize>) -> bool {
+    // The north, east, south and west sides, as whether they are across the X axis, and
+    // whether they are at its far end.
+    let sides = [(false, false), (true, true), (false, true), (true, false)];
+    let coord = |c: &Pair<isize>, x: bool| if x { c.x } else { c.z };
+    let mut checked = HashMap::new();
+
+    let mut side = 0;
+    let mut confirmed = 0;
+    // Go round the sides until all four have been confirmed in a row.
+    while confirmed < sides.len() && !chunks.is_empty() {
+        let (x, far) = sides[side];
+        let coords = chunks.iter().map(|c| coord(c, x));
+        let edge = if far { coords.max() } else { coords.min() }.unwrap();
+
+        let has_edge_blocks = chunks.iter()
+            .filter(|c| coord(c, x) == edge)
+            .any(|c| *checked.entry(*c).or_insert_with(|| has_blocks(c)));
+        if has_edge_blocks {
+            confirmed += 1;
+        } else {
+            chunks.retain(|c| coord(c, x) != edge);
+            confirmed = 0;
+        }
+        side = (side + 1) % sides.len();
+    }
+}
+
+// Reads the block data of a world's regions, one at a time. A Bedrock world's chunk decoder is
+// made for the first region read, and kept for the rest.
+pub struct RegionReader<'a> {
+    world: &'a World<'a>,
+    blocktypes: &'a [BlockType],
+    biome_registry: &'a BiomeRegistry,
+    legacy_blocktypes: &'a [u16],
+    decoder: Option<bedrock::ChunkDecoder<'a>>,
+}
+
+impl<'a> RegionReader<'a> {
+    pub fn new(world: &'a World, blocktypes: &'a [BlockType], biome_registry: &'a BiomeRegistry,
+        legacy_blocktypes: &'a [u16])
+    -> RegionReader<'a> {
+        RegionReader { world, blocktypes, biome_registry, legacy_blocktypes, decoder: None }
+    }
+
+    // Read a region's chunks, and unless neighbours is false, the chunks bordering it.
+    pub fn read(&mut self, r: &Pair<isize>, neighbours: bool)
+    -> Result<Option<region::RegionData>, Box<dyn std::error::Error>> {
+        let (world, blocktypes, biome_registry, legacy_blocktypes) =
+            (self.world, self.blocktypes, self.biome_registry, self.legacy_blocktypes);
+        match &world.format {
+            WorldFormat::Java(format) => region::read_region_data(world, r, *format, blocktypes,
+                biome_registry, legacy_blocktypes, neighbours),
+            WorldFormat::Bedrock(db) => {
+                let decoder = self.decoder.get_or_insert_with(|| bedrock::ChunkDecoder::new(db,
+                    blocktypes, biome_registry, legacy_blocktypes));
+                bedrock::read_region_data(world, decoder, r, blocktypes, neighbours)
+            },
+            WorldFormat::Structure(structure) => structure::read_region_data(world, structure, r,
+                blocktypes, biome_registry, neighbours),
+        }
+    }
+}
+
+// Group a list of chunk coords into regions, for worlds that aren't stored in region files.
+pub fn get_chunk_regions(chunks: &[Pair<isize>], blimits: &Option<Edges<isize>>)
+-> HashMap<Pair<isize>, Region> {
+    // If block limits were passed, transform them into chunk limits.
+    let climits = blimits.map(|blimits| Edges {
+        n: block_to_chunk(blimits.n),
+        e: block_to_chunk(blimits.e),
+        s: block_to_chunk(blimits.s),
+        w: block_to_chunk(blimits.w),
+    });
+
+    let mut regions: HashMap<Pair<isize>, Region> = HashMap::new();
+    for c in chunks {
+        if climits.is_some_and(|climits| !climits.contains(c)) {
+            continue;
+        }
+
+        let r = Pair { x: chunk_to_region(c.x), z: chunk_to_region(c.z) };
+        let cr = Pair { x: chunk_pos_in_region(c.x, None), z: chunk_pos_in_region(c.z, None) };
+        let region = regions.entry(r).or_insert(Region {
+            cedges: Edges { n: cr.z, e: cr.x, s: cr.z, w: cr.x },
+        });
+        region.cedges = Edges {
+            n: region.cedges.n.min(cr.z),
+            e: region.cedges.e.max(cr.x),
+            s: region.cedges.s.max(cr.z),
+            w: region.cedges.w.min(cr.x),
+        };
+    }
+    regions
+}
+
+pub fn get_world<'a>(dimension: &'a Dimension, blimits: &Option<Edges<isize>>,
+    ylimits: &'a Range<usize>)
 -> Result<World<'a>, Error> {
-    let regions = read_world_regions(worldpath, blimits)?;
+    let worldpath = dimension.path.as_path();
+    let dbpath = dimension.savepath.join("db");
+    let mut level = None;
+    let (regions, format) = if structure::is_structure_path(worldpath) {
+        let structure = structure::read_structure(worldpath)?;
+        (get_chunk_regions(&structure.get_chunk_coords(), blimits),
+            WorldFormat::Structure(structure))
+    } else if dbpath.is_dir() {
+        // Bedrock Edition worlds have a LevelDB database instead of region files.
+        let id = bedrock::get_dimension_id(&dimension.name).unwrap_or(bedrock::OVERWORLD);
+        let db = bedrock::Db::open(&dbpath, id)?;
+        (get_chunk_regions(&db.get_chunk_coords(), blimits), WorldFormat::Bedrock(db))
+    } else {
+        // The level's metadata is only a help, so render the world even if it can't be read.
+        level = level::read_level_info(&dimension.savepath).unwrap_or_else(|err| {
+            println!("Error reading level.dat: {}", err);
+            None
+        });
+        let (regions, format) = read_world_regions(worldpath, blimits,
+            level.as_ref().and_then(|level| level.region_format()))?;
+        (regions, WorldFormat::Java(format))
+    };
     if regions.len() == 0 {
         return Err(Error::new(ErrorKind::NotFound, "No data in world."));
     }
@@ -135,12 +296,17 @@ pub fn get_world<'a>(worldpath: &'a Path, blimits: &Option<Edges<isize>>, ylimit
         }
     }
 
-    let cbedges = Edges {
+    let mut cbedges = Edges {
         n: cedges.n * BLOCKS_IN_CHUNK as isize,
         e: cedges.e * BLOCKS_IN_CHUNK as isize + MAX_BLOCK_IN_CHUNK as isize,
         s: cedges.s * BLOCKS_IN_CHUNK as isize + MAX_BLOCK_IN_CHUNK as isize,
         w: cedges.w * BLOCKS_IN_CHUNK as isize,
     };
+    // Structures don't fill their last chunks, so stop at their far edges.
+    if let WorldFormat::Structure(structure) = &format {
+        cbedges.e = min(cbedges.e, structure.size.x as isize - 1);
+        cbedges.s = min(cbedges.s, structure.size.z as isize - 1);
+    }
     let bedges = match blimits {
         Some(blimits) => Edges {
             n: max(cbedges.n, blimits.n),
@@ -153,6 +319,7 @@ pub fn get_world<'a>(worldpath: &'a Path, blimits: &Option<Edges<isize>>, ylimit
 
     Ok(World {
         path: worldpath,
+        format,
         regions,
         redges,
         cedges,
@@ -161,5 +328,36 @@ pub fn get_world<'a>(worldpath: &'a Path, blimits: &Option<Edges<isize>>, ylimit
         csize: cedges.size(),
         bsize: bedges.size(),
         ylimits,
+        level,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn trim_edges() {
+        // A 5x5 square of chunks with blocks only in the middle 2x2, and in one corner.
+        let mut chunks: Vec<Pair<isize>> = (-2..3)
+            .flat_map(|z| (-2..3).map(move |x| Pair { x, z }))
+            .collect();
+        let reads = Cell::new(0);
+        let has_blocks = |c: &Pair<isize>| {
+            reads.set(reads.get() + 1);
+            (0..2).contains(&c.x) && (0..2).contains(&c.z) || (c.x, c.z) == (2, 2)
+        };
+
+        trim_empty_edges(&mut chunks, has_blocks);
+        assert_eq!(chunks.len(), 9);
+        assert!(chunks.iter().all(|c| c.x >= 0 && c.z >= 0));
+        // The middle chunks were never read.
+        assert!(reads.get() < 25);
+
+        let mut chunks = vec![Pair { x: 0, z: 0 }, Pair { x: 1, z: 0 }];
+        trim_empty_edges(&mut chunks, |_| false);
+        assert!(chunks.is_empty());
+    }
+}